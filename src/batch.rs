@@ -0,0 +1,137 @@
+// src/batch.rs
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+
+use crate::password_entry::PasswordEntry;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRecord {
+    pub service: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub expires_in_days: Option<u64>,
+}
+
+impl BatchRecord {
+    pub fn into_entry(self) -> Result<PasswordEntry> {
+        let mut entry = PasswordEntry::new(self.service, self.username, self.password);
+        entry.notes = self.notes;
+        if let Some(days) = self.expires_in_days {
+            entry.expires_at = Some(expiry_from_now(days)?);
+        }
+        Ok(entry)
+    }
+}
+
+// A record for `bulk-add`; the password is generated when left out.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkRecord {
+    pub service: String,
+    pub username: String,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+// TOML has no top-level arrays, so TOML files list records as `[[entry]]`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BulkToml {
+    #[serde(default)]
+    entry: Vec<BulkRecord>,
+}
+
+pub fn read_yaml(path: &Path) -> Result<Vec<BatchRecord>> {
+    let contents = fs::read_to_string(path)?;
+    serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+// Reads a JSON array or TOML file of records, chosen by extension. Fails if
+// any record has an empty service, so nothing is added from a bad file.
+pub fn read_bulk(path: &Path) -> Result<Vec<BulkRecord>> {
+    let contents = fs::read_to_string(path)?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+    let records = match extension.as_deref() {
+        Some("json") => serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?,
+        Some("toml") => toml::from_str::<BulkToml>(&contents)
+            .map_err(|e| anyhow::anyhow!("Failed to parse {}:\n{}", path.display(), e.to_string().trim_end()))?
+            .entry,
+        _ => anyhow::bail!("{} must be a .json or .toml file", path.display()),
+    };
+
+    let empty: Vec<String> = records.iter()
+        .enumerate()
+        .filter(|(_, record)| record.service.trim().is_empty())
+        .map(|(index, _)| (index + 1).to_string())
+        .collect();
+    if !empty.is_empty() {
+        anyhow::bail!("Empty service name in record {}; nothing was added", empty.join(", "));
+    }
+    Ok(records)
+}
+
+pub fn expiry_from_now(days: u64) -> Result<chrono::DateTime<Utc>> {
+    Duration::try_days(days as i64)
+        .and_then(|window| Utc::now().checked_add_signed(window))
+        .ok_or_else(|| anyhow::anyhow!("Expiry of {} days is out of range", days))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixture_records_become_entries() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/batch.yaml");
+        let entries: Vec<PasswordEntry> = read_yaml(&fixture).unwrap()
+            .into_iter()
+            .map(|record| record.into_entry().unwrap())
+            .collect();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].service.as_str(), entries[0].username.as_str()), ("github", "alice"));
+        assert_eq!(entries[0].password, "hunter2-GitHub!");
+        assert!(entries[0].expires_at.is_none());
+        assert_eq!(entries[1].password, "s3cret: with a colon");
+        let expires_in = entries[1].expires_at.unwrap() - Utc::now();
+        assert!(expires_in > Duration::days(29) && expires_in <= Duration::days(30));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let yaml = "- service: github\n  username: alice\n  password: hunter2\n  url: https://github.com\n";
+        assert!(serde_yaml::from_str::<Vec<BatchRecord>>(yaml).is_err());
+    }
+
+    #[test]
+    fn bulk_files_are_read_as_json_or_toml() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for name in ["bulk.json", "bulk.toml"] {
+            let records = read_bulk(&fixtures.join(name)).unwrap();
+            assert_eq!(records.len(), 2, "{}", name);
+            assert_eq!((records[0].service.as_str(), records[0].username.as_str()), ("github", "alice"));
+            assert_eq!(records[0].password.as_deref(), Some("hunter2-GitHub!"));
+            assert!(records[1].password.is_none());
+        }
+    }
+
+    #[test]
+    fn bulk_file_with_an_empty_service_is_refused() {
+        let path = std::env::temp_dir().join(format!("passrusted-bulk-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, r#"[{"service": "github", "username": "alice"}, {"service": " ", "username": "bob"}]"#).unwrap();
+        let result = read_bulk(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.err().unwrap().to_string().contains("record 2"));
+    }
+}
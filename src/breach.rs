@@ -0,0 +1,134 @@
+// src/breach.rs
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use zeroize::Zeroizing;
+
+use crate::password_entry::PasswordEntry;
+
+const HIBP_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+const PREFIX_LENGTH: usize = 5;
+
+// Looks up the hash suffixes known for a 5 character SHA-1 prefix. Only the
+// prefix ever leaves the machine (k-anonymity).
+pub trait BreachChecker {
+    fn range(&self, prefix: &str) -> Result<String>;
+}
+
+pub struct HibpClient {
+    agent: ureq::Agent,
+}
+
+impl HibpClient {
+    pub fn new() -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .user_agent(concat!("secure_password_manager/", env!("CARGO_PKG_VERSION")))
+                .build(),
+        }
+    }
+}
+
+impl BreachChecker for HibpClient {
+    fn range(&self, prefix: &str) -> Result<String> {
+        self.agent
+            .get(&format!("{}{}", HIBP_RANGE_URL, prefix))
+            .set("Add-Padding", "true")
+            .call()
+            .map_err(|e| anyhow!("Breach lookup failed: {}", e))?
+            .into_string()
+            .map_err(|e| anyhow!("Breach lookup returned an unreadable response: {}", e))
+    }
+}
+
+// Returns (service, times seen in breaches) for every compromised entry.
+pub fn find_breached(checker: &dyn BreachChecker, entries: &[PasswordEntry]) -> Result<Vec<(String, u64)>> {
+    let mut ranges: HashMap<String, String> = HashMap::new();
+    let mut breached = Vec::new();
+
+    for entry in entries {
+        let hash = sha1_hex(&entry.password);
+        let (prefix, suffix) = hash.split_at(PREFIX_LENGTH);
+
+        if !ranges.contains_key(prefix) {
+            ranges.insert(prefix.to_string(), checker.range(prefix)?);
+        }
+        let count = breach_count(&ranges[prefix], suffix);
+        if count > 0 {
+            breached.push((entry.service.clone(), count));
+        }
+    }
+
+    breached.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(breached)
+}
+
+fn sha1_hex(password: &str) -> Zeroizing<String> {
+    let hash = digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+    Zeroizing::new(hash.as_ref().iter().map(|b| format!("{:02X}", b)).collect())
+}
+
+// Response lines are "SUFFIX:COUNT"; padding lines carry a count of 0.
+fn breach_count(range: &str, suffix: &str) -> u64 {
+    range
+        .lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    // Serves canned range responses and records which prefixes were asked for.
+    struct MockChecker {
+        ranges: HashMap<&'static str, &'static str>,
+        requested: RefCell<Vec<String>>,
+    }
+
+    impl BreachChecker for MockChecker {
+        fn range(&self, prefix: &str) -> Result<String> {
+            self.requested.borrow_mut().push(prefix.to_string());
+            Ok(self.ranges.get(prefix).copied().unwrap_or("").to_string())
+        }
+    }
+
+    fn entry(service: &str, password: &str) -> PasswordEntry {
+        PasswordEntry::new(service.to_string(), "alice".to_string(), password.to_string())
+    }
+
+    #[test]
+    fn breached_passwords_are_counted_and_sorted() {
+        // SHA-1("password") is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8.
+        let checker = MockChecker {
+            ranges: HashMap::from([(
+                "5BAA6",
+                "0018A45C4D1DEF81644B54AB7F969B88D65:0\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\r\n",
+            )]),
+            requested: RefCell::new(Vec::new()),
+        };
+        let entries = [entry("mail", "password"), entry("bank", "Vq7#tLm2!xRb9&Kp"), entry("forum", "password")];
+
+        let breached = find_breached(&checker, &entries).unwrap();
+
+        assert_eq!(breached, vec![("forum".to_string(), 3861493), ("mail".to_string(), 3861493)]);
+        // One request per distinct prefix, and only the prefix is sent.
+        let requested = checker.requested.borrow();
+        assert_eq!(requested.len(), 2);
+        assert!(requested.iter().all(|prefix| prefix.len() == PREFIX_LENGTH));
+    }
+
+    #[test]
+    fn padding_lines_do_not_count_as_breaches() {
+        let range = "1E4C9B93F3F0682250B6CF8331B7EE68FD8:0\n";
+        assert_eq!(breach_count(range, "1E4C9B93F3F0682250B6CF8331B7EE68FD8"), 0);
+        assert_eq!(breach_count(range, "1e4c9b93f3f0682250b6cf8331b7ee68fd8"), 0);
+        assert_eq!(breach_count("ABC:12\n", "abc"), 12);
+    }
+}
@@ -1,45 +1,547 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::config::Config;
+use crate::export::ExportFormat;
+use crate::import::{ImportFormat, ImportSource};
+use crate::storage::{Compression, SortKey};
 
 #[derive(Parser)]
 #[command(name = "secure_password_manager")]
 #[command(about = "A secure password manager built in Rust")]
 #[command(version = "1.0")]
 pub struct Cli {
-    #[arg(short, long, default_value = "passwords.db")]
-    pub database_path: String,
+    #[arg(short, long)]
+    pub database_path: Option<String>,
+    
+    #[arg(long)]
+    pub profile: Option<String>,
+    
+    #[arg(long)]
+    pub no_expiry_warning: bool,
+    
+    #[arg(long)]
+    pub json: bool,
+    
+    #[arg(long)]
+    pub non_interactive: bool,
+    
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub lock_timeout_secs: Option<u64>,
+    
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub reauth_interval_mins: Option<u64>,
     
+    #[arg(long)]
+    pub dry_run: bool,
+    
+    #[arg(long)]
+    pub read_only: bool,
+    
+    #[arg(long)]
+    pub strict: bool,
+    
+    #[arg(long)]
+    pub no_color: bool,
+    
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+// One line typed into the interactive shell.
+#[derive(Parser)]
+#[command(name = "vault", no_binary_name = true, disable_version_flag = true)]
+pub struct ShellLine {
     #[command(subcommand)]
     pub command: Command,
 }
 
 #[derive(Subcommand)]
 pub enum Command {
-    Init,
+    Init {
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        max_prev_files: u32,
+        #[arg(long, default_value_t = 10)]
+        max_history: u32,
+        #[arg(long)]
+        kdf_memory: Option<u32>,
+        #[arg(long)]
+        kdf_iterations: Option<u32>,
+        #[arg(long)]
+        kdf_parallelism: Option<u32>,
+        #[arg(long, default_value_t = crate::crypto::DEFAULT_REKEY_AFTER, value_parser = clap::value_parser!(u64).range(1..))]
+        rekey_after: u64,
+        #[arg(long, value_enum, default_value_t = Compression::None)]
+        compression: Compression,
+        #[arg(long)]
+        recovery_key: bool,
+        #[arg(long)]
+        weak_master_ok: bool,
+    },
     
     Add {
         service: String,
         #[arg(short, long)]
         username: Option<String>,
+        #[arg(long)]
+        expires_in_days: Option<u64>,
+        #[arg(long)]
+        notes: Option<String>,
+        #[arg(long)]
+        totp: Option<String>,
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_field)]
+        fields: Vec<(String, String)>,
+        #[arg(long = "secret-field", value_name = "NAME=VALUE", value_parser = parse_field)]
+        secret_fields: Vec<(String, String)>,
+        #[arg(long = "alias")]
+        aliases: Vec<String>,
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long, conflicts_with = "password_stdin")]
+        generate_length: Option<usize>,
+        #[arg(long, conflicts_with = "password_stdin")]
+        exclude_chars: Option<String>,
+        #[arg(long, conflicts_with = "password_stdin")]
+        no_symbols: bool,
+        #[arg(long)]
+        remember_policy: bool,
+        #[arg(long, requires = "username")]
+        password_stdin: bool,
+    },
+    
+    AddBatch {
+        file: PathBuf,
+        #[arg(long)]
+        fail_fast: bool,
+    },
+    
+    BulkAdd {
+        file: PathBuf,
     },
     
     Get {
         service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long)]
+        reveal: bool,
+        #[arg(long, alias = "copy")]
+        clip: bool,
+        #[arg(long)]
+        clip_timeout: Option<u64>,
+        #[arg(long)]
+        show_totp_secret: bool,
+        #[arg(long, value_enum, conflicts_with_all = ["clip", "show_totp_secret"])]
+        field: Option<GetField>,
+        #[arg(long, value_name = "SECS", num_args = 0..=1, conflicts_with_all = ["reveal", "clip", "field"])]
+        reveal_timeout: Option<Option<u64>>,
     },
     
-    List,
+    Totp {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+    
+    Open {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long)]
+        no_copy: bool,
+        #[arg(long, conflicts_with = "no_copy")]
+        clip_timeout: Option<u64>,
+    },
+    
+    Qr {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long)]
+        totp: bool,
+    },
+    
+    List {
+        #[arg(long, value_enum, default_value_t = SortKey::Service)]
+        sort: SortKey,
+        #[arg(long)]
+        limit: Option<usize>,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        #[arg(long)]
+        show_age: bool,
+        #[arg(long, default_value_t = 90)]
+        warn_days: u64,
+        #[arg(long, default_value_t = 180)]
+        crit_days: u64,
+        #[arg(long)]
+        favorites: bool,
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    
+    Recent {
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+    
+    Search {
+        query: String,
+        #[arg(long)]
+        username: bool,
+    },
     
     Generate {
         #[arg(short, long)]
         length: Option<usize>,
         #[arg(short, long)]
         include_symbols: bool,
+        #[arg(long, overrides_with = "include_symbols")]
+        no_include_symbols: bool,
+        #[arg(long)]
+        exclude_ambiguous: bool,
+        #[arg(long, default_value_t = 1)]
+        min_lower: usize,
+        #[arg(long, default_value_t = 1)]
+        min_upper: usize,
+        #[arg(long, default_value_t = 1)]
+        min_digits: usize,
+        #[arg(long)]
+        min_symbols: Option<usize>,
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max_repeat: Option<u64>,
+        #[arg(long)]
+        exclude_chars: Option<String>,
+        #[arg(short, long, default_value_t = 1)]
+        count: usize,
+        #[arg(long, conflicts_with_all = ["length", "include_symbols", "no_include_symbols", "exclude_ambiguous", "min_lower", "min_upper", "min_digits", "min_symbols", "max_repeat", "exclude_chars"])]
+        passphrase: bool,
+        #[arg(long, conflicts_with_all = ["passphrase", "exclude_ambiguous", "min_lower", "min_upper", "min_digits", "min_symbols", "max_repeat", "exclude_chars"])]
+        pronounceable: bool,
+        #[arg(long, conflicts_with = "passphrase")]
+        force: bool,
+        #[arg(long, default_value_t = 5, requires = "passphrase")]
+        words: usize,
+        #[arg(long, default_value = "-", requires = "passphrase")]
+        separator: String,
+        #[arg(long, requires = "passphrase")]
+        capitalize: bool,
+        #[arg(long)]
+        clip: bool,
+        #[arg(long)]
+        clip_timeout: Option<u64>,
+    },
+    
+    Strength {
+        password: Option<String>,
     },
     
     Delete {
         service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+    
+    Rename {
+        old_service: String,
+        new_service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+    
+    Favorite {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long)]
+        unset: bool,
+    },
+    
+    Edit {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long, conflicts_with = "clear_notes")]
+        notes: Option<String>,
+        #[arg(long)]
+        clear_notes: bool,
     },
     
     Update {
         service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long)]
+        no_history: bool,
+        #[arg(long)]
+        notes: Option<String>,
+        #[arg(long)]
+        totp: Option<String>,
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_field)]
+        fields: Vec<(String, String)>,
+        #[arg(long = "secret-field", value_name = "NAME=VALUE", value_parser = parse_field)]
+        secret_fields: Vec<(String, String)>,
+        #[arg(long = "alias")]
+        aliases: Vec<String>,
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long, conflicts_with = "password_stdin")]
+        generate_length: Option<usize>,
+        #[arg(long, conflicts_with = "password_stdin")]
+        exclude_chars: Option<String>,
+        #[arg(long, conflicts_with = "password_stdin")]
+        no_symbols: bool,
+        #[arg(long)]
+        remember_policy: bool,
+        #[arg(long, conflicts_with = "remember_policy")]
+        forget_policy: bool,
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    
+    ChangeMaster {
+        #[arg(long)]
+        rekey: bool,
+        #[arg(long)]
+        weak_master_ok: bool,
+    },
+    
+    Recover {
+        #[arg(long)]
+        weak_master_ok: bool,
+    },
+    
+    Purge {
+        #[arg(long)]
+        yes: bool,
+    },
+    
+    Export {
+        #[arg(short, long, value_enum)]
+        format: ExportFormat,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(long)]
+        yes: bool,
+    },
+    
+    Import {
+        #[arg(short, long, value_enum, required_unless_present = "from", conflicts_with = "from")]
+        format: Option<ImportFormat>,
+        #[arg(long, value_enum)]
+        from: Option<ImportSource>,
+        input: PathBuf,
+        #[arg(long)]
+        overwrite: bool,
+        #[arg(long, conflicts_with = "overwrite")]
+        rename: bool,
+    },
+    
+    History {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+    
+    Log {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    
+    Rollback,
+    
+    Verify,
+    
+    Backup {
+        dir: Option<PathBuf>,
+    },
+    
+    Restore {
+        file: PathBuf,
+    },
+    
+    Audit {
+        #[arg(long)]
+        online: bool,
+    },
+    
+    Expired {
+        #[arg(long, default_value_t = 90)]
+        days: u64,
+    },
+    
+    Stats {
+        #[arg(long, default_value_t = 90)]
+        days: u64,
     },
+    
+    Dedupe {
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    Doctor {
+        #[arg(long)]
+        kdf_memory: Option<u32>,
+        #[arg(long)]
+        kdf_iterations: Option<u32>,
+        #[arg(long)]
+        kdf_parallelism: Option<u32>,
+    },
+    
+    Shell,
+    
+    Profiles,
+    
+    Config,
+    
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    
+    RotateMany {
+        #[arg(required = true)]
+        services: Vec<String>,
+        #[arg(short, long)]
+        length: Option<usize>,
+        #[arg(long)]
+        output_csv: Option<PathBuf>,
+    },
+}
+
+// A single value printed bare by `get --field`, for use in scripts.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum GetField {
+    Username,
+    Password,
+    Url,
+    Notes,
+}
+
+// `--field name=value`; the value may be empty, to remove the field.
+fn parse_field(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.to_string())),
+        _ => Err("expected NAME=VALUE".to_string()),
+    }
+}
+
+impl Cli {
+    // An explicit --database-path wins, then --profile (a file in the config
+    // directory), then the config file, then passwords.db in the working
+    // directory.
+    pub fn resolved_database_path(&self, config: &Config) -> anyhow::Result<String> {
+        match (&self.database_path, &self.profile, &config.database_path) {
+            (Some(path), _, _) => Ok(path.clone()),
+            (None, Some(profile), _) => Ok(crate::profiles::profile_path(profile)?.to_string_lossy().into_owned()),
+            (None, None, Some(path)) => Ok(path.clone()),
+            (None, None, None) => Ok(crate::profiles::DEFAULT_DATABASE_PATH.to_string()),
+        }
+    }
+}
+
+impl Command {
+    // Every authenticated command may write the database, if only to record
+    // an access or upgrade the format.
+    pub fn writes_database(&self) -> bool {
+        self.requires_auth()
+            || matches!(
+                self,
+                Command::Init { .. } | Command::ChangeMaster { .. } | Command::Recover { .. } | Command::Purge { .. } | Command::Rollback | Command::Restore { .. }
+            )
+    }
+    
+    // Commands that change the vault, refused by --read-only. Reading a
+    // password is allowed; its access is just not recorded.
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Command::Init { .. }
+                | Command::Add { .. }
+                | Command::AddBatch { .. }
+                | Command::BulkAdd { .. }
+                | Command::Delete { .. }
+                | Command::Rename { .. }
+                | Command::Update { .. }
+                | Command::Favorite { .. }
+                | Command::Edit { .. }
+                | Command::ChangeMaster { .. }
+                | Command::Recover { .. }
+                | Command::Purge { .. }
+                | Command::Import { .. }
+                | Command::Rollback
+                | Command::Restore { .. }
+                | Command::Dedupe { dry_run: false }
+                | Command::RotateMany { .. }
+        )
+    }
+    
+    // Commands that hand out a new master password or recovery key. After a
+    // dry run that key would match nothing on disk, so they are refused.
+    pub fn creates_keys(&self) -> bool {
+        matches!(self, Command::Init { .. } | Command::ChangeMaster { .. } | Command::Recover { .. })
+    }
+    
+    // Authenticated commands other than `log` read the entries, so they are
+    // decrypted up front; `log` only needs the key.
+    pub fn needs_entries(&self) -> bool {
+        self.requires_auth() && !matches!(self, Command::Log { .. })
+    }
+    
+    // These replace the database file as a whole, so they still run when it
+    // is too damaged to open.
+    pub fn replaces_database(&self) -> bool {
+        matches!(self, Command::Rollback | Command::Restore { .. })
+    }
+    
+    pub fn requires_auth(&self) -> bool {
+        !matches!(
+            self,
+            Command::Init { .. }
+                | Command::Generate { .. }
+                | Command::Strength { .. }
+                | Command::Profiles
+                | Command::Config
+                | Command::Completions { .. }
+                | Command::Doctor { .. }
+                | Command::ChangeMaster { .. }
+                | Command::Recover { .. }
+                | Command::Purge { .. }
+                | Command::Rollback
+                | Command::Verify
+                | Command::Backup { .. }
+                | Command::Restore { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn get_accepts_copy_for_clip() {
+        let cli = Cli::try_parse_from(["secure_password_manager", "get", "github", "--copy"]).unwrap();
+        assert!(matches!(cli.command, Command::Get { clip: true, .. }));
+    }
+    
+    #[test]
+    fn import_accepts_bitwarden_as_a_format() {
+        let cli = Cli::try_parse_from(["secure_password_manager", "import", "--format", "bitwarden", "export.json"]).unwrap();
+        assert!(matches!(cli.command, Command::Import { format: Some(ImportFormat::Bitwarden), .. }));
+    }
+    
+    #[test]
+    fn open_copies_unless_told_not_to() {
+        let cli = Cli::try_parse_from(["secure_password_manager", "open", "github"]).unwrap();
+        assert!(matches!(cli.command, Command::Open { no_copy: false, clip_timeout: None, .. }));
+        let cli = Cli::try_parse_from(["secure_password_manager", "open", "github", "--no-copy"]).unwrap();
+        assert!(matches!(cli.command, Command::Open { no_copy: true, .. }));
+        assert!(Cli::try_parse_from(["secure_password_manager", "open", "github", "--no-copy", "--clip-timeout", "5"]).is_err());
+    }
 }
@@ -0,0 +1,105 @@
+// src/clipboard.rs
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use arboard::Clipboard;
+use ring::digest::{self, Digest, SHA256};
+
+use crate::interrupt;
+
+pub const DEFAULT_CLEAR_SECONDS: u64 = 15;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// A copied value waiting to be cleared. The process stays alive for the whole
+// timeout instead of handing the job to a detached helper: on X11 the copied
+// text disappears with the process anyway, and a helper would outlive the
+// terminal the user can see it in. Dropping it clears the clipboard early,
+// so an error or panic on the way out does not leave the value behind.
+pub struct PendingClear {
+    clipboard: Clipboard,
+    // Only a hash of the value is kept, to recognise it later.
+    written: Digest,
+    previous: Option<String>,
+    deadline: Instant,
+    done: bool,
+}
+
+pub fn copy_with_timeout(value: &str, seconds: u64) -> Result<PendingClear> {
+    let mut clipboard = Clipboard::new()
+        .map_err(|e| anyhow::anyhow!("Clipboard unavailable: {}", e))?;
+    let previous = clipboard.get_text().ok();
+    clipboard
+        .set_text(value.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to copy to clipboard: {}", e))?;
+
+    Ok(PendingClear {
+        clipboard,
+        written: digest::digest(&SHA256, value.as_bytes()),
+        previous,
+        deadline: Instant::now() + Duration::from_secs(seconds),
+        done: false,
+    })
+}
+
+impl PendingClear {
+    // Blocks until the timeout, counting down on stderr when `progress` is
+    // set. Ctrl-C ends the wait early: the clipboard is cleared before the
+    // process exits.
+    pub fn wait(mut self, progress: bool) {
+        interrupt::begin_wait();
+
+        let mut shown = None;
+        while !interrupt::interrupted() {
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            if progress && shown != Some(seconds) {
+                eprint!("\rClearing clipboard in {}s ", seconds);
+                let _ = io::stderr().flush();
+                shown = Some(seconds);
+            }
+            thread::sleep(remaining.min(POLL_INTERVAL));
+        }
+        if progress {
+            eprintln!("\rClipboard cleared.      ");
+        }
+
+        interrupt::end_wait();
+        self.clear();
+        if interrupt::interrupted() {
+            std::process::exit(130);
+        }
+    }
+
+    // Puts the previous contents back, but only while the clipboard still
+    // holds what we wrote; anything copied since is left alone.
+    fn clear(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+
+        let current = match self.clipboard.get_text() {
+            Ok(text) => text,
+            Err(_) => return,
+        };
+        if digest::digest(&SHA256, current.as_bytes()).as_ref() != self.written.as_ref() {
+            return;
+        }
+        let _ = match self.previous.take() {
+            Some(text) => self.clipboard.set_text(text),
+            None => self.clipboard.clear(),
+        };
+    }
+}
+
+impl Drop for PendingClear {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,258 @@
+// src/config.rs
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::DEFAULT_CLEAR_SECONDS;
+use crate::crypto::KdfParams;
+use crate::password_generator::GeneratorOptions;
+use crate::profiles;
+use crate::strength::DEFAULT_MASTER_MIN_BITS;
+
+const CONFIG_FILE: &str = "config.toml";
+const DEFAULT_REVEAL_SECONDS: u64 = 10;
+const DEFAULT_EXPIRY_WARNING_DAYS: u64 = 14;
+
+// Defaults read from config.toml. Every field is optional: command-line flags
+// override these, and built-in defaults fill in whatever is left unset.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database_path: Option<String>,
+    pub generator: GeneratorConfig,
+    pub clipboard: ClipboardConfig,
+    pub kdf: KdfConfig,
+    pub master_password: MasterPasswordConfig,
+    pub session: SessionConfig,
+    pub display: DisplayConfig,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorConfig {
+    pub length: Option<usize>,
+    pub include_symbols: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipboardConfig {
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KdfConfig {
+    pub memory_kib: Option<u32>,
+    pub iterations: Option<u32>,
+    pub parallelism: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MasterPasswordConfig {
+    pub min_entropy_bits: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub reauth_interval_mins: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub reveal_timeout_secs: Option<u64>,
+    pub expiry_warning_days: Option<u64>,
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(profiles::profiles_dir()?.join(CONFIG_FILE))
+}
+
+// A missing file, or no config directory at all, is the same as an empty
+// config. toml's errors name the offending key and line.
+pub fn load() -> Result<Config> {
+    let path = match config_path() {
+        Ok(path) => path,
+        Err(_) => return Ok(Config::default()),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => anyhow::bail!("Could not read {}: {}", path.display(), e),
+    };
+    toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid config file {}:\n{}", path.display(), e.to_string().trim_end()))
+}
+
+impl Config {
+    pub fn generator_length(&self, flag: Option<usize>) -> usize {
+        flag.or(self.generator.length).unwrap_or(GeneratorOptions::default().length)
+    }
+
+    // The one place the symbol default is decided, for `generate` as much as
+    // for the passwords add, update and rotate-many generate.
+    pub fn include_symbols(&self, flag: Option<bool>) -> bool {
+        flag.or(self.generator.include_symbols).unwrap_or(GeneratorOptions::default().include_symbols)
+    }
+
+    // Options for passwords generated on the user's behalf by add, update
+    // and rotate-many.
+    pub fn generator_options(&self, length: Option<usize>) -> GeneratorOptions {
+        let include_symbols = self.include_symbols(None);
+        GeneratorOptions {
+            length: self.generator_length(length),
+            include_symbols,
+            min_symbols: if include_symbols { 1 } else { 0 },
+            ..GeneratorOptions::default()
+        }
+    }
+
+    pub fn clip_timeout(&self, flag: Option<u64>) -> u64 {
+        flag.or(self.clipboard.timeout_secs).unwrap_or(DEFAULT_CLEAR_SECONDS)
+    }
+
+    // How long `get --reveal-timeout` leaves the password on screen when the
+    // flag is given without a value.
+    pub fn reveal_timeout(&self, flag: Option<u64>) -> u64 {
+        flag.or(self.display.reveal_timeout_secs).unwrap_or(DEFAULT_REVEAL_SECONDS)
+    }
+
+    // Entries expiring within this many days are warned about after unlocking.
+    pub fn expiry_warning_days(&self) -> u64 {
+        self.display.expiry_warning_days.unwrap_or(DEFAULT_EXPIRY_WARNING_DAYS)
+    }
+
+    pub fn kdf_params(&self, memory_kib: Option<u32>, iterations: Option<u32>, parallelism: Option<u32>) -> KdfParams {
+        let defaults = KdfParams::default();
+        KdfParams {
+            memory_kib: memory_kib.or(self.kdf.memory_kib).unwrap_or(defaults.memory_kib),
+            iterations: iterations.or(self.kdf.iterations).unwrap_or(defaults.iterations),
+            parallelism: parallelism.or(self.kdf.parallelism).unwrap_or(defaults.parallelism),
+        }
+    }
+
+    pub fn master_min_bits(&self) -> f64 {
+        self.master_password.min_entropy_bits.unwrap_or(DEFAULT_MASTER_MIN_BITS)
+    }
+
+    // Off unless set; there is no built-in interval.
+    pub fn reauth_interval(&self, flag: Option<u64>) -> Option<Duration> {
+        flag.or(self.session.reauth_interval_mins)
+            .filter(|&minutes| minutes > 0)
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+
+    // Every setting filled in, as `generate` and `init` would use them with
+    // no flags given.
+    pub fn effective(&self, database_path: String) -> Config {
+        let kdf = self.kdf_params(None, None, None);
+        Config {
+            database_path: Some(database_path),
+            generator: GeneratorConfig {
+                length: Some(self.generator_length(None)),
+                include_symbols: Some(self.include_symbols(None)),
+            },
+            clipboard: ClipboardConfig {
+                timeout_secs: Some(self.clip_timeout(None)),
+            },
+            kdf: KdfConfig {
+                memory_kib: Some(kdf.memory_kib),
+                iterations: Some(kdf.iterations),
+                parallelism: Some(kdf.parallelism),
+            },
+            master_password: MasterPasswordConfig {
+                min_entropy_bits: Some(self.master_min_bits()),
+            },
+            session: SessionConfig {
+                reauth_interval_mins: self.session.reauth_interval_mins,
+            },
+            display: DisplayConfig {
+                reveal_timeout_secs: Some(self.reveal_timeout(None)),
+                expiry_warning_days: Some(self.expiry_warning_days()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn unset_values_fall_back_to_the_built_in_defaults() {
+        let config = Config::default();
+
+        assert_eq!(config.generator_length(None), GeneratorOptions::default().length);
+        assert_eq!(config.clip_timeout(None), DEFAULT_CLEAR_SECONDS);
+        assert_eq!(config.kdf_params(None, None, None), KdfParams::default());
+    }
+
+    #[test]
+    fn flags_override_the_file_and_the_file_overrides_defaults() {
+        let config = parse("[generator]\nlength = 24\ninclude_symbols = false\n[kdf]\niterations = 4\n");
+
+        assert_eq!(config.generator_length(None), 24);
+        assert_eq!(config.generator_length(Some(8)), 8);
+        assert!(!config.generator_options(None).include_symbols);
+        assert_eq!(config.kdf_params(None, None, None).iterations, 4);
+        assert_eq!(config.kdf_params(None, Some(2), None).iterations, 2);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<Config>("[generator]\nlenght = 24\n").is_err());
+    }
+
+    #[test]
+    fn master_password_threshold_comes_from_the_file() {
+        assert_eq!(parse("").master_min_bits(), DEFAULT_MASTER_MIN_BITS);
+        assert_eq!(parse("[master_password]\nmin_entropy_bits = 72.5\n").master_min_bits(), 72.5);
+    }
+
+    #[test]
+    fn a_zero_reauth_interval_turns_it_off() {
+        let config = parse("[session]\nreauth_interval_mins = 0\n");
+        assert_eq!(config.reauth_interval(None), None);
+        assert_eq!(config.reauth_interval(Some(5)), Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn reveal_timeout_comes_from_the_flag_then_the_file() {
+        assert_eq!(parse("").reveal_timeout(None), DEFAULT_REVEAL_SECONDS);
+        let config = parse("[display]\nreveal_timeout_secs = 3\n");
+        assert_eq!(config.reveal_timeout(None), 3);
+        assert_eq!(config.reveal_timeout(Some(20)), 20);
+    }
+
+    #[test]
+    fn expiry_warning_window_comes_from_the_file() {
+        assert_eq!(parse("").expiry_warning_days(), DEFAULT_EXPIRY_WARNING_DAYS);
+        assert_eq!(parse("[display]\nexpiry_warning_days = 30\n").expiry_warning_days(), 30);
+    }
+
+    #[test]
+    fn generated_and_effective_defaults_agree() {
+        for text in ["", "[generator]\ninclude_symbols = false\n", "[generator]\ninclude_symbols = true\n"] {
+            let config = parse(text);
+            let options = config.generator_options(None);
+            let effective = config.effective("passwords.db".to_string());
+
+            assert_eq!(Some(options.include_symbols), effective.generator.include_symbols);
+            assert_eq!(Some(options.length), effective.generator.length);
+            assert_eq!(options.min_symbols, usize::from(options.include_symbols));
+        }
+        assert!(parse("").include_symbols(None));
+        assert!(!parse("[generator]\ninclude_symbols = true\n").include_symbols(Some(false)));
+    }
+}
@@ -1,84 +1,281 @@
 // src/crypto.rs
 
+use std::sync::Once;
+
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit},
+    aead::{Aead, AeadCore, KeyInit, Payload},
     Aes256Gcm, Key, Nonce,
 };
 use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+    Algorithm, Argon2, Params, Version,
 };
 use rand::{rngs::OsRng, RngCore};
-use anyhow::Result;
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use ring::hkdf;
+
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::error::CryptoError;
+
+type Result<T> = std::result::Result<T, CryptoError>;
 
 pub const SALT_LEN: usize = 32;
 pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
 pub const KEY_LEN: usize = 32;
+const RECOVERY_KEY_BYTES: usize = 16;
+// Random 96-bit nonces stay safe for 2^32 encryptions under one key; warn
+// far earlier so a rekey happens long before that.
+pub const DEFAULT_REKEY_AFTER: u64 = 1 << 20;
+
+// HKDF info strings; each purpose gets an independent key.
+pub const SUBKEY_ENTRIES: &[u8] = b"passrusted/entries/v1";
+pub const SUBKEY_KEY_WRAP: &[u8] = b"passrusted/key-wrap/v1";
+pub const SUBKEY_JOURNAL: &[u8] = b"passrusted/journal/v1";
+#[allow(dead_code)]
+pub const SUBKEY_HMAC: &[u8] = b"passrusted/hmac/v1";
+#[allow(dead_code)]
+pub const SUBKEY_FIELD_PASSWORD: &[u8] = b"passrusted/field-password/v1";
+#[allow(dead_code)]
+pub const SUBKEY_ATTACHMENT: &[u8] = b"passrusted/attachment/v1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| CryptoError::InvalidKdfParams(e.to_string()))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+// Page-aligned and page-sized, so locking a key never pins or unlocks a page
+// shared with other data.
+#[repr(align(4096))]
+struct KeyPage([u8; KEY_LEN]);
 
-#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+// The key lives in its own locked page where the OS allows it, so it is not
+// written to swap; it is zeroized before the page is unlocked.
 pub struct MasterKey {
-    key: [u8; KEY_LEN],
+    page: Box<KeyPage>,
+    lock: Option<region::LockGuard>,
 }
 
 impl MasterKey {
-    pub fn from_password(password: &str, salt: &[u8]) -> Result<Self> {
-        let argon2 = Argon2::default();
+    pub fn from_password(password: &str, salt: &[u8], params: &KdfParams) -> Result<Self> {
+        let argon2 = params.argon2()?;
         let salt_string = SaltString::encode_b64(salt)
-            .map_err(|e| anyhow::anyhow!("Failed to encode salt: {}", e))?;
+            .map_err(|e| CryptoError::Hashing(e.to_string()))?;
         let hash = argon2
             .hash_password(password.as_bytes(), &salt_string)
-            .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+            .map_err(|e| CryptoError::Hashing(e.to_string()))?;
+
+        let hash_bytes = hash.hash.ok_or_else(|| CryptoError::Hashing("no hash output".to_string()))?;
+        Ok(Self::from_bytes(&hash_bytes.as_bytes()[..KEY_LEN]))
+    }
 
-        let hash_bytes = hash.hash.ok_or_else(|| anyhow::anyhow!("Failed to extract hash"))?;
-        let mut key = [0u8; KEY_LEN];
-        key.copy_from_slice(&hash_bytes.as_bytes()[..KEY_LEN]);
+    // A fresh random data key, independent of any password.
+    pub fn generate() -> Self {
+        let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+        OsRng.fill_bytes(bytes.as_mut());
+        Self::from_bytes(bytes.as_ref())
+    }
 
-        Ok(Self { key })
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut page = Box::new(KeyPage([0u8; KEY_LEN]));
+        page.0.copy_from_slice(bytes);
+        let lock = lock_memory(&page.0);
+        Self { page, lock }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
-        &self.key
+        &self.page.0
+    }
+}
+
+impl Drop for MasterKey {
+    fn drop(&mut self) {
+        self.page.0.zeroize();
+        self.lock.take();
     }
 }
 
-pub fn derive_key(password: &str, salt: &[u8]) -> Result<MasterKey> {
-    MasterKey::from_password(password, salt)
+impl ZeroizeOnDrop for MasterKey {}
+
+static LOCK_WARNING: Once = Once::new();
+
+fn lock_memory(bytes: &[u8]) -> Option<region::LockGuard> {
+    match region::lock(bytes.as_ptr(), bytes.len()) {
+        Ok(guard) => Some(guard),
+        Err(e) => {
+            LOCK_WARNING.call_once(|| {
+                eprintln!("Warning: could not lock key memory ({}); it may be written to swap", e);
+            });
+            None
+        }
+    }
 }
 
-pub fn hash_master_password(password: &str) -> Result<(String, Vec<u8>)> {
-    let mut salt = [0u8; SALT_LEN];
+pub fn derive_key(password: &str, salt: &[u8], params: &KdfParams) -> Result<MasterKey> {
+    MasterKey::from_password(password, salt, params)
+}
+
+// Encrypts the data key under a key-encryption key derived from the master
+// password or the recovery key, for storing in the header.
+pub fn wrap_key(key: &MasterKey, kek: &MasterKey) -> Result<Vec<u8>> {
+    let subkey = Zeroizing::new(derive_subkey(kek, SUBKEY_KEY_WRAP)?);
+    encrypt_with_key(key.as_bytes(), subkey.as_ref(), SUBKEY_KEY_WRAP)
+}
+
+pub fn unwrap_key(wrapped: &[u8], kek: &MasterKey) -> Result<MasterKey> {
+    let subkey = Zeroizing::new(derive_subkey(kek, SUBKEY_KEY_WRAP)?);
+    let bytes = Zeroizing::new(decrypt_with_key(wrapped, subkey.as_ref(), SUBKEY_KEY_WRAP)?);
+    if bytes.len() != KEY_LEN {
+        return Err(CryptoError::InvalidLength);
+    }
+    Ok(MasterKey::from_bytes(&bytes))
+}
+
+// 128 random bits as eight groups of four hex digits, easy to write down.
+pub fn generate_recovery_key() -> Zeroizing<String> {
+    let mut bytes = Zeroizing::new([0u8; RECOVERY_KEY_BYTES]);
+    OsRng.fill_bytes(bytes.as_mut());
+    let hex = Zeroizing::new(bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>());
+    Zeroizing::new(
+        hex.as_bytes()
+            .chunks(4)
+            .map(|group| std::str::from_utf8(group).unwrap())
+            .collect::<Vec<_>>()
+            .join("-"),
+    )
+}
+
+// Recovery keys are accepted in any case, with or without the dashes.
+pub fn normalize_recovery_key(key: &str) -> Zeroizing<String> {
+    Zeroizing::new(
+        key.chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_uppercase())
+            .collect(),
+    )
+}
+
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
     OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+// The verifier stored in the header. Its salt is its own and is never used
+// to derive a key: with the same salt and parameters, `derive_key` would
+// return the very hash stored here.
+pub fn hash_master_password(password: &str, params: &KdfParams) -> Result<String> {
+    let salt = generate_salt();
 
-    let argon2 = Argon2::default();
+    let argon2 = params.argon2()?;
     let salt_string = SaltString::encode_b64(&salt)
-        .map_err(|e| anyhow::anyhow!("Failed to encode salt: {}", e))?;
+        .map_err(|e| CryptoError::Hashing(e.to_string()))?;
     let hash = argon2
         .hash_password(password.as_bytes(), &salt_string)
-        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+        .map_err(|e| CryptoError::Hashing(e.to_string()))?;
 
-    Ok((hash.to_string(), salt.to_vec()))
+    Ok(hash.to_string())
 }
 
-pub fn verify_master_password(password: &str, hash_str: &str) -> Result<bool> {
+// Whether `hash_str` was made with `salt`, as verifiers were before they got
+// a salt of their own.
+pub fn hash_uses_salt(hash_str: &str, salt: &[u8]) -> bool {
+    let (Ok(parsed_hash), Ok(salt_string)) = (PasswordHash::new(hash_str), SaltString::encode_b64(salt)) else {
+        return false;
+    };
+    parsed_hash.salt.is_some_and(|hash_salt| hash_salt.as_str() == salt_string.as_str())
+}
+
+// The hash carries its own parameters; they must match the ones the key is
+// derived with, or the header has been altered.
+pub fn verify_master_password(password: &str, hash_str: &str, params: &KdfParams) -> Result<bool> {
     let parsed_hash =
-        PasswordHash::new(hash_str).map_err(|e| anyhow::anyhow!("Invalid hash format: {}", e))?;
-    let argon2 = Argon2::default();
+        PasswordHash::new(hash_str).map_err(|e| CryptoError::InvalidHash(e.to_string()))?;
+    let hash_params = Params::try_from(&parsed_hash)
+        .map_err(|e| CryptoError::InvalidHash(e.to_string()))?;
+    if (hash_params.m_cost(), hash_params.t_cost(), hash_params.p_cost())
+        != (params.memory_kib, params.iterations, params.parallelism)
+    {
+        return Err(CryptoError::KdfMismatch);
+    }
+    let argon2 = params.argon2()?;
 
     match argon2.verify_password(password.as_bytes(), &parsed_hash) {
         Ok(_) => Ok(true),
         Err(argon2::password_hash::Error::Password) => Ok(false),
-        Err(e) => Err(anyhow::anyhow!("Password verification error: {}", e)),
+        Err(e) => Err(CryptoError::InvalidHash(e.to_string())),
     }
 }
 
-pub fn encrypt_data(data: &[u8], key: &MasterKey) -> Result<Vec<u8>> {
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_bytes()));
-    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+pub fn derive_subkey(master_key: &MasterKey, purpose: &[u8]) -> Result<[u8; KEY_LEN]> {
+    let info = [purpose];
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(master_key.as_bytes());
+    let okm = prk
+        .expand(&info, hkdf::HKDF_SHA256)
+        .map_err(|_| CryptoError::SubkeyDerivation)?;
+
+    let mut subkey = [0u8; KEY_LEN];
+    okm.fill(&mut subkey)
+        .map_err(|_| CryptoError::SubkeyDerivation)?;
+    Ok(subkey)
+}
+
+// `aad` is authenticated but not encrypted; decryption fails unless the same
+// bytes are passed back.
+pub fn encrypt_data(data: &[u8], key: &MasterKey, aad: &[u8]) -> Result<Vec<u8>> {
+    let subkey = Zeroizing::new(derive_subkey(key, SUBKEY_ENTRIES)?);
+    encrypt_with_key(data, subkey.as_ref(), aad)
+}
+
+pub fn decrypt_data(encrypted_data: &[u8], key: &MasterKey, aad: &[u8]) -> Result<Vec<u8>> {
+    let subkey = Zeroizing::new(derive_subkey(key, SUBKEY_ENTRIES)?);
+    decrypt_with_key(encrypted_data, subkey.as_ref(), aad)
+}
+
+// Event log records get their own subkey, so one can never be passed off as
+// the entries or the other way round.
+pub fn encrypt_journal(data: &[u8], key: &MasterKey) -> Result<Vec<u8>> {
+    let subkey = Zeroizing::new(derive_subkey(key, SUBKEY_JOURNAL)?);
+    encrypt_with_key(data, subkey.as_ref(), SUBKEY_JOURNAL)
+}
+
+pub fn decrypt_journal(encrypted_data: &[u8], key: &MasterKey) -> Result<Vec<u8>> {
+    let subkey = Zeroizing::new(derive_subkey(key, SUBKEY_JOURNAL)?);
+    decrypt_with_key(encrypted_data, subkey.as_ref(), SUBKEY_JOURNAL)
+}
+
+// Vault formats before v3 encrypted the entries with the master key itself.
+pub fn decrypt_legacy_data(encrypted_data: &[u8], key: &MasterKey) -> Result<Vec<u8>> {
+    decrypt_with_key(encrypted_data, key.as_bytes(), &[])
+}
+
+fn encrypt_with_key(data: &[u8], key: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = generate_nonce();
 
     let ciphertext = cipher
-        .encrypt(&nonce, data)
-        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+        .encrypt(&nonce, Payload { msg: data, aad })
+        .map_err(|_| CryptoError::EncryptFailed)?;
 
     let mut result = Vec::new();
     result.extend_from_slice(&nonce);
@@ -87,19 +284,143 @@ pub fn encrypt_data(data: &[u8], key: &MasterKey) -> Result<Vec<u8>> {
     Ok(result)
 }
 
-pub fn decrypt_data(encrypted_data: &[u8], key: &MasterKey) -> Result<Vec<u8>> {
+// The only place nonces are made: a fresh random one from the OS for every
+// encryption. Callers count encryptions per key so it can be rotated.
+fn generate_nonce() -> Nonce<<Aes256Gcm as AeadCore>::NonceSize> {
+    Aes256Gcm::generate_nonce(&mut OsRng)
+}
+
+fn decrypt_with_key(encrypted_data: &[u8], key: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
     if encrypted_data.len() < NONCE_LEN {
-        anyhow::bail!("Invalid encrypted data length");
+        return Err(CryptoError::InvalidLength);
     }
 
     let (nonce_bytes, ciphertext) = encrypted_data.split_at(NONCE_LEN);
     let nonce = Nonce::from_slice(nonce_bytes);
 
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_bytes()));
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
     let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))?;
+        .decrypt(nonce, Payload { msg: ciphertext, aad })
+        .map_err(|_| CryptoError::DecryptFailed)?;
 
-    
     Ok(plaintext)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST_KDF: KdfParams = KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 };
+
+    fn test_key() -> MasterKey {
+        MasterKey::from_bytes(&[7; KEY_LEN])
+    }
+
+    #[test]
+    fn each_purpose_gets_its_own_subkey() {
+        let key = test_key();
+        let entries = derive_subkey(&key, SUBKEY_ENTRIES).unwrap();
+        assert_eq!(entries, derive_subkey(&key, SUBKEY_ENTRIES).unwrap());
+        assert_ne!(entries, derive_subkey(&key, SUBKEY_JOURNAL).unwrap());
+        assert_ne!(entries.as_slice(), key.as_bytes());
+    }
+
+    #[test]
+    fn entries_are_not_readable_with_the_master_key() {
+        let key = test_key();
+        let encrypted = encrypt_data(b"entries", &key, &[]).unwrap();
+        assert_eq!(decrypt_data(&encrypted, &key, &[]).unwrap(), b"entries");
+        assert!(matches!(decrypt_legacy_data(&encrypted, &key), Err(CryptoError::DecryptFailed)));
+    }
+
+    #[test]
+    fn master_password_hash_verifies_with_its_own_params() {
+        let hash = hash_master_password("correct horse", &FAST_KDF).unwrap();
+
+        assert!(verify_master_password("correct horse", &hash, &FAST_KDF).unwrap());
+        assert!(!verify_master_password("wrong horse", &hash, &FAST_KDF).unwrap());
+        let other = KdfParams { iterations: 2, ..FAST_KDF };
+        assert!(matches!(verify_master_password("correct horse", &hash, &other), Err(CryptoError::KdfMismatch)));
+    }
+
+    #[test]
+    fn verifier_salt_is_its_own() {
+        let salt = generate_salt();
+        let hash = hash_master_password("correct horse", &FAST_KDF).unwrap();
+        assert!(!hash_uses_salt(&hash, &salt));
+
+        let salt_string = SaltString::encode_b64(&salt).unwrap();
+        let legacy = FAST_KDF.argon2().unwrap().hash_password(b"correct horse", &salt_string).unwrap().to_string();
+        assert!(hash_uses_salt(&legacy, &salt));
+    }
+
+    #[test]
+    fn changed_aad_or_ciphertext_fails_to_decrypt() {
+        let key = test_key();
+        let mut ciphertext = encrypt_data(b"secret entries", &key, b"header").unwrap();
+
+        assert!(matches!(decrypt_data(&ciphertext, &key, b"HEADER"), Err(CryptoError::DecryptFailed)));
+        let last = ciphertext.len() - 1;
+        ciphertext[last] ^= 1;
+        assert!(matches!(decrypt_data(&ciphertext, &key, b"header"), Err(CryptoError::DecryptFailed)));
+    }
+
+    #[test]
+    fn locked_key_still_encrypts_and_decrypts() {
+        let key = test_key();
+        assert_eq!(key.as_bytes().as_ptr() as usize % 4096, 0);
+        let ciphertext = encrypt_data(b"secret entries", &key, b"header").unwrap();
+
+        assert_eq!(decrypt_data(&ciphertext, &key, b"header").unwrap(), b"secret entries");
+    }
+
+    #[test]
+    fn successive_encryptions_use_different_nonces() {
+        let key = test_key();
+        let first = encrypt_data(b"same plaintext", &key, &[]).unwrap();
+        let second = encrypt_data(b"same plaintext", &key, &[]).unwrap();
+
+        assert_ne!(first[..NONCE_LEN], second[..NONCE_LEN]);
+    }
+
+    #[test]
+    fn wrapped_key_unwraps_with_the_same_kek() {
+        let data_key = MasterKey::generate();
+        let kek = derive_key("correct horse", &generate_salt(), &FAST_KDF).unwrap();
+
+        let wrapped = wrap_key(&data_key, &kek).unwrap();
+        let unwrapped = unwrap_key(&wrapped, &kek).unwrap();
+
+        assert_eq!(unwrapped.as_bytes(), data_key.as_bytes());
+    }
+
+    #[test]
+    fn wrapped_key_does_not_unwrap_with_another_kek() {
+        let data_key = MasterKey::generate();
+        let salt = generate_salt();
+        let kek = derive_key("correct horse", &salt, &FAST_KDF).unwrap();
+        let other = derive_key("wrong horse", &salt, &FAST_KDF).unwrap();
+
+        let wrapped = wrap_key(&data_key, &kek).unwrap();
+
+        assert!(matches!(unwrap_key(&wrapped, &other), Err(CryptoError::DecryptFailed)));
+    }
+
+    #[test]
+    fn recovery_keys_normalize_regardless_of_case_and_dashes() {
+        let key = generate_recovery_key();
+        assert_eq!(key.len(), 8 * 4 + 7);
+
+        let typed = key.to_lowercase().replace('-', " ");
+        assert_eq!(normalize_recovery_key(&typed), normalize_recovery_key(&key));
+    }
+
+    #[test]
+    fn journal_and_entries_keys_are_not_interchangeable() {
+        let key = test_key();
+        let journal = encrypt_journal(b"event", &key).unwrap();
+
+        assert!(matches!(decrypt_data(&journal, &key, SUBKEY_JOURNAL), Err(CryptoError::DecryptFailed)));
+        assert_eq!(decrypt_journal(&journal, &key).unwrap(), b"event");
+    }
+}
@@ -0,0 +1,175 @@
+// src/doctor.rs
+
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use arboard::Clipboard;
+use serde::Serialize;
+
+use crate::crypto::{self, KdfParams, MasterKey};
+use crate::password_generator::{GeneratorOptions, PasswordGenerator};
+use crate::storage::PasswordStore;
+
+const GENERATOR_SAMPLES: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    // Something optional is missing, such as a clipboard on a server.
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    #[serde(rename = "millis", serialize_with = "as_millis")]
+    pub elapsed: Duration,
+}
+
+fn as_millis<S: serde::Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(elapsed.as_millis())
+}
+
+// Self-checks that need no vault or master password, for confirming an
+// install works and for bug reports. The KDF check uses `kdf`, so its timing
+// shows what unlocking would cost with those settings.
+pub fn run_checks(database_path: &str, kdf: &KdfParams) -> Vec<Check> {
+    vec![
+        timed("Argon2 hash and verify", || check_kdf(kdf)),
+        timed("AES-GCM round trip", check_encryption),
+        timed("Password generator", check_generator),
+        timed("Database directory writable", || check_writable_dir(database_path)),
+        timed("Database file", || check_database(database_path)),
+        timed("Clipboard", check_clipboard),
+    ]
+}
+
+fn timed(name: &'static str, check: impl FnOnce() -> Result<(Status, String)>) -> Check {
+    let started = Instant::now();
+    let (status, detail) = check().unwrap_or_else(|e| (Status::Fail, e.to_string()));
+    Check { name, status, detail, elapsed: started.elapsed() }
+}
+
+fn check_kdf(kdf: &KdfParams) -> Result<(Status, String)> {
+    let hash = crypto::hash_master_password("doctor self-test", kdf)?;
+    let started = Instant::now();
+    if !crypto::verify_master_password("doctor self-test", &hash, kdf)? {
+        anyhow::bail!("The hash did not verify against its own password");
+    }
+    let once = started.elapsed();
+    if crypto::verify_master_password("doctor self-test!", &hash, kdf)? {
+        anyhow::bail!("The hash verified against a different password");
+    }
+    // Unlocking runs the KDF twice: once to check the password and once to
+    // derive the key.
+    Ok((Status::Pass, format!(
+        "{} KiB, {} iterations, parallelism {}: unlocking takes about {} ms",
+        kdf.memory_kib, kdf.iterations, kdf.parallelism, (once * 2).as_millis()
+    )))
+}
+
+fn check_encryption() -> Result<(Status, String)> {
+    let key = MasterKey::generate();
+    let plaintext = b"doctor self-test";
+    let aad = b"header";
+    let encrypted = crypto::encrypt_data(plaintext, &key, aad)?;
+    if crypto::decrypt_data(&encrypted, &key, aad)? != plaintext {
+        anyhow::bail!("Decrypted data does not match what was encrypted");
+    }
+
+    let mut tampered = encrypted.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    if crypto::decrypt_data(&tampered, &key, aad).is_ok() {
+        anyhow::bail!("Tampered data was accepted");
+    }
+    if crypto::decrypt_data(&encrypted, &MasterKey::generate(), aad).is_ok() {
+        anyhow::bail!("Data decrypted under the wrong key");
+    }
+    Ok((Status::Pass, "Encrypts, decrypts and rejects tampered data".to_string()))
+}
+
+// The default options promise one of each class at the default length.
+fn check_generator() -> Result<(Status, String)> {
+    let generator = PasswordGenerator::new();
+    let options = GeneratorOptions::default();
+    for _ in 0..GENERATOR_SAMPLES {
+        let password = generator.generate_with_options(&options)?;
+        let complete = password.chars().count() == options.length
+            && password.chars().any(|c| c.is_ascii_lowercase())
+            && password.chars().any(|c| c.is_ascii_uppercase())
+            && password.chars().any(|c| c.is_ascii_digit())
+            && password.chars().any(|c| c.is_ascii_punctuation());
+        if !complete {
+            anyhow::bail!("A generated password is missing a required character class");
+        }
+    }
+    Ok((Status::Pass, format!("{} samples met the default rules", GENERATOR_SAMPLES)))
+}
+
+fn database_dir(database_path: &str) -> &Path {
+    match Path::new(database_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn check_writable_dir(database_path: &str) -> Result<(Status, String)> {
+    let dir = database_dir(database_path);
+    let probe = dir.join(format!(".passrusted-doctor-{}", std::process::id()));
+    OpenOptions::new().write(true).create_new(true).open(&probe)
+        .map_err(|e| anyhow::anyhow!("Cannot create files in {}: {}", dir.display(), e))?;
+    fs::remove_file(&probe)?;
+    Ok((Status::Pass, dir.display().to_string()))
+}
+
+// Reads the header only; the entries stay encrypted.
+fn check_database(database_path: &str) -> Result<(Status, String)> {
+    if !Path::new(database_path).exists() {
+        return Ok((Status::Warn, format!("{} does not exist yet; run init", database_path)));
+    }
+    let store = PasswordStore::new(database_path)?;
+    let state = if store.is_initialized()? { "readable" } else { "not initialized" };
+    Ok((Status::Pass, format!("{} is {}", database_path, state)))
+}
+
+fn check_clipboard() -> Result<(Status, String)> {
+    match Clipboard::new() {
+        Ok(_) => Ok((Status::Pass, "Available".to_string())),
+        Err(e) => Ok((Status::Warn, format!("Unavailable, so --clip will not work: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_pass_without_a_vault() {
+        let dir = std::env::temp_dir();
+        let database_path = dir.join(format!("passrusted-doctor-test-{}.db", std::process::id()));
+        let kdf = KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 };
+        let checks = run_checks(database_path.to_str().unwrap(), &kdf);
+
+        for check in &checks {
+            match check.name {
+                "Database file" => assert_eq!(check.status, Status::Warn),
+                // A test machine may have no display to hold a clipboard.
+                "Clipboard" => assert_ne!(check.status, Status::Fail),
+                _ => assert_eq!(check.status, Status::Pass, "{}: {}", check.name, check.detail),
+            }
+        }
+    }
+
+    #[test]
+    fn bare_file_name_lives_in_the_current_directory() {
+        assert_eq!(database_dir("passwords.db"), Path::new("."));
+        assert_eq!(database_dir("/tmp/passwords.db"), Path::new("/tmp"));
+    }
+}
@@ -0,0 +1,91 @@
+// src/error.rs
+
+use std::io;
+
+use thiserror::Error;
+
+// Failures in crypto.rs. A wrong key or tampered data both show up as
+// `DecryptFailed`; AES-GCM cannot tell them apart.
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("Invalid KDF parameters: {0}")]
+    InvalidKdfParams(String),
+    #[error("Failed to hash password: {0}")]
+    Hashing(String),
+    #[error("Invalid hash format: {0}")]
+    InvalidHash(String),
+    #[error("KDF parameters in the header do not match the master password hash")]
+    KdfMismatch,
+    #[error("Subkey derivation failed")]
+    SubkeyDerivation,
+    #[error("Encryption failed")]
+    EncryptFailed,
+    #[error("Decryption failed")]
+    DecryptFailed,
+    #[error("Invalid encrypted data length")]
+    InvalidLength,
+}
+
+// Failures in PasswordStore, so callers can tell a wrong password from a
+// damaged file or a missing entry without reading the message.
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Database not initialized")]
+    NotInitialized,
+    #[error("Invalid master password!")]
+    InvalidMasterPassword,
+    #[error("Master key not available")]
+    KeyUnavailable,
+    #[error("Vault is locked; enter the master password again")]
+    Locked,
+    #[error("Entries have not been loaded; call load_entries_cached after unlocking")]
+    NotLoaded,
+    #[error("{0}")]
+    Corrupt(String),
+    #[error("An entry stored under {key} names its service {service}; open the vault without --strict to move it")]
+    ServiceMismatch { key: String, service: String },
+    #[error("Database appears truncated or corrupt ({size} bytes)")]
+    Truncated { size: u64 },
+    #[error("Database format version {found} is newer than this program supports (version {supported}); please upgrade")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("Database header or entries have been tampered with or corrupted")]
+    DecryptFailed,
+    #[error("Database {0} is open read-only")]
+    ReadOnly(String),
+    #[error("Database {0} is in use by another process")]
+    InUse(String),
+    #[error("Could not lock {path}: {source}")]
+    LockFailed { path: String, source: io::Error },
+    #[error("This database has no recovery key")]
+    NoRecoveryKey,
+    #[error("No entry found for service: {service}{}", username.as_ref().map(|u| format!(" with username {}", u)).unwrap_or_default())]
+    NotFound { service: String, username: Option<String> },
+    #[error("An entry for {service} ({username}) already exists")]
+    AlreadyExists { service: String, username: String },
+    #[error("{name} matches several services: {}", matches.join(", "))]
+    Ambiguous { name: String, matches: Vec<String> },
+    #[error("Multiple entries for {service}; choose one with --username ({})", usernames.join(", "))]
+    MultipleAccounts { service: String, usernames: Vec<String> },
+    // A request that cannot be carried out as asked, such as an empty name.
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Bincode(#[from] bincode::Error),
+    // Errors passed through from modules that still use anyhow.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl StoreError {
+    // The file itself is damaged, as opposed to a wrong password or a
+    // missing entry; going back to an earlier copy is the way out.
+    pub fn is_damage(&self) -> bool {
+        matches!(self, StoreError::Corrupt(_) | StoreError::Truncated { .. } | StoreError::DecryptFailed)
+    }
+}
@@ -0,0 +1,232 @@
+// src/event_log.rs
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::crypto::{decrypt_journal, encrypt_journal, MasterKey};
+
+// Once the log passes this size it is moved to `.log.1`, replacing the one
+// there, so at most two files' worth of events are kept.
+const MAX_LOG_BYTES: u64 = 256 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Read,
+    Added,
+    Changed,
+    Renamed,
+    Deleted,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AuditAction::Read => "read",
+            AuditAction::Added => "added",
+            AuditAction::Changed => "changed",
+            AuditAction::Renamed => "renamed",
+            AuditAction::Deleted => "deleted",
+        };
+        f.pad(name)
+    }
+}
+
+// What happened to which service and when; never the secrets themselves.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    pub service: String,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, service: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            action,
+            service: service.to_string(),
+        }
+    }
+}
+
+// A read of one entry. Reads go to a journal beside the database instead
+// of into the entries themselves, so that recording one neither rewrites
+// the database nor replaces the save `rollback` would restore.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccessRecord {
+    pub id: Uuid,
+    pub accessed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+pub struct EventLog {
+    // Oldest first.
+    pub events: Vec<AuditEvent>,
+    // Records that did not decrypt, such as ones written under an earlier
+    // data key, or a record cut short by a crash.
+    pub unreadable: usize,
+}
+
+// The log is a sequence of records, each a little-endian u32 length followed
+// by one event encrypted on its own, so adding an event never rewrites the
+// ones before it.
+pub fn append(path: &Path, events: &[AuditEvent], key: &MasterKey) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= MAX_LOG_BYTES) {
+        fs::rename(path, rotated_path(path))?;
+    }
+    append_records(path, events, key)
+}
+
+// Reads the rotated file, if any, and then the current one.
+pub fn read(path: &Path, key: &MasterKey) -> Result<EventLog> {
+    let mut log = EventLog::default();
+    for path in [rotated_path(path), path.to_path_buf()] {
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => anyhow::bail!("Could not read event log {}: {}", path.display(), e),
+        };
+        log.unreadable += read_records(&data, key, &mut log.events);
+    }
+    Ok(log)
+}
+
+// The access journal uses the same record format, but is never rotated:
+// the next save folds it into the entries and removes it.
+pub fn append_access(path: &Path, record: &AccessRecord, key: &MasterKey) -> Result<()> {
+    append_records(path, std::slice::from_ref(record), key)
+}
+
+// Records that do not decrypt are skipped; a read that goes unrecorded
+// only leaves an older access time behind.
+pub fn read_access(path: &Path, key: &MasterKey) -> Result<Vec<AccessRecord>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => anyhow::bail!("Could not read access journal {}: {}", path.display(), e),
+    };
+    let mut records = Vec::new();
+    read_records(&data, key, &mut records);
+    Ok(records)
+}
+
+// Replaces both files with `events` encrypted under `key`, after the data
+// key changes.
+pub fn rewrite(path: &Path, events: &[AuditEvent], key: &MasterKey) -> Result<()> {
+    let temp_path = with_suffix(path, ".tmp");
+    let _ = fs::remove_file(&temp_path);
+    append(&temp_path, events, key)?;
+    if temp_path.exists() {
+        fs::rename(&temp_path, path)?;
+    } else {
+        remove_if_present(path)?;
+    }
+    remove_if_present(&rotated_path(path))
+}
+
+pub fn paths(path: &Path) -> [PathBuf; 2] {
+    [path.to_path_buf(), rotated_path(path)]
+}
+
+fn append_records<T: Serialize>(path: &Path, items: &[T], key: &MasterKey) -> Result<()> {
+    let mut records = Vec::new();
+    for item in items {
+        let record = encrypt_journal(&serde_json::to_vec(item)?, key)?;
+        records.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        records.extend_from_slice(&record);
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&records)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// Returns how many records could not be read.
+fn read_records<T: DeserializeOwned>(mut data: &[u8], key: &MasterKey, items: &mut Vec<T>) -> usize {
+    let mut unreadable = 0;
+    while !data.is_empty() {
+        let Some((length, rest)) = data.split_first_chunk::<4>() else {
+            return unreadable + 1;
+        };
+        let length = u32::from_le_bytes(*length) as usize;
+        if rest.len() < length {
+            return unreadable + 1;
+        }
+        let (record, rest) = rest.split_at(length);
+        match decrypt_journal(record, key).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok()) {
+            Some(item) => items.push(item),
+            None => unreadable += 1,
+        }
+        data = rest;
+    }
+    unreadable
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".1")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn remove_if_present(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log() -> PathBuf {
+        std::env::temp_dir().join(format!("passrusted-log-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn appended_events_read_back_in_order() {
+        let path = temp_log();
+        let key = MasterKey::generate();
+        append(&path, &[AuditEvent::new(AuditAction::Added, "github")], &key).unwrap();
+        append(&path, &[AuditEvent::new(AuditAction::Read, "github"), AuditEvent::new(AuditAction::Deleted, "gitlab")], &key).unwrap();
+
+        let log = read(&path, &key).unwrap();
+        let events: Vec<(AuditAction, &str)> = log.events.iter().map(|event| (event.action, event.service.as_str())).collect();
+        assert_eq!(events, [(AuditAction::Added, "github"), (AuditAction::Read, "github"), (AuditAction::Deleted, "gitlab")]);
+        assert_eq!(log.unreadable, 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn foreign_and_truncated_records_are_counted_as_unreadable() {
+        let path = temp_log();
+        let key = MasterKey::generate();
+        let other = MasterKey::generate();
+        append(&path, &[AuditEvent::new(AuditAction::Added, "github")], &other).unwrap();
+        append(&path, &[AuditEvent::new(AuditAction::Read, "github")], &key).unwrap();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[200, 0, 0, 0, 1]).unwrap();
+
+        let log = read(&path, &key).unwrap();
+        assert_eq!(log.events.len(), 1);
+        assert_eq!(log.events[0].action, AuditAction::Read);
+        assert_eq!(log.unreadable, 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+// src/export.rs
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use uuid::Uuid;
+
+use crate::password_entry::{EntryField, PasswordEntry};
+use crate::totp;
+
+pub const EXPORT_FIELDS: [EntryField; 10] = [
+    EntryField::Id,
+    EntryField::Service,
+    EntryField::Username,
+    EntryField::Password,
+    EntryField::CreatedAt,
+    EntryField::UpdatedAt,
+    EntryField::ExpiresAt,
+    EntryField::Notes,
+    EntryField::TotpSecret,
+    EntryField::Url,
+];
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    // KeePass 2.x XML, which KeePass and KeePassXC can import.
+    KeepassXml,
+}
+
+pub trait Exporter {
+    fn write(&self, entries: &[PasswordEntry], writer: &mut dyn Write) -> Result<()>;
+}
+
+impl ExportFormat {
+    pub fn exporter(self) -> &'static dyn Exporter {
+        match self {
+            ExportFormat::Json => &JsonExporter,
+            ExportFormat::Csv => &CsvExporter,
+            ExportFormat::KeepassXml => &KeepassXmlExporter,
+        }
+    }
+}
+
+// Plaintext exports are created readable by the owner only.
+pub fn create_private_file(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    Ok(options.open(path)?)
+}
+
+pub struct JsonExporter;
+
+impl Exporter for JsonExporter {
+    fn write(&self, entries: &[PasswordEntry], writer: &mut dyn Write) -> Result<()> {
+        let records: Vec<BTreeMap<String, String>> = entries.iter()
+            .map(|entry| entry.export_fields(&EXPORT_FIELDS).into_iter().collect())
+            .collect();
+
+        serde_json::to_writer_pretty(&mut *writer, &records)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+pub struct CsvExporter;
+
+impl Exporter for CsvExporter {
+    fn write(&self, entries: &[PasswordEntry], writer: &mut dyn Write) -> Result<()> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        csv_writer.write_record(EXPORT_FIELDS.iter().map(|field| field.name()))?;
+
+        for entry in entries {
+            let mut values = entry.export_fields(&EXPORT_FIELDS);
+            let record: Vec<String> = EXPORT_FIELDS.iter()
+                .map(|field| values.remove(field.name()).unwrap_or_default())
+                .collect();
+            csv_writer.write_record(&record)?;
+        }
+
+        csv_writer.flush()?;
+        Ok(())
+    }
+}
+
+// Writes the unencrypted XML that KeePass 2 exports and imports, with every
+// entry in one group. Old passwords become the entry's history, the TOTP
+// secret an `otp` key URI as KeePassXC stores it, and custom fields and
+// aliases extra strings. Tags carry over, and favorites are tagged `favorite`.
+pub struct KeepassXmlExporter;
+
+impl Exporter for KeepassXmlExporter {
+    fn write(&self, entries: &[PasswordEntry], writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>"#)?;
+        writeln!(writer, "<KeePassFile>")?;
+        writeln!(writer, "\t<Meta>")?;
+        write_element(writer, 2, "Generator", "PassRusted")?;
+        writeln!(writer, "\t</Meta>")?;
+        writeln!(writer, "\t<Root>")?;
+        writeln!(writer, "\t\t<Group>")?;
+        write_element(writer, 3, "UUID", &keepass_uuid(&Uuid::new_v4()))?;
+        write_element(writer, 3, "Name", "PassRusted")?;
+        for entry in entries {
+            write_keepass_entry(writer, entry)?;
+        }
+        writeln!(writer, "\t\t</Group>")?;
+        writeln!(writer, "\t</Root>")?;
+        writeln!(writer, "</KeePassFile>")?;
+        Ok(())
+    }
+}
+
+fn write_keepass_entry(writer: &mut dyn Write, entry: &PasswordEntry) -> Result<()> {
+    writeln!(writer, "\t\t\t<Entry>")?;
+    write_element(writer, 4, "UUID", &keepass_uuid(&entry.id))?;
+    let mut tags = entry.tags.clone();
+    if entry.favorite && !entry.has_tag("favorite") {
+        tags.push("favorite".to_string());
+    }
+    if !tags.is_empty() {
+        write_element(writer, 4, "Tags", &tags.join(";"))?;
+    }
+    write_times(writer, 4, entry.created_at, entry.updated_at, entry.last_accessed, entry.expires_at)?;
+
+    let mut keys = HashSet::new();
+    write_string(writer, 4, &mut keys, "Title", &entry.service, false)?;
+    write_string(writer, 4, &mut keys, "UserName", &entry.username, false)?;
+    write_string(writer, 4, &mut keys, "Password", &entry.password, true)?;
+    write_string(writer, 4, &mut keys, "URL", entry.url.as_deref().unwrap_or_default(), false)?;
+    write_string(writer, 4, &mut keys, "Notes", entry.notes.as_deref().unwrap_or_default(), false)?;
+    if let Some(secret) = &entry.totp_secret {
+        let username = Some(entry.username.as_str()).filter(|username| !username.is_empty());
+        match totp::provisioning_uri(&entry.service, username, secret) {
+            Ok(uri) => write_string(writer, 4, &mut keys, "otp", &uri, true)?,
+            Err(_) => write_string(writer, 4, &mut keys, "otp", secret, true)?,
+        }
+    }
+    if !entry.aliases.is_empty() {
+        write_string(writer, 4, &mut keys, "Aliases", &entry.aliases.join(", "), false)?;
+    }
+    for field in &entry.custom_fields {
+        write_string(writer, 4, &mut keys, &field.name, &field.value, field.secret)?;
+    }
+
+    // KeePass keeps history oldest first, each item a full copy of the entry.
+    if !entry.history.is_empty() {
+        writeln!(writer, "\t\t\t\t<History>")?;
+        for item in entry.history.iter().rev() {
+            writeln!(writer, "\t\t\t\t\t<Entry>")?;
+            write_element(writer, 6, "UUID", &keepass_uuid(&entry.id))?;
+            write_times(writer, 6, entry.created_at, item.replaced_at, None, None)?;
+            let mut keys = HashSet::new();
+            write_string(writer, 6, &mut keys, "Title", &entry.service, false)?;
+            write_string(writer, 6, &mut keys, "UserName", &entry.username, false)?;
+            write_string(writer, 6, &mut keys, "Password", &item.password, true)?;
+            writeln!(writer, "\t\t\t\t\t</Entry>")?;
+        }
+        writeln!(writer, "\t\t\t\t</History>")?;
+    }
+    writeln!(writer, "\t\t\t</Entry>")?;
+    Ok(())
+}
+
+fn write_times(
+    writer: &mut dyn Write,
+    depth: usize,
+    created: DateTime<Utc>,
+    modified: DateTime<Utc>,
+    accessed: Option<DateTime<Utc>>,
+    expires: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let indent = "\t".repeat(depth);
+    writeln!(writer, "{}<Times>", indent)?;
+    write_element(writer, depth + 1, "CreationTime", &keepass_time(created))?;
+    write_element(writer, depth + 1, "LastModificationTime", &keepass_time(modified))?;
+    write_element(writer, depth + 1, "LastAccessTime", &keepass_time(accessed.unwrap_or(modified)))?;
+    write_element(writer, depth + 1, "Expires", if expires.is_some() { "True" } else { "False" })?;
+    if let Some(expires) = expires {
+        write_element(writer, depth + 1, "ExpiryTime", &keepass_time(expires))?;
+    }
+    writeln!(writer, "{}</Times>", indent)?;
+    Ok(())
+}
+
+// KeePass refuses two strings with the same key, so a custom field named like
+// a standard one, such as "Password", is written as "Password (2)".
+fn write_string(
+    writer: &mut dyn Write,
+    depth: usize,
+    keys: &mut HashSet<String>,
+    key: &str,
+    value: &str,
+    protect: bool,
+) -> Result<()> {
+    let mut unique = key.to_string();
+    let mut n = 2;
+    while keys.contains(&unique) {
+        unique = format!("{} ({})", key, n);
+        n += 1;
+    }
+    let indent = "\t".repeat(depth);
+    let protect = if protect { r#" ProtectInMemory="True""# } else { "" };
+    writeln!(writer, "{}<String>", indent)?;
+    writeln!(writer, "{}\t<Key>{}</Key>", indent, xml_escape(&unique))?;
+    writeln!(writer, "{}\t<Value{}>{}</Value>", indent, protect, xml_escape(value))?;
+    writeln!(writer, "{}</String>", indent)?;
+    keys.insert(unique);
+    Ok(())
+}
+
+fn write_element(writer: &mut dyn Write, depth: usize, name: &str, value: &str) -> Result<()> {
+    writeln!(writer, "{}<{}>{}</{}>", "\t".repeat(depth), name, xml_escape(value), name)?;
+    Ok(())
+}
+
+// Control characters other than tab and line breaks are not allowed in XML 1.0
+// at all, so they are dropped.
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {},
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn keepass_uuid(id: &Uuid) -> String {
+    BASE64.encode(id.as_bytes())
+}
+
+fn keepass_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password_entry::{CustomField, PasswordHistoryItem};
+
+    #[test]
+    fn keepass_xml_carries_history_favorites_and_custom_fields() {
+        let mut entry = PasswordEntry::new("R&D <wiki>".to_string(), "alice".to_string(), "new-password".to_string());
+        entry.favorite = true;
+        entry.history.push(PasswordHistoryItem { password: "old-password".to_string(), replaced_at: entry.updated_at });
+        entry.custom_fields.push(CustomField { name: "Password".to_string(), value: "1234".to_string(), secret: true });
+
+        let mut output = Vec::new();
+        ExportFormat::KeepassXml.exporter().write(&[entry], &mut output).unwrap();
+        let xml = String::from_utf8(output).unwrap();
+
+        assert!(xml.contains("<Value>R&amp;D &lt;wiki&gt;</Value>"));
+        assert!(xml.contains("<Tags>favorite</Tags>"));
+        assert!(xml.contains("<Key>Password (2)</Key>"));
+        assert!(xml.contains(r#"<Value ProtectInMemory="True">old-password</Value>"#));
+        assert!(xml.find("</History>").unwrap() < xml.rfind("</Entry>").unwrap());
+    }
+}
@@ -0,0 +1,679 @@
+// src/import.rs
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use keepass::db::Value;
+use keepass::error::{DatabaseKeyError, DatabaseOpenError};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::export::ExportFormat;
+use crate::password_entry::{self, CustomField, PasswordEntry, PasswordHistoryItem};
+use crate::totp;
+
+pub struct ParsedImport {
+    pub entries: Vec<PasswordEntry>,
+    pub errors: Vec<String>,
+}
+
+type Record = BTreeMap<String, String>;
+
+// Turns the contents of an export into entries. Records that cannot be
+// mapped are reported in `errors` rather than failing the whole import.
+pub trait ImportAdapter {
+    fn parse(&self, input: &str) -> Result<ParsedImport>;
+}
+
+// Exports from other password managers, read with `import --from`.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ImportSource {
+    Chrome,
+    Firefox,
+    Bitwarden,
+    Keepass,
+}
+
+impl ImportSource {
+    pub fn adapter(self) -> &'static dyn ImportAdapter {
+        match self {
+            ImportSource::Chrome => &ChromeCsv,
+            ImportSource::Firefox => &FirefoxCsv,
+            ImportSource::Bitwarden => &BitwardenJson,
+            ImportSource::Keepass => &KeepassCsv,
+        }
+    }
+}
+
+// The first eight bytes of every KeePass 2.x database.
+const KDBX_SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+
+pub fn read_entries(path: &Path, adapter: &dyn ImportAdapter) -> Result<ParsedImport> {
+    let contents = String::from_utf8(fs::read(path)?)
+        .map_err(|_| anyhow::anyhow!("{} is not a text file", path.display()))?;
+    adapter.parse(contents.trim_start_matches('\u{feff}'))
+}
+
+pub fn is_kdbx(path: &Path) -> Result<bool> {
+    let mut signature = [0u8; 8];
+    let mut file = fs::File::open(path)?;
+    Ok(file.read_exact(&mut signature).is_ok() && signature == KDBX_SIGNATURE)
+}
+
+// Reads a KeePass 2 database (KDBX 3.1 or 4) unlocked with its password.
+// Entries from every group but the recycle bin are imported: history becomes
+// old passwords, `otp` the TOTP secret, a `favorite` tag the favorite flag,
+// other tags the entry's tags, `Aliases` the aliases, and any other string a
+// custom field.
+pub fn read_kdbx(path: &Path, password: &str) -> Result<ParsedImport> {
+    let mut file = fs::File::open(path)?;
+    let database = keepass::Database::open(&mut file, keepass::DatabaseKey::new().with_password(password))
+        .map_err(|e| match e {
+            DatabaseOpenError::Key(DatabaseKeyError::IncorrectKey) => anyhow::anyhow!("Wrong password for {}", path.display()),
+            e => anyhow::anyhow!("Cannot open {}: {}", path.display(), e),
+        })?;
+
+    let mut entries = Vec::new();
+    collect_kdbx_entries(&database.root, database.meta.recyclebin_uuid, &mut entries);
+    Ok(collect(entries.into_iter().map(entry_from_kdbx)))
+}
+
+fn collect_kdbx_entries<'a>(group: &'a keepass::db::Group, recycle_bin: Option<Uuid>, entries: &mut Vec<&'a keepass::db::Entry>) {
+    if Some(group.uuid) == recycle_bin {
+        return;
+    }
+    entries.extend(&group.entries);
+    for child in &group.groups {
+        collect_kdbx_entries(child, recycle_bin, entries);
+    }
+}
+
+const KDBX_STANDARD_FIELDS: [&str; 7] = ["Title", "UserName", "Password", "URL", "Notes", "otp", "Aliases"];
+
+fn entry_from_kdbx(kdbx: &keepass::db::Entry) -> Result<PasswordEntry> {
+    let field = |key: &str| kdbx.get(key).map(str::to_string);
+    let mut fields: Vec<CustomField> = kdbx.fields.iter()
+        .filter(|(key, _)| !KDBX_STANDARD_FIELDS.contains(&key.as_str()))
+        .filter_map(|(key, value)| match value {
+            Value::Unprotected(text) if !text.is_empty() => Some(CustomField { name: key.clone(), value: text.clone(), secret: false }),
+            Value::Protected(text) if !text.unsecure().is_empty() => Some(CustomField {
+                name: key.clone(),
+                value: String::from_utf8_lossy(text.unsecure()).into_owned(),
+                secret: true,
+            }),
+            _ => None,
+        })
+        .collect();
+    fields.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut entry = entry_from_login(Login {
+        name: field("Title").unwrap_or_default(),
+        username: field("UserName").unwrap_or_default(),
+        password: field("Password").unwrap_or_default(),
+        url: field("URL"),
+        notes: field("Notes"),
+        totp: field("otp"),
+        favorite: kdbx.tags.iter().any(|tag| tag.eq_ignore_ascii_case("favorite")),
+        created_at: kdbx.times.get_creation().map(|time| time.and_utc()),
+        updated_at: kdbx.times.get_last_modification().map(|time| time.and_utc()),
+        fields,
+    })?;
+
+    entry.id = kdbx.uuid;
+    if kdbx.times.expires {
+        entry.expires_at = kdbx.times.get_expiry().map(|time| time.and_utc());
+    }
+    let tags = kdbx.tags.iter()
+        .filter(|tag| !tag.eq_ignore_ascii_case("favorite") && !tag.trim().is_empty())
+        .cloned()
+        .collect();
+    entry.tags = password_entry::normalize_tags(tags)?;
+    if let Some(aliases) = non_empty(field("Aliases")) {
+        entry.aliases = aliases.split(',').map(str::trim).filter(|alias| !alias.is_empty()).map(str::to_string).collect();
+    }
+
+    // KeePass keeps history oldest first; ours is newest first.
+    if let Some(history) = &kdbx.history {
+        let mut current = entry.password.clone();
+        for old in history.get_entries().iter().rev() {
+            let password = old.get_password().unwrap_or_default();
+            if password.is_empty() || password == current {
+                continue;
+            }
+            entry.history.push(PasswordHistoryItem {
+                password: password.to_string(),
+                replaced_at: old.times.get_last_modification().map(|time| time.and_utc()).unwrap_or(entry.updated_at),
+            });
+            current = password.to_string();
+        }
+    }
+
+    entry.validate()?;
+    Ok(entry)
+}
+
+// Values of `import --format`: our own export formats, plus `bitwarden`
+// for the same JSON `--from bitwarden` reads.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ImportFormat {
+    Json,
+    Csv,
+    KeepassXml,
+    Bitwarden,
+}
+
+impl ImportFormat {
+    pub fn adapter(self) -> &'static dyn ImportAdapter {
+        match self {
+            ImportFormat::Json => &ExportFormat::Json,
+            ImportFormat::Csv => &ExportFormat::Csv,
+            ImportFormat::KeepassXml => &ExportFormat::KeepassXml,
+            ImportFormat::Bitwarden => ImportSource::Bitwarden.adapter(),
+        }
+    }
+}
+
+// Our own JSON and CSV exports.
+impl ImportAdapter for ExportFormat {
+    fn parse(&self, input: &str) -> Result<ParsedImport> {
+        let records = match self {
+            ExportFormat::Json => json_records(input)?,
+            ExportFormat::Csv => csv_records(input)?,
+            ExportFormat::KeepassXml => anyhow::bail!(
+                "KeePass XML can be exported but not imported; import the .kdbx database itself instead"
+            ),
+        };
+        Ok(collect(records.into_iter().map(|record| record.and_then(entry_from_record))))
+    }
+}
+
+fn collect(entries: impl Iterator<Item = Result<PasswordEntry>>) -> ParsedImport {
+    let mut parsed = ParsedImport { entries: Vec::new(), errors: Vec::new() };
+    for (index, entry) in entries.enumerate() {
+        match entry {
+            Ok(entry) => parsed.entries.push(entry),
+            Err(e) => parsed.errors.push(format!("Record {}: {}", index + 1, e)),
+        }
+    }
+    parsed
+}
+
+fn json_records(contents: &str) -> Result<Vec<Result<Record>>> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(contents)?;
+
+    Ok(values.into_iter()
+        .map(|value| {
+            let object = value.as_object()
+                .ok_or_else(|| anyhow::anyhow!("expected a JSON object"))?;
+            object.iter()
+                .map(|(key, value)| match value {
+                    serde_json::Value::String(text) => Ok((key.clone(), text.clone())),
+                    serde_json::Value::Null => Ok((key.clone(), String::new())),
+                    _ => Err(anyhow::anyhow!("field '{}' must be a string", key)),
+                })
+                .collect()
+        })
+        .collect())
+}
+
+fn csv_records(contents: &str) -> Result<Vec<Result<Record>>> {
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let headers = reader.headers()?.clone();
+
+    Ok(reader.records()
+        .map(|row| {
+            let row = row?;
+            Ok(headers.iter()
+                .zip(row.iter())
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect())
+        })
+        .collect())
+}
+
+fn entry_from_record(mut record: Record) -> Result<PasswordEntry> {
+    let service = record.remove("service")
+        .ok_or_else(|| anyhow::anyhow!("missing 'service'"))?;
+    let password = record.remove("password")
+        .ok_or_else(|| anyhow::anyhow!("missing 'password'"))?;
+    let username = record.remove("username").unwrap_or_default();
+
+    let mut entry = PasswordEntry::new(service, username, password);
+    if let Some(id) = non_empty(record.remove("id")) {
+        entry.id = Uuid::parse_str(&id)
+            .map_err(|e| anyhow::anyhow!("invalid id '{}': {}", id, e))?;
+    }
+    if let Some(created_at) = non_empty(record.remove("created_at")) {
+        entry.created_at = parse_timestamp("created_at", &created_at)?;
+    }
+    if let Some(updated_at) = non_empty(record.remove("updated_at")) {
+        entry.updated_at = parse_timestamp("updated_at", &updated_at)?;
+    }
+    if let Some(expires_at) = non_empty(record.remove("expires_at")) {
+        entry.expires_at = Some(parse_timestamp("expires_at", &expires_at)?);
+    }
+    entry.notes = non_empty(record.remove("notes"));
+    if let Some(secret) = non_empty(record.remove("totp_secret")) {
+        entry.totp_secret = Some(totp::normalize_secret(&secret)?);
+    }
+    if let Some(url) = non_empty(record.remove("url")) {
+        entry.url = Some(password_entry::validate_url(&url)?);
+    }
+
+    entry.validate()?;
+    Ok(entry)
+}
+
+// The fields other managers have in common, before they become an entry.
+#[derive(Default)]
+struct Login {
+    name: String,
+    username: String,
+    password: String,
+    url: Option<String>,
+    notes: Option<String>,
+    totp: Option<String>,
+    favorite: bool,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    fields: Vec<CustomField>,
+}
+
+// Entries without a name are named after the host of their URL. Addresses
+// we do not accept as a URL, such as android:// app links, are kept as a
+// custom field so nothing is lost.
+fn entry_from_login(login: Login) -> Result<PasswordEntry> {
+    let url = login.url.filter(|url| !url.trim().is_empty());
+    let service = match (login.name.trim(), &url) {
+        ("", Some(url)) => url::Url::parse(url.trim()).ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .ok_or_else(|| anyhow::anyhow!("no name, and no host in URL '{}'", url))?,
+        ("", None) => anyhow::bail!("no name or URL"),
+        (name, _) => name.to_string(),
+    };
+
+    let mut entry = PasswordEntry::new(service, login.username, login.password);
+    if let Some(url) = url {
+        match password_entry::validate_url(&url) {
+            Ok(url) => entry.url = Some(url),
+            Err(_) => entry.set_custom_field(CustomField { name: "url".to_string(), value: url, secret: false }),
+        }
+    }
+    entry.notes = non_empty(login.notes);
+    if let Some(totp) = non_empty(login.totp) {
+        entry.totp_secret = Some(totp::parse_secret(&totp)?);
+    }
+    entry.favorite = login.favorite;
+    if let Some(created_at) = login.created_at {
+        entry.created_at = created_at;
+    }
+    entry.updated_at = login.updated_at.unwrap_or(entry.created_at);
+    for field in login.fields {
+        entry.set_custom_field(field);
+    }
+
+    entry.validate()?;
+    Ok(entry)
+}
+
+// Removes the first of `names` present in a CSV record.
+fn take(record: &mut Record, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| record.remove(*name))
+}
+
+fn csv_logins(input: &str, to_login: impl Fn(Record) -> Result<Login>) -> Result<ParsedImport> {
+    let records = csv_records(input)?;
+    Ok(collect(records.into_iter().map(|record| record.and_then(&to_login).and_then(entry_from_login))))
+}
+
+// Chrome and other Chromium browsers: name,url,username,password,note.
+struct ChromeCsv;
+
+impl ImportAdapter for ChromeCsv {
+    fn parse(&self, input: &str) -> Result<ParsedImport> {
+        csv_logins(input, |mut record| Ok(Login {
+            name: take(&mut record, &["name"]).unwrap_or_default(),
+            url: take(&mut record, &["url"]),
+            username: take(&mut record, &["username"]).unwrap_or_default(),
+            password: take(&mut record, &["password"]).unwrap_or_default(),
+            notes: take(&mut record, &["note"]),
+            ..Login::default()
+        }))
+    }
+}
+
+// Firefox has no entry names, so the service is the host of the URL.
+// Its times are milliseconds since the epoch.
+struct FirefoxCsv;
+
+impl ImportAdapter for FirefoxCsv {
+    fn parse(&self, input: &str) -> Result<ParsedImport> {
+        csv_logins(input, |mut record| Ok(Login {
+            url: take(&mut record, &["url"]),
+            username: take(&mut record, &["username"]).unwrap_or_default(),
+            password: take(&mut record, &["password"]).unwrap_or_default(),
+            created_at: parse_millis("timeCreated", take(&mut record, &["timeCreated"]))?,
+            updated_at: parse_millis("timePasswordChanged", take(&mut record, &["timePasswordChanged"]))?,
+            ..Login::default()
+        }))
+    }
+}
+
+// KeePassXC's CSV export, or KeePass 2's with its column names.
+struct KeepassCsv;
+
+impl ImportAdapter for KeepassCsv {
+    fn parse(&self, input: &str) -> Result<ParsedImport> {
+        csv_logins(input, |mut record| {
+            let created_at = match non_empty(take(&mut record, &["Created"])) {
+                Some(created_at) => Some(parse_timestamp("Created", &created_at)?),
+                None => None,
+            };
+            let updated_at = match non_empty(take(&mut record, &["Last Modified"])) {
+                Some(updated_at) => Some(parse_timestamp("Last Modified", &updated_at)?),
+                None => None,
+            };
+            Ok(Login {
+                name: take(&mut record, &["Title", "Account"]).unwrap_or_default(),
+                username: take(&mut record, &["Username", "Login Name"]).unwrap_or_default(),
+                password: take(&mut record, &["Password"]).unwrap_or_default(),
+                url: take(&mut record, &["URL", "Web Site"]),
+                notes: take(&mut record, &["Notes", "Comments"]),
+                totp: take(&mut record, &["TOTP"]),
+                created_at,
+                updated_at,
+                ..Login::default()
+            })
+        })
+    }
+}
+
+// Bitwarden's unencrypted JSON export. Only login items carry passwords;
+// cards, identities and secure notes are reported and left out.
+struct BitwardenJson;
+
+#[derive(Deserialize)]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem {
+    #[serde(rename = "type")]
+    kind: u8,
+    #[serde(default)]
+    name: String,
+    notes: Option<String>,
+    #[serde(default)]
+    favorite: bool,
+    login: Option<BitwardenLogin>,
+    #[serde(default)]
+    fields: Vec<BitwardenField>,
+    creation_date: Option<DateTime<Utc>>,
+    revision_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Option<Vec<BitwardenUri>>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenField {
+    name: Option<String>,
+    value: Option<String>,
+    // 0 text, 1 hidden, 2 boolean
+    #[serde(rename = "type", default)]
+    kind: u8,
+}
+
+const BITWARDEN_LOGIN: u8 = 1;
+const BITWARDEN_HIDDEN_FIELD: u8 = 1;
+
+impl ImportAdapter for BitwardenJson {
+    fn parse(&self, input: &str) -> Result<ParsedImport> {
+        let export: BitwardenExport = serde_json::from_str(input)?;
+        if export.encrypted {
+            anyhow::bail!("Encrypted Bitwarden exports cannot be read; export as unencrypted JSON instead");
+        }
+        Ok(collect(export.items.into_iter().map(|item| {
+            let item: BitwardenItem = serde_json::from_value(item)?;
+            let login = match item.login {
+                Some(login) if item.kind == BITWARDEN_LOGIN => login,
+                _ => anyhow::bail!("'{}' is not a login", item.name),
+            };
+            entry_from_login(Login {
+                name: item.name,
+                username: login.username.unwrap_or_default(),
+                password: login.password.unwrap_or_default(),
+                url: login.uris.unwrap_or_default().into_iter().find_map(|uri| non_empty(uri.uri)),
+                notes: item.notes,
+                totp: login.totp,
+                favorite: item.favorite,
+                created_at: item.creation_date,
+                updated_at: item.revision_date,
+                fields: item.fields.into_iter()
+                    .filter_map(|field| Some(CustomField {
+                        name: non_empty(field.name)?,
+                        value: field.value.unwrap_or_default(),
+                        secret: field.kind == BITWARDEN_HIDDEN_FIELD,
+                    }))
+                    .collect(),
+            })
+        })))
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
+
+fn parse_timestamp(field: &str, value: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| anyhow::anyhow!("invalid {} '{}': {}", field, value, e))
+}
+
+fn parse_millis(field: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>> {
+    let Some(value) = non_empty(value) else {
+        return Ok(None);
+    };
+    value.parse::<i64>().ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("invalid {} '{}'", field, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keepass::config::{DatabaseConfig, KdfConfig};
+    use keepass::db::{Database, Entry, Group, History};
+    use keepass::DatabaseKey;
+    use std::path::PathBuf;
+
+    const PASSWORD: &str = "kdbx fixture password";
+
+    fn kdbx_entry(title: &str, password: &str) -> Entry {
+        let mut entry = Entry::new();
+        entry.fields.insert("Title".to_string(), Value::Unprotected(title.to_string()));
+        entry.fields.insert("UserName".to_string(), Value::Unprotected("alice".to_string()));
+        entry.fields.insert("Password".to_string(), Value::Protected(password.as_bytes().into()));
+        entry
+    }
+
+    // Saves `root` as a KDBX 4 database with a cheap KDF and returns its path.
+    fn write_kdbx(root: Group, recycle_bin: Option<Uuid>) -> PathBuf {
+        let config = DatabaseConfig { kdf_config: KdfConfig::Aes { rounds: 1 }, ..DatabaseConfig::default() };
+        let mut database = Database::new(config);
+        database.root = root;
+        database.meta.recyclebin_uuid = recycle_bin;
+
+        let path = std::env::temp_dir().join(format!("passrusted-test-{}.kdbx", Uuid::new_v4()));
+        let mut file = fs::File::create(&path).unwrap();
+        database.save(&mut file, DatabaseKey::new().with_password(PASSWORD)).unwrap();
+        path
+    }
+
+    fn read(contents: &str, format: ImportFormat) -> ParsedImport {
+        let path = std::env::temp_dir().join(format!("passrusted-import-{}", Uuid::new_v4()));
+        fs::write(&path, contents).unwrap();
+        let parsed = read_entries(&path, format.adapter());
+        fs::remove_file(&path).unwrap();
+        parsed.unwrap()
+    }
+
+    #[test]
+    fn our_own_exports_are_read() {
+        let json = r#"[{"service": "github", "username": "alice", "password": "hunter2"}, {"username": "bob"}]"#;
+        let parsed = read(json, ImportFormat::Json);
+        assert_eq!(parsed.entries[0].service, "github");
+        assert_eq!(parsed.errors, ["Record 2: missing 'service'"]);
+
+        let csv = "service,username,password,created_at\ngithub,alice,hunter2,2024-01-01T00:00:00Z\ngitlab,bob,hunter3,yesterday\n";
+        let parsed = read(csv, ImportFormat::Csv);
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].created_at.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert!(parsed.errors[0].starts_with("Record 2: invalid created_at 'yesterday'"));
+    }
+
+    #[test]
+    fn chrome_csv_is_read() {
+        let input = "name,url,username,password,note\nGitHub,https://github.com/login,alice,hunter2,work\n,https://gitlab.com/,bob,hunter3,\n";
+        let parsed = ImportSource::Chrome.adapter().parse(input).unwrap();
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.entries[0].service, "GitHub");
+        assert_eq!(parsed.entries[0].notes.as_deref(), Some("work"));
+        assert_eq!(parsed.entries[1].service, "gitlab.com");
+        assert_eq!(parsed.entries[1].password, "hunter3");
+    }
+
+    #[test]
+    fn firefox_csv_is_named_after_the_host() {
+        let input = "url,username,password,httpRealm,formActionOrigin,guid,timeCreated,timeLastUsed,timePasswordChanged\n\
+                     https://github.com,alice,hunter2,,https://github.com,{1},1600000000000,1600000000000,1700000000000\n\
+                     https://example.com,bob,hunter3,,,{2},yesterday,,\n";
+        let parsed = ImportSource::Firefox.adapter().parse(input).unwrap();
+
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].service, "github.com");
+        assert_eq!(parsed.entries[0].created_at.timestamp(), 1_600_000_000);
+        assert_eq!(parsed.entries[0].updated_at.timestamp(), 1_700_000_000);
+        assert_eq!(parsed.errors, ["Record 2: invalid timeCreated 'yesterday'"]);
+    }
+
+    #[test]
+    fn keepass_csv_is_read() {
+        let input = "\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\",\"TOTP\",\"Icon\",\"Last Modified\",\"Created\"\n\
+                     \"Root\",\"GitHub\",\"alice\",\"hunter2\",\"https://github.com\",\"\",\"JBSWY3DPEHPK3PXP\",\"0\",\"2024-02-01T00:00:00Z\",\"2024-01-01T00:00:00Z\"\n";
+        let parsed = ImportSource::Keepass.adapter().parse(input).unwrap();
+
+        assert!(parsed.errors.is_empty());
+        let github = &parsed.entries[0];
+        assert_eq!((github.service.as_str(), github.username.as_str()), ("GitHub", "alice"));
+        assert_eq!(github.totp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(github.created_at.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(github.updated_at.to_rfc3339(), "2024-02-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn bitwarden_logins_are_read_and_other_items_reported() {
+        let input = r#"{"encrypted": false, "items": [
+            {"type": 1, "name": "GitHub", "favorite": true, "notes": "work",
+             "login": {"username": "alice", "password": "hunter2", "uris": [{"uri": "https://github.com"}]},
+             "fields": [{"name": "PIN", "value": "1234", "type": 1}]},
+            {"type": 3, "name": "Visa"}
+        ]}"#;
+        let parsed = ImportFormat::Bitwarden.adapter().parse(input).unwrap();
+
+        assert_eq!(parsed.entries.len(), 1);
+        let github = &parsed.entries[0];
+        assert!(github.favorite);
+        assert_eq!(github.password, "hunter2");
+        assert!(github.custom_fields.iter().any(|field| field.name == "PIN" && field.secret));
+        assert_eq!(parsed.errors, ["Record 2: 'Visa' is not a login"]);
+
+        assert!(ImportSource::Bitwarden.adapter().parse(r#"{"encrypted": true, "items": []}"#).is_err());
+    }
+
+    #[test]
+    fn reads_entries_from_every_group_but_the_recycle_bin() {
+        let mut github = kdbx_entry("GitHub", "new-password");
+        github.fields.insert("URL".to_string(), Value::Unprotected("https://github.com".to_string()));
+        github.fields.insert("otp".to_string(), Value::Protected("otpauth://totp/GitHub?secret=JBSWY3DPEHPK3PXP".as_bytes().into()));
+        github.fields.insert("PIN".to_string(), Value::Protected("1234".as_bytes().into()));
+        github.tags = vec!["work".to_string(), "favorite".to_string()];
+        let mut history = History::default();
+        history.add_entry(kdbx_entry("GitHub", "old-password"));
+        github.history = Some(history);
+
+        let mut work = Group::new("Work");
+        work.entries.push(github);
+        let mut bin = Group::new("Recycle Bin");
+        bin.entries.push(kdbx_entry("Deleted", "gone"));
+        let bin_uuid = bin.uuid;
+        let mut root = Group::new("Root");
+        root.entries.push(kdbx_entry("Email", "mail-password"));
+        root.groups.push(work);
+        root.groups.push(bin);
+
+        let path = write_kdbx(root, Some(bin_uuid));
+        let parsed = read_kdbx(&path, PASSWORD);
+        let _ = fs::remove_file(&path);
+        let parsed = parsed.unwrap();
+
+        assert!(parsed.errors.is_empty());
+        let mut services: Vec<_> = parsed.entries.iter().map(|entry| entry.service.as_str()).collect();
+        services.sort();
+        assert_eq!(services, ["Email", "GitHub"]);
+
+        let github = parsed.entries.iter().find(|entry| entry.service == "GitHub").unwrap();
+        assert_eq!(github.username, "alice");
+        assert_eq!(github.password, "new-password");
+        assert_eq!(github.url.as_deref(), Some("https://github.com/"));
+        assert_eq!(github.totp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert!(github.favorite);
+        assert_eq!(github.tags, ["work"]);
+        assert_eq!(github.history.len(), 1);
+        assert_eq!(github.history[0].password, "old-password");
+        let pin = github.custom_fields.iter().find(|field| field.name == "PIN").unwrap();
+        assert_eq!(pin.value, "1234");
+        assert!(pin.secret);
+    }
+
+    #[test]
+    fn wrong_kdbx_password_is_reported() {
+        let mut root = Group::new("Root");
+        root.entries.push(kdbx_entry("Email", "mail-password"));
+        let path = write_kdbx(root, None);
+        let result = read_kdbx(&path, "not the password");
+        let is_kdbx = is_kdbx(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert!(is_kdbx);
+        assert!(result.err().unwrap().to_string().starts_with("Wrong password"));
+    }
+
+    #[test]
+    fn keepass_xml_is_refused_as_an_import_format() {
+        assert!(ImportFormat::KeepassXml.adapter().parse("<KeePassFile/>").is_err());
+    }
+}
@@ -0,0 +1,33 @@
+// src/interrupt.rs
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+static HANDLER: Once = Once::new();
+static WAITING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// One Ctrl-C handler for the whole process. While something is waiting with
+// `begin_wait`, Ctrl-C only sets a flag for it to notice and finish cleanly;
+// a second Ctrl-C, or one at any other time, ends the process as it would
+// without a handler.
+pub fn begin_wait() {
+    HANDLER.call_once(|| {
+        let _ = ctrlc::set_handler(|| {
+            if WAITING.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            std::process::exit(130);
+        });
+    });
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    WAITING.store(true, Ordering::SeqCst);
+}
+
+pub fn end_wait() {
+    WAITING.store(false, Ordering::SeqCst);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,13 @@
+// src/lib.rs
+
+// The vault itself, usable without the command-line front end: open a
+// `storage::PasswordStore`, unlock it with the master password, and read or
+// change its entries.
+
+pub mod crypto;
+pub mod error;
+pub mod event_log;
+pub mod password_entry;
+pub mod password_generator;
+pub mod storage;
+pub mod strength;
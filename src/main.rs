@@ -1,47 +1,355 @@
 // src/main.rs
 
-mod crypto;
-mod storage;
-mod password_entry;
-mod password_generator;
+mod batch;
+mod breach;
+mod clipboard;
+mod config;
+mod doctor;
+mod export;
+mod import;
+mod interrupt;
+mod progress;
+mod totp;
+mod profiles;
+mod qr;
 mod cli;
 
+// The vault lives in the library; these modules are the command-line front
+// end to it.
+use secure_password_manager::{crypto, password_entry, password_generator, storage, strength};
+use secure_password_manager::error::StoreError;
+
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use colored::*;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+use crate::cli::{Cli, Command, GetField, ShellLine};
+use crate::config::Config;
+use crate::crypto::KdfParams;
+use crate::export::ExportFormat;
+use crate::password_entry::{CustomField, PasswordEntry};
+use crate::storage::{Compression, ConflictPolicy, ImportReport, PasswordStore, SortKey};
+use crate::password_generator::{GeneratorOptions, PasswordGenerator};
+
+const MASTER_PASSWORD_ENV: &str = "PASSRUSTED_MASTER";
+// Same length whatever the password, so the mask gives nothing away.
+const PASSWORD_MASK: &str = "••••••••";
+
+static ENV_PASSWORD_WARNING: Once = Once::new();
 
-use crate::cli::{Cli, Command};
-use crate::storage::PasswordStore;
-use crate::password_generator::PasswordGenerator;
+#[derive(Clone, Copy)]
+struct RunOptions<'a> {
+    json: bool,
+    non_interactive: bool,
+    dry_run: bool,
+    read_only: bool,
+    config: &'a Config,
+}
+
+impl RunOptions<'_> {
+    // Spinners go to stderr, and only when someone is watching it.
+    fn show_progress(&self) -> bool {
+        !self.json && !self.non_interactive && io::stderr().is_terminal()
+    }
+}
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let json = cli.json;
+    if !use_color(cli.no_color, json, io::stdout().is_terminal()) {
+        colored::control::set_override(false);
+    }
 
     match run_cli(cli) {
         Ok(_) => Ok(()),
         Err(e) => {
-            eprintln!("{} {}", "Error:".red().bold(), e);
-            std::process::exit(1);
+            if json {
+                eprintln!("{}", serde_json::json!({ "error": e.to_string() }));
+            } else {
+                eprintln!("{} {}", "Error:".red().bold(), e);
+                if e.downcast_ref::<StoreError>().is_some_and(StoreError::is_damage) {
+                    eprintln!("{}", "The database file looks damaged. 'restore <file>' replaces it with a backup, keeping the damaged file as .prev; 'rollback' goes back to the previous save.".yellow());
+                }
+            }
+            std::process::exit(exit_code(&e));
         }
     }
 }
 
+// Colors are for people at a terminal. Piped or redirected output, and
+// JSON, never carry escape codes. NO_COLOR is honoured by `colored` itself.
+fn use_color(no_color: bool, json: bool, stdout_is_terminal: bool) -> bool {
+    !no_color && !json && stdout_is_terminal
+}
+
+// 2 when the named entry does not exist, 1 for any other failure.
+fn exit_code(error: &anyhow::Error) -> i32 {
+    match error.downcast_ref::<StoreError>() {
+        Some(StoreError::NotFound { .. }) => 2,
+        _ => 1,
+    }
+}
+
 fn run_cli(cli: Cli) -> Result<()> {
-    let mut store = PasswordStore::new(&cli.database_path)?;
+    // Completions must work without a database.
+    if let Command::Completions { shell } = cli.command {
+        return print_completions(shell);
+    }
+
+    // Refused before any password is asked for.
+    check_writable(&cli.command, cli.read_only)?;
+    check_dry_run(&cli.command, cli.dry_run)?;
+
+    let config = config::load()?;
+    let database_path = cli.resolved_database_path(&config)?;
+    // Runs without opening the database, which may be what is broken.
+    if let Command::Doctor { kdf_memory, kdf_iterations, kdf_parallelism } = cli.command {
+        return run_doctor(&database_path, config.kdf_params(kdf_memory, kdf_iterations, kdf_parallelism), cli.json);
+    }
+    let mut store = match PasswordStore::new(&database_path) {
+        Err(e) if e.is_damage() && cli.command.replaces_database() => PasswordStore::for_repair(&database_path),
+        result => result?,
+    };
+    store.set_lock_timeout(cli.lock_timeout_secs.map(Duration::from_secs));
+    store.set_reauth_interval(config.reauth_interval(cli.reauth_interval_mins));
+    store.set_dry_run(cli.dry_run);
+    store.set_read_only(cli.read_only);
+    store.set_strict(cli.strict);
+    if cli.command.writes_database() && !cli.read_only {
+        store.acquire_file_lock()?;
+    }
+
+    let options = RunOptions {
+        json: cli.json,
+        non_interactive: cli.non_interactive,
+        dry_run: cli.dry_run,
+        read_only: cli.read_only,
+        config: &config,
+    };
+
+    if cli.command.requires_auth() {
+        authenticate_user(&mut store, options)?;
+        load_entries_for(&mut store, &cli.command)?;
+        if !cli.json {
+            warn_rekey_due(&store);
+        }
+        if !cli.no_expiry_warning && !cli.json && cli.command.needs_entries() {
+            warn_expiring_entries(&store, config.expiry_warning_days());
+        }
+    }
 
     match cli.command {
-        Command::Init => initialize_database(&mut store),
-        Command::Add { service, username } => add_password(&mut store, &service, username.as_deref()),
-        Command::Get { service } => get_password(&mut store, &service),
-        Command::List => list_passwords(&mut store),
-        Command::Generate { length, include_symbols } => generate_password(length, include_symbols),
-        Command::Delete { service } => delete_password(&mut store, &service),
-        Command::Update { service } => update_password(&mut store, &service),
+        Command::Shell => run_shell(&mut store, options),
+        command => run_command(&mut store, command, options),
+    }
+}
+
+fn check_writable(command: &Command, read_only: bool) -> Result<()> {
+    if read_only && command.is_mutating() {
+        anyhow::bail!("This command changes the database, so it cannot run with --read-only");
+    }
+    Ok(())
+}
+
+fn check_dry_run(command: &Command, dry_run: bool) -> Result<()> {
+    if dry_run && command.creates_keys() {
+        anyhow::bail!("This command sets a new master password or recovery key, so it cannot run with --dry-run");
+    }
+    Ok(())
+}
+
+fn run_command(store: &mut PasswordStore, command: Command, options: RunOptions) -> Result<()> {
+    let json = options.json;
+
+    match command {
+        Command::Init { max_prev_files, max_history, kdf_memory, kdf_iterations, kdf_parallelism, rekey_after, compression, recovery_key, weak_master_ok } => {
+            let kdf = options.config.kdf_params(kdf_memory, kdf_iterations, kdf_parallelism);
+            let policy = MasterPolicy { min_bits: options.config.master_min_bits(), allow_weak: weak_master_ok };
+            initialize_database(store, InitSettings { max_prev_files, max_history, kdf, rekey_after, compression, recovery_key }, policy, options.non_interactive)
+        },
+        Command::Add { service, username, expires_in_days, notes, totp, url, fields, secret_fields, aliases, tags, generate_length, exclude_chars, no_symbols, remember_policy, password_stdin } => {
+            let password = password_stdin.then(read_password_stdin).transpose()?;
+            let custom_fields = custom_fields(fields, secret_fields);
+            let generator_options = entry_generator_options(options.config, generate_length, exclude_chars, no_symbols);
+            let policy = remember_policy.then(|| Some(generator_options.clone()));
+            add_password(store, &service, username.as_deref(), expires_in_days, OptionalFields { notes, totp, url, custom_fields, aliases, tags, policy }, password, &generator_options)
+        },
+        Command::AddBatch { file, fail_fast } => add_batch(store, &file, fail_fast),
+        Command::BulkAdd { file } => bulk_add(store, &file, &options.config.generator_options(None)),
+        Command::Get { service, username, reveal, clip, clip_timeout, show_totp_secret, field, reveal_timeout } => {
+            let service = select_service(store, &service, options)?;
+            let username = select_username(store, &service, username, options)?;
+            // Hiding the password again needs a terminal to redraw; anywhere
+            // else it is just revealed.
+            let reveal_for = reveal_timeout.map(|seconds| options.config.reveal_timeout(seconds));
+            let (reveal, reveal_for) = match reveal_for {
+                Some(seconds) if !json && io::stdout().is_terminal() => (reveal, Some(seconds)),
+                Some(_) => (true, None),
+                None => (reveal, None),
+            };
+            let display = GetDisplay { reveal, clip, clip_timeout: options.config.clip_timeout(clip_timeout), show_totp_secret, field, reveal_for };
+            get_password(store, &service, username.as_deref(), display, json)
+        },
+        Command::Totp { service, username } => {
+            let username = select_username(store, &service, username, options)?;
+            show_totp_code(store, &service, username.as_deref())
+        },
+        Command::Open { service, username, no_copy, clip_timeout } => {
+            let username = select_username(store, &service, username, options)?;
+            let clip = (!no_copy).then(|| options.config.clip_timeout(clip_timeout));
+            open_url(store, &service, username.as_deref(), clip)
+        },
+        Command::Qr { service, username, totp } => {
+            let username = select_username(store, &service, username, options)?;
+            show_qr_code(store, &service, username.as_deref(), totp)
+        },
+        Command::List { sort, limit, offset, show_age, warn_days, crit_days, favorites, tag } => {
+            if crit_days <= warn_days {
+                anyhow::bail!("--crit-days must be greater than --warn-days");
+            }
+            list_passwords(store, sort, limit, offset, ListFilter { favorites, tag }, AgeDisplay { show_age, warn_days, crit_days }, json)
+        },
+        Command::Recent { count } => recent_passwords(store, count, json),
+        Command::Search { query, username } => search_passwords(store, &query, username, json),
+        Command::Generate { passphrase: true, words, separator, capitalize, count, clip, clip_timeout, .. } => {
+            generate_passphrase(words, &separator, capitalize, count, clip.then(|| options.config.clip_timeout(clip_timeout)))
+        },
+        Command::Generate { length, include_symbols, no_include_symbols, exclude_ambiguous, min_lower, min_upper, min_digits, min_symbols, max_repeat, exclude_chars, count, pronounceable, force, clip, clip_timeout, .. } => {
+            let clip = clip.then(|| options.config.clip_timeout(clip_timeout));
+            let include_symbols = options.config.include_symbols(match (include_symbols, no_include_symbols) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            });
+            let length = options.config.generator_length(length);
+            if length < strength::MIN_LENGTH && !force {
+                anyhow::bail!(
+                    "A length of {} is below the recommended minimum of {}; pass --force to generate it anyway",
+                    length,
+                    strength::MIN_LENGTH
+                );
+            }
+            if pronounceable {
+                generate_pronounceable(length, include_symbols, count, clip)
+            } else {
+                generate_password(GeneratorOptions {
+                    length,
+                    include_symbols,
+                    exclude_ambiguous,
+                    min_lower,
+                    min_upper,
+                    min_digits,
+                    min_symbols: min_symbols.unwrap_or(if include_symbols { 1 } else { 0 }),
+                    max_repeat: max_repeat.map(|max| max as usize),
+                    exclude_chars: exclude_chars.unwrap_or_default(),
+                }, count, clip)
+            }
+        },
+        Command::Strength { password } => check_strength(password),
+        Command::Delete { service, username } => {
+            let username = select_username(store, &service, username, options)?;
+            delete_password(store, &service, username.as_deref(), options.dry_run)
+        },
+        Command::Rename { old_service, new_service, username } => {
+            let username = select_username(store, &old_service, username, options)?;
+            rename_entry(store, &old_service, &new_service, username.as_deref(), options.dry_run)
+        },
+        Command::Update { service, username, no_history, notes, totp, url, fields, secret_fields, aliases, tags, generate_length, exclude_chars, no_symbols, remember_policy, forget_policy, password_stdin } => {
+            // stdin carries the password, so it cannot also answer prompts.
+            let select_options = RunOptions { non_interactive: options.non_interactive || password_stdin, ..options };
+            let username = select_username(store, &service, username, select_options)?;
+            // Checked before the new password is asked for or read.
+            let entry = store.get_entry(&service, username.as_deref())?
+                .ok_or_else(|| entry_not_found(&service, username.as_deref()))?;
+            let password = password_stdin.then(read_password_stdin).transpose()?;
+            let custom_fields = custom_fields(fields, secret_fields);
+            // Generator flags given this time override the remembered policy.
+            let flags_given = generate_length.is_some() || exclude_chars.is_some() || no_symbols;
+            let generator_options = match entry.gen_policy.clone() {
+                Some(policy) if !flags_given && !forget_policy => {
+                    if password.is_none() {
+                        println!("Generated passwords follow the policy remembered for {}: {}", entry.service.cyan(), describe_policy(&policy));
+                    }
+                    policy
+                },
+                _ => entry_generator_options(options.config, generate_length, exclude_chars, no_symbols),
+            };
+            let policy = match (remember_policy, forget_policy) {
+                (true, _) => Some(Some(generator_options.clone())),
+                (_, true) => Some(None),
+                _ => None,
+            };
+            update_password(store, &service, username.as_deref(), no_history, OptionalFields { notes, totp, url, custom_fields, aliases, tags, policy }, password, &generator_options)
+        },
+        Command::ChangeMaster { rekey, weak_master_ok } => {
+            change_master_password(store, rekey, MasterPolicy { min_bits: options.config.master_min_bits(), allow_weak: weak_master_ok })
+        },
+        Command::Recover { weak_master_ok } => {
+            recover_database(store, MasterPolicy { min_bits: options.config.master_min_bits(), allow_weak: weak_master_ok })
+        },
+        Command::Purge { yes } => purge_database(store, yes, options),
+        Command::Export { format, output, yes } => export_passwords(store, format, output.as_deref(), yes),
+        Command::Import { format, from, input, overwrite, rename } => {
+            let adapter = match (format, from) {
+                (_, Some(source)) => source.adapter(),
+                (Some(format), None) => format.adapter(),
+                (None, None) => anyhow::bail!("Pass --format or --from"),
+            };
+            let on_conflict = match (overwrite, rename) {
+                (true, _) => ConflictPolicy::Overwrite,
+                (_, true) => ConflictPolicy::Rename,
+                _ => ConflictPolicy::Skip,
+            };
+            import_passwords(store, adapter, &input, on_conflict, options.dry_run)
+        },
+        Command::Favorite { service, username, unset } => {
+            let username = select_username(store, &service, username, options)?;
+            set_favorite(store, &service, username.as_deref(), !unset)
+        },
+        Command::Edit { service, username, notes, clear_notes } => {
+            let username = select_username(store, &service, username, options)?;
+            edit_notes(store, &service, username.as_deref(), notes, clear_notes)
+        },
+        Command::History { service, username } => {
+            let username = select_username(store, &service, username, options)?;
+            show_history(store, &service, username.as_deref())
+        },
+        Command::Log { limit } => show_event_log(store, limit, json),
+        Command::Rollback => rollback_database(store, options),
+        Command::Verify => verify_database(store, options),
+        Command::Backup { dir } => backup_database(store, dir.as_deref()),
+        Command::Restore { file } => restore_database(store, &file, options.dry_run),
+        Command::Audit { online } => audit_passwords(store, online),
+        Command::Expired { days } => show_expired(store, days, json),
+        Command::Stats { days } => show_stats(store, days, json),
+        Command::Dedupe { dry_run } => dedupe_entries(store, dry_run || options.dry_run),
+        Command::Doctor { kdf_memory, kdf_iterations, kdf_parallelism } => {
+            run_doctor(store.path(), options.config.kdf_params(kdf_memory, kdf_iterations, kdf_parallelism), json)
+        },
+        Command::Shell => anyhow::bail!("Already in the shell"),
+        Command::Profiles => list_profiles(),
+        Command::Config => print_config(options.config, store.path(), json),
+        Command::Completions { shell } => print_completions(shell),
+        Command::RotateMany { services, length, output_csv } => rotate_many(store, &services, options.config.generator_options(length), output_csv.as_deref(), options.dry_run),
     }
 }
 
-fn initialize_database(store: &mut PasswordStore) -> Result<()> {
+// Settings fixed when a vault is created.
+struct InitSettings {
+    max_prev_files: u32,
+    max_history: u32,
+    kdf: KdfParams,
+    rekey_after: u64,
+    compression: Compression,
+    recovery_key: bool,
+}
+
+fn initialize_database(store: &mut PasswordStore, settings: InitSettings, policy: MasterPolicy, non_interactive: bool) -> Result<()> {
     if store.is_initialized()? {
         println!("{}", "Database already initialized!".yellow());
         return Ok(());
@@ -49,147 +357,403 @@ fn initialize_database(store: &mut PasswordStore) -> Result<()> {
 
     println!("{}", "Initializing secure password database...".cyan().bold());
 
-    let master_password = rpassword::prompt_password("Enter master password: ")?;
-    let confirm_password = rpassword::prompt_password("Confirm master password: ")?;
+    let master_password = match env_master_password(non_interactive) {
+        Some(password) => {
+            check_master_password(&password, policy)?;
+            password
+        },
+        None => prompt_new_master_password("Enter master password: ", "Confirm master password: ", policy)?,
+    };
 
-    if master_password != confirm_password {
-        anyhow::bail!("Passwords do not match!");
+    store.initialize(&master_password, settings.max_prev_files, settings.max_history, settings.kdf, settings.rekey_after, settings.compression)?;
+    println!("{}", "Database initialized successfully!".green().bold());
+    if settings.recovery_key {
+        show_recovery_key(&store.create_recovery_key()?);
     }
+    Ok(())
+}
 
-    if master_password.len() < 8 {
-        anyhow::bail!("Master password must be at least 8 characters long!");
+fn show_recovery_key(recovery_key: &str) {
+    println!();
+    println!("{}", "Recovery key (shown only once):".cyan().bold());
+    println!("  {}", recovery_key.green().bold());
+    println!("{}", "Write it down and keep it somewhere safe. It unlocks the vault with 'recover' if you forget the master password.".yellow());
+}
+
+fn change_master_password(store: &mut PasswordStore, rekey: bool, policy: MasterPolicy) -> Result<()> {
+    if !store.is_initialized()? {
+        anyhow::bail!("Database not initialized. Run 'init' command first.");
     }
 
-    store.initialize(&master_password)?;
-    println!("{}", "Database initialized successfully!".green().bold());
+    let current_password = rpassword::prompt_password("Current master password: ")?;
+    if !store.verify_master_password(&current_password)? {
+        anyhow::bail!("Invalid master password!");
+    }
+
+    let new_password = prompt_new_master_password("New master password: ", "Confirm new master password: ", policy)?;
+
+    if rekey {
+        println!("{}", "Re-encrypting database...".cyan());
+    }
+    let recovery_key = store.change_master_password(&current_password, &new_password, rekey)?;
+    println!("{}", "Master password changed successfully!".green().bold());
+    println!("{}", "Previous saves (.prev files) still open with the old master password.".yellow());
+    if let Some(recovery_key) = recovery_key {
+        println!("{}", "The vault was re-encrypted, so the old recovery key no longer works.".yellow());
+        show_recovery_key(&recovery_key);
+    }
     Ok(())
 }
 
-fn add_password(store: &mut PasswordStore, service: &str, username: Option<&str>) -> Result<()> {
-    authenticate_user(store)?;
+// A recovery key is good for one unlock: setting the new master password
+// re-encrypts the vault and replaces it.
+fn recover_database(store: &mut PasswordStore, policy: MasterPolicy) -> Result<()> {
+    if !store.is_initialized()? {
+        anyhow::bail!("Database not initialized. Run 'init' command first.");
+    }
+    if !store.has_recovery_key() {
+        anyhow::bail!("This database has no recovery key; it can only be unlocked with the master password");
+    }
 
-    let username = match username {
-        Some(u) => u.to_string(),
-        None => {
-            print!("Username: ");
-            io::stdout().flush()?;
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            input.trim().to_string()
-        }
-    };
+    let recovery_key = Zeroizing::new(rpassword::prompt_password("Recovery key: ")?);
+    if !store.unlock_with_recovery_key(&recovery_key)? {
+        anyhow::bail!("Invalid recovery key!");
+    }
 
-    println!("Choose password option:");
-    println!("1. Generate random password");
-    println!("2. Enter custom password");
+    println!("{}", "Recovery key accepted. Choose a new master password.".green());
+    let new_password = prompt_new_master_password("New master password: ", "Confirm new master password: ", policy)?;
 
-    print!("Choice (1/2): ");
-    io::stdout().flush()?;
-    let mut choice = String::new();
-    io::stdin().read_line(&mut choice)?;
+    println!("{}", "Re-encrypting database...".cyan());
+    let new_recovery_key = store.reset_master_password(&new_password)?;
+    println!("{}", "Master password reset successfully!".green().bold());
+    if let Some(new_recovery_key) = new_recovery_key {
+        println!("{}", "The recovery key you entered no longer works.".yellow());
+        show_recovery_key(&new_recovery_key);
+    }
+    Ok(())
+}
 
-    let password = match choice.trim() {
-        "1" => {
-            let generator = PasswordGenerator::new();
-            generator.generate(16, true)?
-        },
-        "2" => {
-            rpassword::prompt_password("Enter password: ")?
-        },
-        _ => anyhow::bail!("Invalid choice!")
+// Authenticates itself, so that purging a database that is already gone is
+// not an error.
+fn purge_database(store: &mut PasswordStore, yes: bool, options: RunOptions) -> Result<()> {
+    if !Path::new(store.path()).exists() {
+        println!("{}", format!("No database at {}; nothing to purge.", store.path()).yellow());
+        return Ok(());
+    }
+
+    let master_password = match env_master_password(options.non_interactive) {
+        Some(password) => password,
+        None => rpassword::prompt_password("Master password: ")?,
     };
+    if !store.verify_master_password(&master_password)? {
+        anyhow::bail!("Invalid master password!");
+    }
 
-    store.add_entry(service, &username, &password)?;
-    println!("{} Password added for {} ({})", "✓".green().bold(), service.cyan(), username);
+    if options.dry_run {
+        for path in store.files() {
+            println!("Would remove {}", path);
+        }
+        return Ok(());
+    }
+    if !yes {
+        println!("{}", format!("This permanently deletes {}, its previous saves and its event log. It cannot be undone.", store.path()).red().bold());
+        print!("Type DELETE ALL to confirm: ");
+        io::stdout().flush()?;
+        let mut confirmation = String::new();
+        io::stdin().read_line(&mut confirmation)?;
+
+        if confirmation.trim_end_matches(['\r', '\n']) != "DELETE ALL" {
+            println!("Purge cancelled.");
+            return Ok(());
+        }
+    }
+
+    for path in store.purge()? {
+        println!("Removed {}", path);
+    }
+    println!("{}", "Database purged.".green().bold());
     Ok(())
 }
 
-fn get_password(store: &mut PasswordStore, service: &str) -> Result<()> {
-    authenticate_user(store)?;
+// Runs without the usual authentication, which would fail on a corrupted
+// vault before anything could be reported, and would migrate an old one.
+fn verify_database(store: &mut PasswordStore, options: RunOptions) -> Result<()> {
+    if !store.is_initialized()? {
+        anyhow::bail!("Database not initialized. Run 'init' command first.");
+    }
 
-    match store.get_entry(service)? {
-        Some(entry) => {
-            println!("{}", "Password Entry".cyan().bold());
-            println!("Service: {}", entry.service.yellow());
-            println!("Username: {}", entry.username.yellow());
-            println!("Password: {}", entry.password.green());
-            println!("Created: {}", entry.created_at.format("%Y-%m-%d %H:%M:%S").to_string().blue());
-            println!("Updated: {}", entry.updated_at.format("%Y-%m-%d %H:%M:%S").to_string().blue());
-        },
-        None => {
-            println!("{}", format!("No entry found for service: {}", service).red());
+    let master_password = match env_master_password(options.non_interactive) {
+        Some(password) => password,
+        None => rpassword::prompt_password("Master password: ")?,
+    };
+    let report = store.verify(&master_password)?;
+
+    if options.json {
+        print_json(&serde_json::json!({
+            "ok": report.passed(),
+            "version": report.version,
+            "entries": report.entries,
+            "problems": report.problems,
+        }))?;
+    } else if report.passed() {
+        println!("{} Vault OK: {} entries verified (format version {})", "✓".green().bold(), report.entries, report.version);
+    } else {
+        for problem in &report.problems {
+            println!("{} {}", "✗".red().bold(), problem);
         }
     }
+
+    if !report.passed() {
+        anyhow::bail!("Vault verification failed with {} problem(s)", report.problems.len());
+    }
     Ok(())
 }
 
-// FIX: Takes a mutable store to allow authentication
-fn list_passwords(store: &mut PasswordStore) -> Result<()> {
-    authenticate_user(store)?;
+fn import_passwords(store: &mut PasswordStore, adapter: &dyn import::ImportAdapter, input: &Path, on_conflict: ConflictPolicy, dry_run: bool) -> Result<()> {
+    // A .kdbx database is recognised by its signature, whatever the format.
+    let parsed = if import::is_kdbx(input)? {
+        let password = Zeroizing::new(rpassword::prompt_password("KeePass database password: ")?);
+        import::read_kdbx(input, &password)?
+    } else {
+        import::read_entries(input, adapter)?
+    };
 
-    let entries = store.list_entries()?;
+    for error in &parsed.errors {
+        println!("{} {}", "✗".red().bold(), error);
+    }
+    if parsed.entries.is_empty() {
+        println!("{}", "Nothing to import.".yellow());
+        return Ok(());
+    }
 
-    if entries.is_empty() {
-        println!("{}", "No passwords stored yet.".yellow());
+    let preview = store.import_entries_dry_run(parsed.entries.clone(), on_conflict)?;
+    println!("{}", "Import preview:".cyan().bold());
+    print_import_report(&preview);
+    if dry_run {
+        println!("{}", "Dry run: nothing was imported.".yellow());
         return Ok(());
     }
 
-    println!("{}", "Stored Passwords:".cyan().bold());
-    println!("{}", "=".repeat(50));
+    print!("Proceed with the import? (y/N): ");
+    io::stdout().flush()?;
+    let mut confirmation = String::new();
+    io::stdin().read_line(&mut confirmation)?;
 
-    for entry in entries {
-        println!("{} {} ({})",
-            "•".green(),
-            entry.service.yellow().bold(),
-            entry.username.blue()
-        );
-        println!("  Last updated: {}",
-            entry.updated_at.format("%Y-%m-%d %H:%M:%S").to_string().dimmed()
-        );
+    if confirmation.trim().to_lowercase() != "y" {
+        println!("Import cancelled.");
+        return Ok(());
+    }
+
+    let report = store.import_entries(parsed.entries, on_conflict)?;
+    println!("{} Import complete.", "✓".green().bold());
+    print_import_report(&report);
+    if !parsed.errors.is_empty() {
+        println!("{}", format!("{} records could not be mapped and were not imported.", parsed.errors.len()).yellow());
     }
     Ok(())
 }
 
-fn generate_password(length: Option<usize>, include_symbols: bool) -> Result<()> {
-    let generator = PasswordGenerator::new();
-    let length = length.unwrap_or(16);
-    let password = generator.generate(length, include_symbols)?;
+fn print_import_report(report: &ImportReport) {
+    println!("  Added: {}", report.added.len().to_string().green());
+    println!("  Overwritten: {}", report.overwritten.len().to_string().yellow());
+    println!("  Skipped: {}", report.skipped.len().to_string().blue());
+    if !report.renamed.is_empty() {
+        println!("  Renamed: {}", report.renamed.len().to_string().cyan());
+    }
+    if !report.skipped.is_empty() {
+        println!("  Skipped services: {}", report.skipped.join(", "));
+    }
+    if !report.renamed.is_empty() {
+        println!("  Renamed services: {}", report.renamed.join(", "));
+    }
+}
+
+fn rollback_database(store: &mut PasswordStore, options: RunOptions) -> Result<()> {
+    // A damaged file cannot be read, but can still be rolled back.
+    if !store.is_initialized()? && !Path::new(store.path()).exists() {
+        anyhow::bail!("Database not initialized. Run 'init' command first.");
+    }
+
+    let mut previous = store.previous_save()?;
+    let master_password = match env_master_password(options.non_interactive) {
+        Some(password) => password,
+        None => rpassword::prompt_password("Master password for the previous save: ")?,
+    };
+    if !previous.verify_master_password(&master_password)? {
+        anyhow::bail!("Invalid master password!");
+    }
+    if options.dry_run {
+        println!("Would replace {} with {}", store.path(), previous.path());
+        return Ok(());
+    }
+
+    print!("Replace the current database with the previous save? (y/N): ");
+    io::stdout().flush()?;
+    let mut confirmation = String::new();
+    io::stdin().read_line(&mut confirmation)?;
 
-    println!("{}", "Generated Password:".cyan().bold());
-    println!("{}", password.green().bold());
+    if confirmation.trim().to_lowercase() == "y" {
+        store.rollback_last_save()?;
+        println!("{} Rolled back to the previous save.", "✓".green().bold());
+    } else {
+        println!("Rollback cancelled.");
+    }
     Ok(())
 }
 
-fn delete_password(store: &mut PasswordStore, service: &str) -> Result<()> {
-    authenticate_user(store)?;
+fn backup_database(store: &PasswordStore, dir: Option<&Path>) -> Result<()> {
+    let target = store.backup_to(dir)?;
+    println!("{} Backed up to {}", "✓".green().bold(), target.display());
+    Ok(())
+}
 
-    if store.get_entry(service)?.is_none() {
-        println!("{}", format!("No entry found for service: {}", service).red());
+fn restore_database(store: &mut PasswordStore, file: &Path, dry_run: bool) -> Result<()> {
+    let backup = PasswordStore::open_backup(file)?;
+    if dry_run {
+        println!("Would replace {} with {}", store.path(), file.display());
         return Ok(());
     }
 
-    print!("Are you sure you want to delete the entry for '{}'? (y/N): ", service);
+    print!("Replace the current database with {}? (y/N): ", file.display());
     io::stdout().flush()?;
     let mut confirmation = String::new();
     io::stdin().read_line(&mut confirmation)?;
 
     if confirmation.trim().to_lowercase() == "y" {
-        store.delete_entry(service)?;
-        println!("{} Entry deleted for {}", "✓".green().bold(), service.cyan());
+        store.restore_from(backup)?;
+        println!("{} Restored from {}", "✓".green().bold(), file.display());
     } else {
-        println!("Deletion cancelled.");
+        println!("Restore cancelled.");
     }
     Ok(())
 }
 
-fn update_password(store: &mut PasswordStore, service: &str) -> Result<()> {
-    authenticate_user(store)?;
+// What a new master password must meet. With `allow_weak` a password that
+// falls short is only warned about.
+#[derive(Clone, Copy)]
+struct MasterPolicy {
+    min_bits: f64,
+    allow_weak: bool,
+}
+
+fn prompt_new_master_password(prompt: &str, confirm_prompt: &str, policy: MasterPolicy) -> Result<String> {
+    let master_password = rpassword::prompt_password(prompt)?;
+    let confirm_password = rpassword::prompt_password(confirm_prompt)?;
+
+    if master_password != confirm_password {
+        anyhow::bail!("Passwords do not match!");
+    }
+
+    check_master_password(&master_password, policy)?;
+    Ok(master_password)
+}
+
+fn check_master_password(password: &str, policy: MasterPolicy) -> Result<()> {
+    check_master_password_length(password)?;
+
+    let report = strength::estimate_strength(password);
+    println!("Master password strength: {} (~{:.0} bits)", report.category.colored_label(), report.entropy_bits);
+    let problems = strength::master_password_problems(password, policy.min_bits);
+    if problems.is_empty() {
+        return Ok(());
+    }
 
-    if store.get_entry(service)?.is_none() {
-        println!("{}", format!("No entry found for service: {}", service).red());
+    for problem in &problems {
+        println!("{} {}", "✗".red().bold(), problem);
+    }
+    if policy.allow_weak {
+        println!("{}", "Using the weak master password as --weak-master-ok was given.".yellow());
         return Ok(());
     }
+    anyhow::bail!("Master password is too weak; choose a stronger one or pass --weak-master-ok")
+}
+
+fn check_master_password_length(password: &str) -> Result<()> {
+    if password.len() < 8 {
+        anyhow::bail!("Master password must be at least 8 characters long!");
+    }
+    Ok(())
+}
+
+// Fields `add` and `update` accept besides the password.
+struct OptionalFields {
+    notes: Option<String>,
+    totp: Option<String>,
+    url: Option<String>,
+    custom_fields: Vec<CustomField>,
+    aliases: Vec<String>,
+    tags: Vec<String>,
+    // A generation policy to remember on the entry, or Some(None) to forget
+    // it.
+    policy: Option<Option<GeneratorOptions>>,
+}
+
+// Options for the passwords add and update generate, from their flags.
+fn entry_generator_options(config: &Config, length: Option<usize>, exclude_chars: Option<String>, no_symbols: bool) -> GeneratorOptions {
+    let mut generator_options = GeneratorOptions { exclude_chars: exclude_chars.unwrap_or_default(), ..config.generator_options(length) };
+    if no_symbols {
+        generator_options.include_symbols = false;
+        generator_options.min_symbols = 0;
+    }
+    generator_options
+}
+
+fn describe_policy(policy: &GeneratorOptions) -> String {
+    let mut parts = vec![
+        format!("{} characters", policy.length),
+        if policy.include_symbols { "symbols" } else { "no symbols" }.to_string(),
+    ];
+    if policy.exclude_ambiguous {
+        parts.push("no look-alike characters".to_string());
+    }
+    if !policy.exclude_chars.is_empty() {
+        parts.push(format!("excluding {}", policy.exclude_chars));
+    }
+    if let Some(max) = policy.max_repeat {
+        parts.push(format!("at most {} repeated", max));
+    }
+    parts.join(", ")
+}
+
+fn custom_fields(fields: Vec<(String, String)>, secret_fields: Vec<(String, String)>) -> Vec<CustomField> {
+    let plain = fields.into_iter().map(|(name, value)| CustomField { name, value, secret: false });
+    let secret = secret_fields.into_iter().map(|(name, value)| CustomField { name, value, secret: true });
+    plain.chain(secret).collect()
+}
+
+// How `get` shows the secrets of the entry it finds.
+struct GetDisplay {
+    reveal: bool,
+    clip: bool,
+    clip_timeout: u64,
+    show_totp_secret: bool,
+    field: Option<GetField>,
+    // Seconds to show the password for before overwriting it on screen.
+    reveal_for: Option<u64>,
+}
+
+// Reads the password for --password-stdin: the first line, with only its
+// line ending removed.
+fn read_password_stdin() -> Result<String> {
+    read_password_line(io::stdin().lock())
+}
+
+fn read_password_line(mut reader: impl io::BufRead) -> Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let end = line.strip_suffix('\n').map_or(line.len(), |rest| rest.strip_suffix('\r').unwrap_or(rest).len());
+    line.truncate(end);
+    if line.is_empty() {
+        anyhow::bail!("No password received on stdin");
+    }
+    Ok(line)
+}
+
+fn choose_password(prompt: &str, generator_options: &GeneratorOptions) -> Result<String> {
+    choose_password_from(io::stdin().lock(), prompt, generator_options)
+}
 
+fn choose_password_from(mut input: impl io::BufRead, prompt: &str, generator_options: &GeneratorOptions) -> Result<String> {
     println!("Choose password option:");
     println!("1. Generate random password");
     println!("2. Enter custom password");
@@ -197,34 +761,1525 @@ fn update_password(store: &mut PasswordStore, service: &str) -> Result<()> {
     print!("Choice (1/2): ");
     io::stdout().flush()?;
     let mut choice = String::new();
-    io::stdin().read_line(&mut choice)?;
+    input.read_line(&mut choice)?;
 
-    let new_password = match choice.trim() {
+    match choice.trim() {
         "1" => {
             let generator = PasswordGenerator::new();
-            generator.generate(16, true)?
+            generator.generate_with_options(generator_options)
         },
         "2" => {
-            rpassword::prompt_password("Enter new password: ")?
+            let password = rpassword::prompt_password(prompt)?;
+            print_strength(&password);
+            Ok(password)
         },
         _ => anyhow::bail!("Invalid choice!")
+    }
+}
+
+fn add_password(store: &mut PasswordStore, service: &str, username: Option<&str>, expires_in_days: Option<u64>, fields: OptionalFields, password: Option<String>, generator_options: &GeneratorOptions) -> Result<()> {
+    let totp_secret = fields.totp.map(|secret| totp::normalize_secret(&secret)).transpose()?;
+    let url = fields.url.map(|url| password_entry::validate_url(&url)).transpose()?;
+    let tags = password_entry::normalize_tags(fields.tags)?;
+
+    let username = match username {
+        Some(u) => u.to_string(),
+        None => {
+            print!("Username: ");
+            io::stdout().flush()?;
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)?;
+            input.trim().to_string()
+        }
     };
 
-    store.update_password(service, &new_password)?;
-    println!("{} Password updated for {}", "✓".green().bold(), service.cyan());
+    let password = match password {
+        Some(password) => password,
+        None => choose_password("Enter password: ", generator_options)?,
+    };
+
+    let mut entry = PasswordEntry::new(service.to_string(), username.clone(), password);
+    entry.notes = fields.notes;
+    entry.totp_secret = totp_secret;
+    entry.url = url;
+    for field in fields.custom_fields {
+        entry.set_custom_field(field);
+    }
+    entry.aliases = fields.aliases;
+    entry.tags = tags;
+    entry.gen_policy = fields.policy.flatten();
+    if let Some(days) = expires_in_days {
+        entry.expires_at = Some(batch::expiry_from_now(days)?);
+    }
+
+    store.add_entry(entry)?;
+    if store.is_dry_run() {
+        println!("Would add a password for {} ({})", service.cyan(), username);
+        return Ok(());
+    }
+    println!("{} Password added for {} ({})", "✓".green().bold(), service.cyan(), username);
     Ok(())
 }
 
-fn authenticate_user(store: &mut PasswordStore) -> Result<()> {
-    if !store.is_initialized()? {
-        anyhow::bail!("Database not initialized. Run 'init' command first.");
-    }
+fn add_batch(store: &mut PasswordStore, file: &Path, fail_fast: bool) -> Result<()> {
+    let records = batch::read_yaml(file)?;
+    let total = records.len();
+    let mut failures = Vec::new();
 
-    let master_password = rpassword::prompt_password("Master password: ")?;
+    // Entries added before a failure are kept, as if each were saved alone.
+    store.begin_batch();
+    for (index, record) in records.into_iter().enumerate() {
+        let service = record.service.clone();
+        let result = record.into_entry().and_then(|entry| Ok(store.add_entry_checked(entry)?));
 
-    if !store.verify_master_password(&master_password)? {
-        anyhow::bail!("Invalid master password!");
+        match result {
+            Ok(()) if store.is_dry_run() => println!("Would add {}", service.cyan()),
+            Ok(()) => println!("{} Added {}", "✓".green().bold(), service.cyan()),
+            Err(e) if fail_fast => {
+                store.commit_batch()?;
+                anyhow::bail!("Entry {} ({}): {}", index + 1, service, e);
+            },
+            Err(e) => failures.push(format!("Entry {} ({}): {}", index + 1, service, e)),
+        }
     }
+    store.commit_batch()?;
 
-    Ok(())
-}
\ No newline at end of file
+    if !failures.is_empty() {
+        for failure in &failures {
+            println!("{} {}", "✗".red().bold(), failure);
+        }
+        anyhow::bail!("{} of {} entries could not be added", failures.len(), total);
+    }
+
+    if store.is_dry_run() {
+        println!("Would add {} entries.", total);
+        return Ok(());
+    }
+    println!("{}", format!("Added {} entries.", total).green().bold());
+    Ok(())
+}
+
+fn bulk_add(store: &mut PasswordStore, file: &Path, generator_options: &GeneratorOptions) -> Result<()> {
+    let records = batch::read_bulk(file)?;
+    let total = records.len();
+    let generator = PasswordGenerator::new();
+
+    let mut generated = Vec::with_capacity(total);
+    let mut entries = Vec::with_capacity(total);
+    for record in records {
+        let password = match record.password {
+            Some(password) => {
+                generated.push(None);
+                password
+            },
+            None => {
+                let password = Zeroizing::new(generator.generate_with_options(generator_options)?);
+                generated.push(Some(password.clone()));
+                password.to_string()
+            },
+        };
+        entries.push(PasswordEntry::new(record.service, record.username, password));
+    }
+    let labels: Vec<String> = entries.iter().map(|entry| format!("{} ({})", entry.service, entry.username)).collect();
+
+    let results = store.add_entries(entries)?;
+    let mut failures = 0;
+    for ((label, result), generated) in labels.iter().zip(results).zip(&generated) {
+        match (result, generated) {
+            (Ok(()), _) if store.is_dry_run() => println!("Would add {}", label.cyan()),
+            (Ok(()), Some(password)) => println!("{} Added {} with generated password {}", "✓".green().bold(), label.cyan(), password.green().bold()),
+            (Ok(()), None) => println!("{} Added {}", "✓".green().bold(), label.cyan()),
+            (Err(e), _) => {
+                failures += 1;
+                println!("{} {}: {}", "✗".red().bold(), label, e);
+            },
+        }
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{} of {} entries could not be added", failures, total);
+    }
+    if store.is_dry_run() {
+        println!("Would add {} entries.", total);
+        return Ok(());
+    }
+    println!("{}", format!("Added {} entries.", total).green().bold());
+    Ok(())
+}
+
+// Resolves a name that is not an exact service through `matching_services`,
+// asking which one was meant when several match.
+fn select_service(store: &PasswordStore, service: &str, options: RunOptions) -> Result<String> {
+    let services = store.matching_services(service)?;
+    if services.len() < 2 {
+        return Ok(services.into_iter().next().unwrap_or_else(|| service.to_string()));
+    }
+    if options.json || options.non_interactive {
+        anyhow::bail!("{} matches several services: {}", service, services.join(", "));
+    }
+
+    println!("{} matches {} services:", service.cyan(), services.len());
+    for (index, service) in services.iter().enumerate() {
+        println!("  {}. {}", index + 1, service.blue());
+    }
+    print!("Choose one (1-{}): ", services.len());
+    io::stdout().flush()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+
+    match choice.trim().parse::<usize>() {
+        Ok(n) if (1..=services.len()).contains(&n) => Ok(services[n - 1].clone()),
+        _ => anyhow::bail!("Invalid choice!"),
+    }
+}
+
+// Asks which entry is meant when a service has several and no --username
+// was given. Without a terminal to ask on, the store reports the choices.
+fn select_username(store: &PasswordStore, service: &str, username: Option<String>, options: RunOptions) -> Result<Option<String>> {
+    if username.is_some() || options.json || options.non_interactive {
+        return Ok(username);
+    }
+    let usernames = store.usernames(service)?;
+    if usernames.len() < 2 {
+        return Ok(None);
+    }
+
+    println!("{} has {} entries:", service.cyan(), usernames.len());
+    for (index, username) in usernames.iter().enumerate() {
+        println!("  {}. {}", index + 1, username.blue());
+    }
+    print!("Choose one (1-{}): ", usernames.len());
+    io::stdout().flush()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+
+    match choice.trim().parse::<usize>() {
+        Ok(n) if (1..=usernames.len()).contains(&n) => Ok(Some(usernames[n - 1].clone())),
+        _ => anyhow::bail!("Invalid choice!"),
+    }
+}
+
+// Reported with its own exit status, so scripts can tell a missing entry
+// from other failures.
+fn entry_not_found(service: &str, username: Option<&str>) -> anyhow::Error {
+    StoreError::NotFound { service: service.to_string(), username: username.map(str::to_string) }.into()
+}
+
+fn get_password(store: &mut PasswordStore, service: &str, username: Option<&str>, display: GetDisplay, json: bool) -> Result<()> {
+    if let Some(field) = display.field {
+        return print_entry_field(store, service, username, field, display.reveal);
+    }
+    match store.get_entry(service, username)? {
+        Some(entry) => {
+            store.mark_accessed(service, Some(&entry.username))?;
+            let pending_clear = if display.clip {
+                Some(clipboard::copy_with_timeout(&entry.password, display.clip_timeout)?)
+            } else {
+                None
+            };
+
+            if json {
+                print_json(&entry_detail_json(&entry, &display))?;
+                if let Some(pending_clear) = pending_clear {
+                    pending_clear.wait(false);
+                }
+                return Ok(());
+            }
+
+            println!("{}", "Password Entry".cyan().bold());
+            println!("Service: {}", entry.service.yellow());
+            println!("Username: {}", entry.username.yellow());
+            if !entry.aliases.is_empty() {
+                println!("Aliases: {}", entry.aliases.join(", "));
+            }
+            if !entry.tags.is_empty() {
+                println!("Tags: {}", entry.tags.join(", "));
+            }
+            if let Some(url) = &entry.url {
+                println!("URL: {}", url.blue().underline());
+            }
+            // A timed reveal prints the password last, once the rest is shown.
+            if display.reveal_for.is_none() || display.clip {
+                println!("Password: {}", password_display(&entry.password, &display));
+            }
+            println!("Created: {}", entry.created_at.format("%Y-%m-%d %H:%M:%S").to_string().blue());
+            println!("Updated: {}", entry.updated_at.format("%Y-%m-%d %H:%M:%S").to_string().blue());
+            if let Some(expires_at) = entry.expires_at {
+                println!("Expires: {}", expires_at.format("%Y-%m-%d %H:%M:%S").to_string().blue());
+            }
+            if let Some(notes) = &entry.notes {
+                println!("Notes: {}", notes);
+            }
+            for field in &entry.custom_fields {
+                if field.secret && !display.reveal {
+                    println!("{}: {}", field.name, PASSWORD_MASK);
+                } else if field.secret {
+                    println!("{}: {}", field.name, field.value.green());
+                } else {
+                    println!("{}: {}", field.name, field.value);
+                }
+            }
+            if let Some(policy) = &entry.gen_policy {
+                println!("Generation policy: {}", describe_policy(policy));
+            }
+            if let Some(secret) = &entry.totp_secret {
+                if display.show_totp_secret {
+                    println!("TOTP secret: {}", secret.green());
+                } else {
+                    println!("TOTP: {}", "configured".green());
+                }
+            }
+
+            if let Some(seconds) = display.reveal_for {
+                reveal_briefly(&entry.password, seconds)?;
+            }
+            if let Some(pending_clear) = pending_clear {
+                pending_clear.wait(true);
+            }
+        },
+        None => return Err(entry_not_found(service, username)),
+    }
+    Ok(())
+}
+
+// The full entry as `get --json` prints it; the password only with --reveal.
+fn entry_detail_json(entry: &PasswordEntry, display: &GetDisplay) -> serde_json::Value {
+    let mut value = entry_summary_json(entry);
+    if display.reveal {
+        value["password"] = serde_json::json!(entry.password);
+    }
+    value["url"] = serde_json::json!(entry.url);
+    value["aliases"] = serde_json::json!(entry.aliases);
+    value["tags"] = serde_json::json!(entry.tags);
+    value["notes"] = serde_json::json!(entry.notes);
+    value["gen_policy"] = serde_json::json!(entry.gen_policy);
+    value["custom_fields"] = entry.custom_fields.iter()
+        .map(|field| {
+            let mut value = serde_json::json!({ "name": field.name, "secret": field.secret });
+            if !field.secret || display.reveal {
+                value["value"] = serde_json::json!(field.value);
+            }
+            value
+        })
+        .collect();
+    if display.show_totp_secret {
+        value["totp_secret"] = serde_json::json!(entry.totp_secret);
+    } else {
+        value["totp"] = serde_json::json!(entry.totp_secret.is_some());
+    }
+    value
+}
+
+fn password_display(password: &str, display: &GetDisplay) -> String {
+    if display.reveal {
+        password.green().to_string()
+    } else if display.clip {
+        "copied to clipboard".green().to_string()
+    } else {
+        format!("{} {}", PASSWORD_MASK, "(pass --reveal to show)".dimmed())
+    }
+}
+
+// Prints the password as the last line, then overwrites it with the mask
+// after `seconds`, or at once on Ctrl-C. Only the visible screen is cleaned;
+// a copy may remain in the terminal's scrollback or logs.
+fn reveal_briefly(password: &str, seconds: u64) -> Result<()> {
+    eprintln!("{}", format!("The password is hidden after {}s, but may stay in the terminal's scrollback.", seconds).yellow());
+    print!("Password: {}", password.green());
+    io::stdout().flush()?;
+
+    interrupt::begin_wait();
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    while !interrupt::interrupted() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        std::thread::sleep(remaining.min(Duration::from_millis(100)));
+    }
+    interrupt::end_wait();
+
+    // Back to the start of the line, erase it, and print the mask instead.
+    println!("\r\x1b[2KPassword: {} {}", PASSWORD_MASK, "(hidden)".dimmed());
+    io::stdout().flush()?;
+    if interrupt::interrupted() {
+        std::process::exit(130);
+    }
+    Ok(())
+}
+
+// `get --field`: the bare value and a newline, for command substitution. The
+// password still needs --reveal when it would land on a terminal.
+fn print_entry_field(store: &mut PasswordStore, service: &str, username: Option<&str>, field: GetField, reveal: bool) -> Result<()> {
+    if matches!(field, GetField::Password) && !reveal && io::stdout().is_terminal() {
+        anyhow::bail!("Pass --reveal to print the password to a terminal");
+    }
+    let entry = store.get_entry(service, username)?
+        .ok_or_else(|| entry_not_found(service, username))?;
+    store.mark_accessed(service, Some(&entry.username))?;
+
+    println!("{}", entry_field(&entry, field)?);
+    Ok(())
+}
+
+fn entry_field(entry: &PasswordEntry, field: GetField) -> Result<&str> {
+    match field {
+        GetField::Username => Ok(&entry.username),
+        GetField::Password => Ok(&entry.password),
+        GetField::Url => entry.url.as_deref().ok_or_else(|| anyhow::anyhow!("{} has no URL", entry.service)),
+        GetField::Notes => entry.notes.as_deref().ok_or_else(|| anyhow::anyhow!("{} has no notes", entry.service)),
+    }
+}
+
+fn show_totp_code(store: &PasswordStore, service: &str, username: Option<&str>) -> Result<()> {
+    let entry = match store.get_entry(service, username)? {
+        Some(entry) => entry,
+        None => return Err(entry_not_found(service, username)),
+    };
+
+    match &entry.totp_secret {
+        Some(secret) => {
+            let (code, remaining) = totp::current_code(secret)?;
+            println!("{} ({}s remaining)", code.green().bold(), remaining);
+        },
+        None => println!("{}", format!("No TOTP secret stored for {}", service).yellow()),
+    }
+    Ok(())
+}
+
+// Copies the password, unless `clip` is None, before opening the URL, so a
+// missing clipboard is reported before the browser starts.
+fn open_url(store: &mut PasswordStore, service: &str, username: Option<&str>, clip: Option<u64>) -> Result<()> {
+    let entry = match store.get_entry(service, username)? {
+        Some(entry) => entry,
+        None => return Err(entry_not_found(service, username)),
+    };
+
+    let pending_clear = match clip {
+        Some(seconds) => {
+            let pending_clear = clipboard::copy_with_timeout(&entry.password, seconds)?;
+            store.mark_accessed(service, Some(&entry.username))?;
+            Some(pending_clear)
+        },
+        None => None,
+    };
+
+    match &entry.url {
+        Some(url) => {
+            open::that_detached(url).map_err(|e| anyhow::anyhow!("Could not open {}: {}", url, e))?;
+            println!("{} Opened {}", "✓".green().bold(), url.blue());
+        },
+        None => println!("{}", format!("No URL stored for {}", service).yellow()),
+    }
+    if let Some(pending_clear) = pending_clear {
+        println!("{} Password for {} copied to clipboard", "✓".green().bold(), service.cyan());
+        pending_clear.wait(true);
+    }
+    Ok(())
+}
+
+fn show_qr_code(store: &PasswordStore, service: &str, username: Option<&str>, totp: bool) -> Result<()> {
+    let entry = match store.get_entry(service, username)? {
+        Some(entry) => entry,
+        None => return Err(entry_not_found(service, username)),
+    };
+
+    let (data, what) = if totp {
+        match &entry.totp_secret {
+            Some(secret) => (totp::provisioning_uri(&entry.service, Some(entry.username.as_str()).filter(|u| !u.is_empty()), secret)?, "TOTP secret"),
+            None => {
+                println!("{}", format!("No TOTP secret stored for {}", service).yellow());
+                return Ok(());
+            }
+        }
+    } else {
+        (Zeroizing::new(entry.password.clone()), "password")
+    };
+    let image = qr::render(&data)?;
+
+    println!("{}", format!("Warning: this QR code shows the {} for {} to anyone who can see the screen.", what, service).yellow().bold());
+    println!("{}", image.as_str());
+    print!("Press Enter to clear the screen...");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    // Clear the visible screen and the scrollback, then home the cursor.
+    print!("\x1b[2J\x1b[3J\x1b[H");
+    io::stdout().flush()?;
+    Ok(())
+}
+
+fn show_history(store: &PasswordStore, service: &str, username: Option<&str>) -> Result<()> {
+    let entry = match store.get_entry(service, username)? {
+        Some(entry) => entry,
+        None => return Err(entry_not_found(service, username)),
+    };
+
+    if entry.history.is_empty() {
+        println!("{}", format!("No previous passwords for {}", service).yellow());
+        return Ok(());
+    }
+
+    println!("{}", format!("Previous passwords for {}:", service).cyan().bold());
+    for item in &entry.history {
+        println!("{} {}", item.replaced_at.format("%Y-%m-%d %H:%M:%S").to_string().blue(), item.password.green());
+    }
+    Ok(())
+}
+
+fn show_event_log(store: &PasswordStore, limit: usize, json: bool) -> Result<()> {
+    let log = store.read_event_log()?;
+    let events = &log.events[log.events.len().saturating_sub(limit)..];
+
+    if json {
+        return print_json(&serde_json::json!({
+            "events": events,
+            "unreadable": log.unreadable,
+        }));
+    }
+
+    if events.is_empty() {
+        println!("{}", "No events recorded.".yellow());
+    }
+    for event in events {
+        println!(
+            "{} {:<8} {}",
+            event.timestamp.format("%Y-%m-%d %H:%M:%S").to_string().blue(),
+            event.action,
+            event.service
+        );
+    }
+    if log.unreadable > 0 {
+        println!("{}", format!("{} event(s) could not be read.", log.unreadable).yellow());
+    }
+    Ok(())
+}
+
+// How the age of each entry is shown by `list`: "Last updated" is green,
+// yellow from `warn_days` and red from `crit_days`.
+struct AgeDisplay {
+    show_age: bool,
+    warn_days: u64,
+    crit_days: u64,
+}
+
+impl Default for AgeDisplay {
+    fn default() -> Self {
+        Self { show_age: false, warn_days: 90, crit_days: 180 }
+    }
+}
+
+impl AgeDisplay {
+    fn colorize(&self, text: String, age_days: u64) -> ColoredString {
+        if age_days >= self.crit_days {
+            text.red()
+        } else if age_days >= self.warn_days {
+            text.yellow()
+        } else {
+            text.green()
+        }
+    }
+}
+
+// Which entries `list` shows.
+struct ListFilter {
+    favorites: bool,
+    tag: Option<String>,
+}
+
+fn list_passwords(store: &PasswordStore, sort: SortKey, limit: Option<usize>, offset: usize, filter: ListFilter, age: AgeDisplay, json: bool) -> Result<()> {
+    let entries = store.list_entries_sorted(sort, limit, offset, filter.favorites, filter.tag.as_deref())?;
+
+    if json {
+        let now = chrono::Utc::now();
+        return print_json(&entries.iter().map(|entry| {
+            let mut value = entry_summary_json(entry);
+            if age.show_age {
+                value["age_days"] = serde_json::json!(entry.age_days(now));
+            }
+            value
+        }).collect::<Vec<_>>());
+    }
+
+    if entries.is_empty() {
+        if offset > 0 {
+            println!("{}", "No more passwords.".yellow());
+        } else if let Some(tag) = &filter.tag {
+            println!("{}", format!("No entries tagged '{}'.", tag).yellow());
+        } else if filter.favorites {
+            println!("{}", "No favorites yet; mark one with `favorite <service>`.".yellow());
+        } else {
+            println!("{}", "No passwords stored yet.".yellow());
+        }
+        return Ok(());
+    }
+
+    println!("{}", "Stored Passwords:".cyan().bold());
+    print_entry_summaries(&entries, &age);
+    Ok(())
+}
+
+fn recent_passwords(store: &PasswordStore, count: usize, json: bool) -> Result<()> {
+    let entries = store.recently_accessed(count)?;
+
+    if json {
+        return print_json(&entries.iter().map(entry_summary_json).collect::<Vec<_>>());
+    }
+
+    if entries.is_empty() {
+        println!("{}", "No passwords have been retrieved yet.".yellow());
+        return Ok(());
+    }
+
+    println!("{}", "Recently Used:".cyan().bold());
+    println!("{}", "=".repeat(50));
+    for entry in &entries {
+        println!("{} {} ({})", "•".green(), entry.service.yellow().bold(), entry.username.blue());
+        if let Some(last_accessed) = entry.last_accessed {
+            println!("  Last accessed: {}", last_accessed.format("%Y-%m-%d %H:%M:%S").to_string().dimmed());
+        }
+    }
+    Ok(())
+}
+
+fn search_passwords(store: &PasswordStore, query: &str, match_username: bool, json: bool) -> Result<()> {
+    let entries = store.search(query, match_username)?;
+
+    if json {
+        return print_json(&entries.iter().map(entry_summary_json).collect::<Vec<_>>());
+    }
+
+    if entries.is_empty() {
+        println!("{}", format!("No matches for '{}'.", query).yellow());
+        return Ok(());
+    }
+
+    println!("{}", format!("Matches for '{}':", query).cyan().bold());
+    print_entry_summaries(&entries, &AgeDisplay::default());
+    Ok(())
+}
+
+fn print_entry_summaries(entries: &[PasswordEntry], age: &AgeDisplay) {
+    println!("{}", "=".repeat(50));
+
+    let now = chrono::Utc::now();
+    for entry in entries {
+        println!("{} {} ({})",
+            if entry.favorite { "★".yellow() } else { "•".green() },
+            entry.service.yellow().bold(),
+            entry.username.blue()
+        );
+        if !entry.tags.is_empty() {
+            println!("  Tags: {}", entry.tags.join(", ").dimmed());
+        }
+        let age_days = entry.age_days(now);
+        let updated = age.colorize(entry.updated_at.format("%Y-%m-%d %H:%M:%S").to_string(), age_days);
+        if age.show_age {
+            println!("  Last updated: {} ({} days ago)", updated, age_days);
+        } else {
+            println!("  Last updated: {}", updated);
+        }
+    }
+}
+
+fn entry_summary_json(entry: &PasswordEntry) -> serde_json::Value {
+    serde_json::json!({
+        "id": entry.id,
+        "service": entry.service,
+        "username": entry.username,
+        "created_at": entry.created_at,
+        "updated_at": entry.updated_at,
+        "expires_at": entry.expires_at,
+        "last_accessed": entry.last_accessed,
+        "favorite": entry.favorite,
+        "tags": entry.tags,
+    })
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn generate_password(options: GeneratorOptions, count: usize, clip: Option<u64>) -> Result<()> {
+    let generator = PasswordGenerator::new();
+    let passwords = (0..count)
+        .map(|_| generator.generate_with_options(&options))
+        .collect::<Result<Vec<_>>>()?;
+
+    let pending_clear = print_generated("Password", &passwords, clip)?;
+    println!(
+        "Entropy: {:.0} bits ({} characters from a set of {})",
+        generator.entropy_bits(&options),
+        options.length,
+        generator.charset_size(&options)
+    );
+    if let Some(pending_clear) = pending_clear {
+        pending_clear.wait(true);
+    }
+    Ok(())
+}
+
+fn generate_pronounceable(length: usize, include_symbols: bool, count: usize, clip: Option<u64>) -> Result<()> {
+    let generator = PasswordGenerator::new();
+    let passwords = (0..count)
+        .map(|_| generator.generate_pronounceable(length, include_symbols))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(pending_clear) = print_generated("Password", &passwords, clip)? {
+        pending_clear.wait(true);
+    }
+    Ok(())
+}
+
+fn generate_passphrase(words: usize, separator: &str, capitalize: bool, count: usize, clip: Option<u64>) -> Result<()> {
+    let generator = PasswordGenerator::new();
+    let passphrases = (0..count)
+        .map(|_| generator.generate_passphrase(words, separator, capitalize))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(pending_clear) = print_generated("Passphrase", &passphrases, clip)? {
+        pending_clear.wait(true);
+    }
+    Ok(())
+}
+
+// With `clip` set to a timeout, the value goes to the clipboard instead of
+// the terminal; the caller waits on the returned clear.
+fn print_generated(kind: &str, values: &[String], clip: Option<u64>) -> Result<Option<clipboard::PendingClear>> {
+    match (values, clip) {
+        ([], _) => anyhow::bail!("Count must be at least 1"),
+        ([value], Some(timeout)) => {
+            let pending_clear = clipboard::copy_with_timeout(value, timeout)?;
+            println!("{} Generated {} copied to clipboard", "✓".green().bold(), kind.to_lowercase());
+            return Ok(Some(pending_clear));
+        },
+        (_, Some(_)) => anyhow::bail!("--clip copies a single value; leave out --count"),
+        ([value], None) => {
+            println!("{}", format!("Generated {}:", kind).cyan().bold());
+            println!("{}", value.green().bold());
+        },
+        (_, None) => {
+            println!("{}", format!("Generated {}s:", kind).cyan().bold());
+            for (index, value) in values.iter().enumerate() {
+                println!("{:>3}. {}", index + 1, value.green().bold());
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn check_strength(password: Option<String>) -> Result<()> {
+    let password = match password {
+        Some(p) => p,
+        None => rpassword::prompt_password("Password to check: ")?,
+    };
+
+    print_strength(&password);
+    Ok(())
+}
+
+fn print_strength(password: &str) {
+    let report = strength::estimate_strength(password);
+    println!("Strength: {} ({:.0} bits)", report.category.colored_label(), report.entropy_bits);
+    for weakness in &report.weaknesses {
+        println!("  {} {}", "-".yellow(), weakness);
+    }
+}
+
+fn delete_password(store: &mut PasswordStore, service: &str, username: Option<&str>, dry_run: bool) -> Result<()> {
+    let entry = match store.get_entry(service, username)? {
+        Some(entry) => entry,
+        None => return Err(entry_not_found(service, username)),
+    };
+    if dry_run {
+        println!("Would delete the entry for '{}' (user {})", entry.service, entry.username);
+        return Ok(());
+    }
+
+    print!("Are you sure you want to delete the entry for '{}'? (y/N): ", entry.service);
+    io::stdout().flush()?;
+    let mut confirmation = String::new();
+    io::stdin().read_line(&mut confirmation)?;
+
+    if confirmation.trim().to_lowercase() == "y" {
+        store.delete_entry(&entry.service, Some(&entry.username))?;
+        println!("{} Entry deleted for {}", "✓".green().bold(), entry.service.cyan());
+    } else {
+        println!("Deletion cancelled.");
+    }
+    Ok(())
+}
+
+// Pins the entry to the top of `list`, or unpins it with --unset.
+fn set_favorite(store: &mut PasswordStore, service: &str, username: Option<&str>, favorite: bool) -> Result<()> {
+    store.set_favorite(service, username, favorite)?;
+    if store.is_dry_run() {
+        let change = if favorite { "mark" } else { "unmark" };
+        println!("Would {} {} as a favorite", change, service.cyan());
+        return Ok(());
+    }
+    if favorite {
+        println!("{} {} marked as a favorite", "★".yellow(), service.cyan());
+    } else {
+        println!("{} {} is no longer a favorite", "✓".green().bold(), service.cyan());
+    }
+    Ok(())
+}
+
+// Sets the notes from --notes, removes them with --clear-notes, or otherwise
+// reads them from stdin up to end of file, so they can span several lines.
+// The password is left alone.
+fn edit_notes(store: &mut PasswordStore, service: &str, username: Option<&str>, notes: Option<String>, clear: bool) -> Result<()> {
+    let entry = store.get_entry(service, username)?
+        .ok_or_else(|| entry_not_found(service, username))?;
+
+    let notes = match (notes, clear) {
+        (_, true) => None,
+        (Some(notes), false) => Some(notes),
+        (None, false) => {
+            if io::stdin().is_terminal() {
+                if let Some(current) = &entry.notes {
+                    println!("Current notes:\n{}", current);
+                }
+                println!("Enter the new notes, then press Ctrl-D on an empty line:");
+            }
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            Some(input.trim_end().to_string())
+        },
+    };
+    let notes = notes.filter(|notes| !notes.trim().is_empty());
+
+    let cleared = notes.is_none();
+    store.set_notes(service, Some(&entry.username), notes)?;
+    if store.is_dry_run() {
+        let change = if cleared { "remove the notes from" } else { "update the notes for" };
+        println!("Would {} {}", change, service.cyan());
+        return Ok(());
+    }
+    if cleared {
+        println!("{} Notes removed from {}", "✓".green().bold(), service.cyan());
+    } else {
+        println!("{} Notes updated for {}", "✓".green().bold(), service.cyan());
+    }
+    Ok(())
+}
+
+// In a dry run the store still checks the rename; it just is not saved.
+fn rename_entry(store: &mut PasswordStore, old_service: &str, new_service: &str, username: Option<&str>, dry_run: bool) -> Result<()> {
+    store.rename_entry(old_service, new_service, username)?;
+    if dry_run {
+        println!("Would rename {} to {}", old_service.cyan(), new_service.cyan());
+        return Ok(());
+    }
+    println!("{} Renamed {} to {}", "✓".green().bold(), old_service.cyan(), new_service.cyan());
+    Ok(())
+}
+
+fn update_password(store: &mut PasswordStore, service: &str, username: Option<&str>, no_history: bool, fields: OptionalFields, password: Option<String>, generator_options: &GeneratorOptions) -> Result<()> {
+    let totp_secret = fields.totp.map(|secret| totp::normalize_secret(&secret)).transpose()?;
+    let url = fields.url.map(|url| password_entry::validate_url(&url)).transpose()?;
+    let tags = password_entry::normalize_tags(fields.tags)?;
+
+    let new_password = match password {
+        Some(password) => password,
+        None => choose_password("Enter new password: ", generator_options)?,
+    };
+
+    store.begin_batch();
+    let result = (|| -> Result<()> {
+        if no_history {
+            store.replace_password_no_history(service, username, &new_password)?;
+        } else {
+            store.update_password(service, username, &new_password)?;
+        }
+        if fields.notes.is_some() {
+            store.set_notes(service, username, fields.notes)?;
+        }
+        if totp_secret.is_some() {
+            store.set_totp_secret(service, username, totp_secret)?;
+        }
+        if url.is_some() {
+            store.set_url(service, username, url)?;
+        }
+        if !fields.custom_fields.is_empty() {
+            store.set_custom_fields(service, username, fields.custom_fields)?;
+        }
+        if !fields.aliases.is_empty() {
+            store.set_aliases(service, username, fields.aliases)?;
+        }
+        if !tags.is_empty() {
+            store.set_tags(service, username, tags)?;
+        }
+        if let Some(policy) = fields.policy {
+            store.set_gen_policy(service, username, policy)?;
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(()) => store.commit_batch()?,
+        Err(e) => {
+            store.rollback_batch();
+            return Err(e);
+        }
+    }
+    if store.is_dry_run() {
+        println!("Would update the password for {}", service.cyan());
+        return Ok(());
+    }
+    println!("{} Password updated for {}", "✓".green().bold(), service.cyan());
+    if no_history {
+        println!("{}", "Password history not updated.".yellow());
+    }
+    Ok(())
+}
+
+fn export_passwords(store: &PasswordStore, format: ExportFormat, output: Option<&Path>, yes: bool) -> Result<()> {
+    eprintln!("{}", "Warning: the export contains every password in plaintext.".red().bold());
+
+    if !yes {
+        eprint!("Continue with the export? (y/N): ");
+        io::stderr().flush()?;
+        let mut confirmation = String::new();
+        io::stdin().read_line(&mut confirmation)?;
+
+        if confirmation.trim().to_lowercase() != "y" {
+            eprintln!("Export cancelled.");
+            return Ok(());
+        }
+    }
+
+    let mut entries = store.list_entries()?;
+    entries.sort_by(|a, b| a.service.cmp(&b.service));
+
+    match output {
+        Some(path) => {
+            let mut file = export::create_private_file(path)?;
+            format.exporter().write(&entries, &mut file)?;
+            file.sync_all()?;
+            eprintln!("{} Exported {} entries to {}", "✓".green().bold(), entries.len(), path.display());
+        },
+        None => {
+            format.exporter().write(&entries, &mut io::stdout().lock())?;
+        }
+    }
+    Ok(())
+}
+
+fn audit_passwords(store: &PasswordStore, online: bool) -> Result<()> {
+    let report = store.audit();
+
+    if report.reused.is_empty() {
+        println!("{} No reused passwords found.", "✓".green().bold());
+    } else {
+        println!("{}", format!("{} reused password(s):", report.reused.len()).yellow().bold());
+        for services in &report.reused {
+            println!("{} {}", "•".yellow(), services.join(", ").cyan());
+        }
+    }
+
+    if report.weak.is_empty() {
+        println!("{} No weak passwords found.", "✓".green().bold());
+    } else {
+        println!("{}", format!("{} weak password(s):", report.weak.len()).yellow().bold());
+        for entry in &report.weak {
+            println!("{} {} ({:.0} bits): {}", "•".yellow(), entry.service.cyan(), entry.entropy_bits, entry.issues.join(", "));
+        }
+    }
+
+    if online {
+        println!("Checking passwords against Have I Been Pwned...");
+        let entries = store.list_entries()?;
+        let breached = breach::find_breached(&breach::HibpClient::new(), &entries)?;
+        if breached.is_empty() {
+            println!("{} No breached passwords found.", "✓".green().bold());
+        } else {
+            println!("{}", format!("{} breached password(s):", breached.len()).red().bold());
+            for (service, count) in breached {
+                println!("{} {} (seen {} times)", "•".red(), service.cyan(), count);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn show_expired(store: &PasswordStore, days: u64, json: bool) -> Result<()> {
+    let entries = store.entries_older_than(days)?;
+    let now = chrono::Utc::now();
+
+    if json {
+        let values: Vec<serde_json::Value> = entries.iter()
+            .map(|entry| {
+                let mut value = entry_summary_json(entry);
+                value["age_days"] = serde_json::json!(entry.age_days(now));
+                value
+            })
+            .collect();
+        return print_json(&values);
+    }
+
+    if entries.is_empty() {
+        println!("{} No passwords older than {} days.", "✓".green().bold(), days);
+        return Ok(());
+    }
+
+    println!("{}", format!("{} password(s) older than {} days:", entries.len(), days).yellow().bold());
+    for entry in &entries {
+        println!("{} {} ({}) - {} days old", "•".yellow(), entry.service.cyan(), entry.username.blue(), entry.age_days(now));
+    }
+    Ok(())
+}
+
+fn show_stats(store: &PasswordStore, days: u64, json: bool) -> Result<()> {
+    let stats = store.stats(days)?;
+    if json {
+        return print_json(&stats);
+    }
+
+    let date = |time: Option<chrono::DateTime<chrono::Utc>>| time.map_or("-".to_string(), |time| time.format("%Y-%m-%d").to_string());
+    println!("{}", "Vault Summary".cyan().bold());
+    println!("{}", "=".repeat(50));
+    println!("Entries: {}", stats.entries.to_string().yellow());
+    println!("Unique usernames: {}", stats.unique_usernames.to_string().yellow());
+    let reused = stats.reused_passwords.to_string();
+    println!("Reused passwords: {}", if stats.reused_passwords > 0 { reused.red() } else { reused.green() });
+    let old = stats.older_than_days.to_string();
+    println!("Older than {} days: {}", stats.old_days, if stats.older_than_days > 0 { old.yellow() } else { old.green() });
+    println!("Average password length: {:.1}", stats.average_length);
+    println!("Oldest entry: {}", date(stats.oldest_created).blue());
+    println!("Newest entry: {}", date(stats.newest_created).blue());
+    Ok(())
+}
+
+fn dedupe_entries(store: &mut PasswordStore, dry_run: bool) -> Result<()> {
+    let groups = store.duplicate_groups()?;
+    if groups.is_empty() {
+        println!("{} No duplicate entries found.", "✓".green().bold());
+        return Ok(());
+    }
+
+    println!("{}", format!("{} group(s) of possible duplicates:", groups.len()).yellow().bold());
+    let mut deleted = 0;
+    for group in &groups {
+        println!();
+        for (index, entry) in group.iter().enumerate() {
+            println!(
+                "  {}. {} ({}) - updated {}",
+                index + 1,
+                entry.service.cyan(),
+                entry.username.blue(),
+                entry.updated_at.format("%Y-%m-%d %H:%M:%S").to_string().dimmed()
+            );
+        }
+        if dry_run {
+            continue;
+        }
+
+        print!("Keep which entry? (1-{}, Enter to skip): ", group.len());
+        io::stdout().flush()?;
+        let mut choice = String::new();
+        io::stdin().read_line(&mut choice)?;
+        if choice.trim().is_empty() {
+            continue;
+        }
+
+        let keep = match choice.trim().parse::<usize>() {
+            Ok(n) if (1..=group.len()).contains(&n) => n - 1,
+            _ => {
+                println!("{}", "Invalid choice, skipping this group.".yellow());
+                continue;
+            },
+        };
+        let remove: Vec<&PasswordEntry> = group.iter()
+            .enumerate()
+            .filter(|(index, _)| *index != keep)
+            .map(|(_, entry)| entry)
+            .collect();
+        store.delete_entries(&remove.iter().map(|entry| entry.id).collect::<Vec<_>>())?;
+        deleted += remove.len();
+        let removed: Vec<&str> = remove.iter().map(|entry| entry.service.as_str()).collect();
+        println!("{} Kept {}, deleted {}", "✓".green().bold(), group[keep].service.cyan(), removed.join(", "));
+    }
+
+    if !dry_run {
+        println!();
+        println!("{} entr{} deleted.", deleted, if deleted == 1 { "y" } else { "ies" });
+    }
+    Ok(())
+}
+
+fn rotate_many(store: &mut PasswordStore, services: &[String], options: GeneratorOptions, output_csv: Option<&Path>, dry_run: bool) -> Result<()> {
+    let rotated = store.generate_and_rotate_multiple(services, &options)?;
+    let mut listed = Vec::new();
+    for service in services {
+        if !listed.contains(service) {
+            listed.push(service.clone());
+        }
+    }
+
+    // No password was generated, so there is nothing to write to the CSV.
+    if dry_run {
+        println!("Would rotate {} passwords:", listed.len());
+        for service in &listed {
+            println!("  {}", service.yellow());
+        }
+        if let Some(path) = output_csv {
+            println!("Would write the new passwords to {}", path.display());
+        }
+        return Ok(());
+    }
+
+    println!("{}", format!("Rotated {} passwords. Update each service:", rotated.len()).cyan().bold());
+    for service in &listed {
+        println!("  [ ] {}", service.yellow());
+    }
+
+    match output_csv {
+        Some(path) => {
+            let mut writer = csv::Writer::from_writer(export::create_private_file(path)?);
+            writer.write_record(["service", "password"])?;
+            for service in &listed {
+                writer.write_record([service, &rotated[service]])?;
+            }
+            writer.flush()?;
+            println!("{}", format!("Warning: {} contains plaintext passwords. Delete it once your accounts are updated.", path.display()).yellow().bold());
+        },
+        None => {
+            println!("Use 'get <service>' to view each new password.");
+        }
+    }
+    Ok(())
+}
+
+fn warn_expiring_entries(store: &PasswordStore, days: u64) {
+    let expiring = store.watch_for_expiry_and_notify(days);
+    if expiring.is_empty() {
+        return;
+    }
+
+    let noun = if expiring.len() == 1 { "password" } else { "passwords" };
+    println!("{}", format!("⚠ {} {} expiring soon: {}", expiring.len(), noun, expiring.join(", ")).yellow().bold());
+}
+
+fn warn_rekey_due(store: &PasswordStore) {
+    if let Some(encryptions) = store.rekey_due() {
+        println!("{}", format!("⚠ This vault has been encrypted {} times under the current key; run 'change-master --rekey' to rekey it", encryptions).yellow().bold());
+    }
+}
+
+fn print_config(config: &Config, database_path: &str, json: bool) -> Result<()> {
+    let effective = config.effective(database_path.to_string());
+    if json {
+        return print_json(&effective);
+    }
+
+    let path = config::config_path()?;
+    let status = if path.exists() { "" } else { " (not found)" };
+    println!("{}", format!("# Config file: {}{}", path.display(), status).dimmed());
+    print!("{}", toml::to_string(&effective)?);
+    Ok(())
+}
+
+fn run_doctor(database_path: &str, kdf: KdfParams, json: bool) -> Result<()> {
+    let checks = doctor::run_checks(database_path, &kdf);
+    let failed = checks.iter().filter(|check| check.status == doctor::Status::Fail).count();
+
+    if json {
+        print_json(&checks)?;
+    } else {
+        for check in &checks {
+            let mark = match check.status {
+                doctor::Status::Pass => "✓".green().bold(),
+                doctor::Status::Warn => "!".yellow().bold(),
+                doctor::Status::Fail => "✗".red().bold(),
+            };
+            println!("{} {} {} {}", mark, check.name, format!("({} ms)", check.elapsed.as_millis()).dimmed(), check.detail);
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
+}
+
+fn list_profiles() -> Result<()> {
+    let profiles = profiles::list_profiles()?;
+    if profiles.is_empty() {
+        println!("{}", format!("No profiles found in {}", profiles::profiles_dir()?.display()).yellow());
+        return Ok(());
+    }
+
+    println!("{}", "Profiles:".cyan().bold());
+    for profile in profiles {
+        println!("{} {}", "•".green(), profile.yellow());
+    }
+    Ok(())
+}
+
+fn print_completions(shell: clap_complete::Shell) -> Result<()> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+    Ok(())
+}
+
+fn run_shell(store: &mut PasswordStore, options: RunOptions) -> Result<()> {
+    println!("{}", "Vault unlocked. Type 'help' for commands or 'exit' to quit.".cyan());
+
+    let mut line = String::new();
+    loop {
+        print!("vault> ");
+        io::stdout().flush()?;
+
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+        let input = line.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if input == "exit" || input == "quit" {
+            break;
+        }
+
+        if store.lock_if_idle() {
+            println!("{}", "Session locked after inactivity.".yellow());
+        }
+        if store.is_locked() {
+            if let Err(e) = authenticate_user(store, options) {
+                eprintln!("{} {}", "Error:".red().bold(), e);
+                continue;
+            }
+        }
+
+        let Some(args) = shlex::split(input) else {
+            eprintln!("{} Unbalanced quotes", "Error:".red().bold());
+            continue;
+        };
+        match ShellLine::try_parse_from(args) {
+            Ok(ShellLine { command }) => {
+                let result = check_writable(&command, options.read_only)
+                    .and_then(|()| check_dry_run(&command, options.dry_run))
+                    .and_then(|()| reauthenticate_if_due(store, &command, options))
+                    .and_then(|()| load_entries_for(store, &command))
+                    .and_then(|()| run_command(store, command, options));
+                if let Err(e) = result {
+                    eprintln!("{} {}", "Error:".red().bold(), e);
+                }
+            },
+            Err(e) => {
+                let _ = e.print();
+            }
+        }
+    }
+
+    store.lock();
+    println!("{}", "Vault locked.".green());
+    Ok(())
+}
+
+fn authenticate_user(store: &mut PasswordStore, options: RunOptions) -> Result<()> {
+    if !store.is_initialized()? {
+        anyhow::bail!("Database not initialized. Run 'init' command first.");
+    }
+
+    let master_password = match env_master_password(options.non_interactive) {
+        Some(password) => password,
+        None => rpassword::prompt_password("Master password: ")?,
+    };
+
+    // A cancelled unlock leaves the store locked, even if the key was
+    // derived by the time the worker finished.
+    let verified = progress::with_spinner("Unlocking...", options.show_progress(), || store.verify_master_password(&master_password))
+        .inspect_err(|_| store.lock())?;
+    if !verified? {
+        anyhow::bail!("Invalid master password!");
+    }
+
+    if let Some(migration) = store.migrate(&master_password)? {
+        eprintln!("{}", format!("Upgraded database from format version {}; the old file is kept as .prev", migration.from_version).yellow());
+        eprintln!("{}", "Files in the old format can be decrypted without the master password. Delete the .prev files and older backups once you no longer need them.".yellow());
+        if let Some(recovery_key) = migration.recovery_key {
+            eprintln!("{}", "The vault was re-encrypted, so the old recovery key no longer works.".yellow());
+            show_recovery_key(&recovery_key);
+        }
+    }
+    Ok(())
+}
+
+// Cheap once loaded: the file is only decrypted again if it has changed.
+fn load_entries_for(store: &mut PasswordStore, command: &Command) -> Result<()> {
+    if command.needs_entries() {
+        store.load_entries_cached()?;
+    }
+    for warning in store.take_load_warnings() {
+        eprintln!("{}", format!("Warning: {}", warning).yellow());
+    }
+    Ok(())
+}
+
+// In the shell, commands that read or change entries ask for the master
+// password again once the re-authentication interval has passed.
+fn reauthenticate_if_due(store: &mut PasswordStore, command: &Command, options: RunOptions) -> Result<()> {
+    if !command.requires_auth() || !store.needs_reauth() {
+        return Ok(());
+    }
+    let master_password = match env_master_password(options.non_interactive) {
+        Some(password) => password,
+        None => {
+            println!("{}", "The master password is due to be re-entered.".yellow());
+            rpassword::prompt_password("Master password: ")?
+        }
+    };
+    if !progress::with_spinner("Checking...", options.show_progress(), || store.reauthenticate(&master_password))?? {
+        anyhow::bail!("Invalid master password!");
+    }
+    Ok(())
+}
+
+// The environment is only consulted with --non-interactive; `env` looks up a
+// variable so the choice can be made without touching the real environment.
+fn master_password_from_env(non_interactive: bool, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if !non_interactive {
+        return None;
+    }
+    env(MASTER_PASSWORD_ENV).filter(|password| !password.is_empty())
+}
+
+fn env_master_password(non_interactive: bool) -> Option<String> {
+    let password = master_password_from_env(non_interactive, |key| std::env::var(key).ok())?;
+    ENV_PASSWORD_WARNING.call_once(|| {
+        eprintln!("{}", format!("Warning: reading the master password from {} is less secure than typing it.", MASTER_PASSWORD_ENV).yellow());
+    });
+    Some(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_batch_round_trips_the_fixture_through_the_vault() {
+        let dir = std::env::temp_dir().join(format!("passrusted-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("passwords.db").to_string_lossy().into_owned();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/batch.yaml");
+
+        let mut store = PasswordStore::new(&path).unwrap();
+        store.initialize("correct horse battery staple", 1, 10, KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 }, crypto::DEFAULT_REKEY_AFTER, storage::Compression::None).unwrap();
+        add_batch(&mut store, &fixture, true).unwrap();
+        assert!(add_batch(&mut store, &fixture, true).is_err());
+
+        let mut reopened = PasswordStore::new(&path).unwrap();
+        assert!(reopened.verify_master_password("correct horse battery staple").unwrap());
+        reopened.load_entries_cached().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, "hunter2-GitHub!");
+        assert!(reopened.get_entry("gitlab", None).unwrap().unwrap().expires_at.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dry_run_add_batch_saves_nothing() {
+        let dir = std::env::temp_dir().join(format!("passrusted-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("passwords.db").to_string_lossy().into_owned();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/batch.yaml");
+
+        let mut store = PasswordStore::new(&path).unwrap();
+        store.initialize("correct horse battery staple", 1, 10, KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 }, crypto::DEFAULT_REKEY_AFTER, storage::Compression::None).unwrap();
+        let before = std::fs::read(&path).unwrap();
+        store.set_dry_run(true);
+        add_batch(&mut store, &fixture, true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shell_lines_parse_as_commands() {
+        let args = shlex::split(r#"add "my bank" -u alice"#).unwrap();
+        match ShellLine::try_parse_from(args).unwrap().command {
+            Command::Add { service, username, .. } => {
+                assert_eq!(service, "my bank");
+                assert_eq!(username.as_deref(), Some("alice"));
+            },
+            _ => panic!("expected an add command"),
+        }
+        assert!(ShellLine::try_parse_from(["frobnicate"]).is_err());
+    }
+
+    #[test]
+    fn json_summaries_leave_out_secrets() {
+        let mut entry = PasswordEntry::new("github".to_string(), "alice".to_string(), "hunter2".to_string());
+        entry.notes = Some("work account".to_string());
+        let value = entry_summary_json(&entry);
+
+        assert_eq!(value["service"], "github");
+        assert_eq!(value["username"], "alice");
+        assert_eq!(value["id"], entry.id.to_string());
+        assert!(value["expires_at"].is_null());
+        assert!(value.get("password").is_none());
+        assert!(value.get("notes").is_none());
+    }
+
+    #[test]
+    fn generating_zero_candidates_is_an_error() {
+        assert!(generate_password(GeneratorOptions::default(), 0, None).is_err());
+        assert!(generate_passphrase(5, "-", false, 0, None).is_err());
+        assert!(generate_password(GeneratorOptions::default(), 3, None).is_ok());
+    }
+
+    #[test]
+    fn clipping_several_generated_values_is_an_error() {
+        let values = vec!["one".to_string(), "two".to_string()];
+        assert!(print_generated("Password", &values, Some(45)).is_err());
+        assert!(generate_passphrase(5, "-", false, 2, Some(45)).is_err());
+    }
+
+    #[test]
+    fn master_password_is_read_from_the_environment_only_when_non_interactive() {
+        let set = |key: &str| (key == MASTER_PASSWORD_ENV).then(|| "hunter2".to_string());
+
+        assert_eq!(master_password_from_env(true, set), Some("hunter2".to_string()));
+        assert_eq!(master_password_from_env(false, set), None);
+        assert_eq!(master_password_from_env(true, |_| None), None);
+        assert_eq!(master_password_from_env(true, |_| Some(String::new())), None);
+    }
+
+    #[test]
+    fn completions_cover_the_subcommands() {
+        Cli::command().debug_assert();
+
+        let mut command = Cli::command();
+        let mut script = Vec::new();
+        clap_complete::generate(clap_complete::Shell::Bash, &mut command, "secure_password_manager", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("rotate-many"));
+        assert!(script.contains("completions"));
+    }
+
+    #[test]
+    fn stdin_passwords_lose_only_the_line_ending() {
+        assert_eq!(read_password_line(&b" two words \r\nignored\n"[..]).unwrap(), " two words ");
+        assert_eq!(read_password_line(&b"no newline"[..]).unwrap(), "no newline");
+        assert!(read_password_line(&b"\n"[..]).is_err());
+        assert!(read_password_line(&b""[..]).is_err());
+    }
+
+    fn display(reveal: bool, clip: bool) -> GetDisplay {
+        GetDisplay { reveal, clip, clip_timeout: 45, show_totp_secret: false, field: None, reveal_for: None }
+    }
+
+    #[test]
+    fn get_masks_the_password_unless_revealed() {
+        let masked = password_display("hunter2", &display(false, false));
+        assert!(masked.contains(PASSWORD_MASK) && !masked.contains("hunter2"));
+        assert!(password_display("hunter2", &display(true, false)).contains("hunter2"));
+
+        let clipped = password_display("hunter2", &display(false, true));
+        assert!(clipped.contains("copied to clipboard") && !clipped.contains("hunter2"));
+        assert!(password_display("hunter2", &display(true, true)).contains("hunter2"));
+    }
+
+    #[test]
+    fn get_json_includes_the_password_only_when_revealed() {
+        let mut entry = PasswordEntry::new("github".to_string(), "alice".to_string(), "hunter2".to_string());
+        entry.totp_secret = Some("JBSWY3DPEHPK3PXP".to_string());
+
+        let hidden = entry_detail_json(&entry, &display(false, true));
+        assert!(hidden.get("password").is_none());
+        assert_eq!(hidden["totp"], true);
+        assert!(hidden.get("totp_secret").is_none());
+
+        let revealed = entry_detail_json(&entry, &display(true, false));
+        assert_eq!(revealed["password"], "hunter2");
+    }
+
+    #[test]
+    fn list_age_colours_switch_at_the_thresholds() {
+        let age = AgeDisplay { show_age: false, warn_days: 30, crit_days: 60 };
+        let colour = |days| age.colorize(String::new(), days).fgcolor;
+        assert_eq!(colour(0), Some(colored::Color::Green));
+        assert_eq!(colour(29), Some(colored::Color::Green));
+        assert_eq!(colour(30), Some(colored::Color::Yellow));
+        assert_eq!(colour(59), Some(colored::Color::Yellow));
+        assert_eq!(colour(60), Some(colored::Color::Red));
+    }
+
+    #[test]
+    fn secret_custom_fields_are_masked_unless_revealed() {
+        let mut entry = PasswordEntry::new("bank".to_string(), "alice".to_string(), "hunter2".to_string());
+        entry.custom_fields = custom_fields(
+            vec![("branch".to_string(), "north".to_string())],
+            vec![("pin".to_string(), "1234".to_string())],
+        );
+
+        let hidden = entry_detail_json(&entry, &display(false, false));
+        assert_eq!(hidden["custom_fields"][0]["value"], "north");
+        assert_eq!(hidden["custom_fields"][1]["secret"], true);
+        assert!(hidden["custom_fields"][1].get("value").is_none());
+
+        let revealed = entry_detail_json(&entry, &display(true, false));
+        assert_eq!(revealed["custom_fields"][1]["value"], "1234");
+    }
+
+    #[test]
+    fn choosing_to_generate_uses_the_generator_options() {
+        let options = GeneratorOptions { length: 24, include_symbols: false, min_symbols: 0, ..GeneratorOptions::default() };
+        let password = choose_password_from(&b"1\n"[..], "Password: ", &options).unwrap();
+        assert_eq!(password.len(), 24);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()), "{}", password);
+
+        assert!(choose_password_from(&b"3\n"[..], "Password: ", &options).is_err());
+    }
+
+    #[test]
+    fn read_only_refuses_only_mutating_commands() {
+        let parse = |line: &str| ShellLine::try_parse_from(shlex::split(line).unwrap()).unwrap().command;
+        assert!(check_writable(&parse("delete github"), true).is_err());
+        assert!(check_writable(&parse("dedupe"), true).is_err());
+        assert!(check_writable(&parse("dedupe --dry-run"), true).is_ok());
+        assert!(check_writable(&parse("get github"), true).is_ok());
+        assert!(check_writable(&parse("delete github"), false).is_ok());
+    }
+
+    #[test]
+    fn dry_run_refuses_commands_that_hand_out_keys() {
+        let parse = |line: &str| ShellLine::try_parse_from(shlex::split(line).unwrap()).unwrap().command;
+        assert!(check_dry_run(&parse("change-master"), true).is_err());
+        assert!(check_dry_run(&parse("recover"), true).is_err());
+        assert!(check_dry_run(&parse("delete github"), true).is_ok());
+        assert!(check_dry_run(&parse("change-master"), false).is_ok());
+    }
+
+    #[test]
+    fn each_field_selector_prints_its_value() {
+        let mut entry = PasswordEntry::new("github".to_string(), "alice".to_string(), "hunter2".to_string());
+        assert_eq!(entry_field(&entry, GetField::Username).unwrap(), "alice");
+        assert_eq!(entry_field(&entry, GetField::Password).unwrap(), "hunter2");
+        assert!(entry_field(&entry, GetField::Url).is_err());
+        assert!(entry_field(&entry, GetField::Notes).is_err());
+
+        entry.url = Some("https://github.com/".to_string());
+        entry.notes = Some("work account".to_string());
+        assert_eq!(entry_field(&entry, GetField::Url).unwrap(), "https://github.com/");
+        assert_eq!(entry_field(&entry, GetField::Notes).unwrap(), "work account");
+    }
+
+    #[test]
+    fn a_missing_entry_exits_with_status_two() {
+        assert_eq!(exit_code(&entry_not_found("github", Some("alice"))), 2);
+        assert_eq!(exit_code(&StoreError::Locked.into()), 1);
+        assert_eq!(exit_code(&anyhow::anyhow!("something else")), 1);
+    }
+
+    #[test]
+    fn colors_are_only_used_at_a_terminal_without_opting_out() {
+        assert!(use_color(false, false, true));
+        assert!(!use_color(true, false, true));
+        assert!(!use_color(false, true, true));
+        assert!(!use_color(false, false, false));
+    }
+}
@@ -1,10 +1,67 @@
 // src/password_entry.rs
 
+use std::collections::HashMap;
+
+use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
+use crate::password_generator::GeneratorOptions;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryField {
+    Id,
+    Service,
+    Username,
+    Password,
+    CreatedAt,
+    UpdatedAt,
+    ExpiresAt,
+    Notes,
+    TotpSecret,
+    Url,
+    // The tags, joined with commas.
+    Tags,
+    // One `meta_<name>` key per custom field.
+    Metadata,
+}
+
+impl EntryField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryField::Id => "id",
+            EntryField::Service => "service",
+            EntryField::Username => "username",
+            EntryField::Password => "password",
+            EntryField::CreatedAt => "created_at",
+            EntryField::UpdatedAt => "updated_at",
+            EntryField::ExpiresAt => "expires_at",
+            EntryField::Notes => "notes",
+            EntryField::TotpSecret => "totp_secret",
+            EntryField::Url => "url",
+            EntryField::Tags => "tags",
+            EntryField::Metadata => "metadata",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Zeroize, ZeroizeOnDrop)]
+pub struct PasswordHistoryItem {
+    pub password: String,
+    #[zeroize(skip)]
+    pub replaced_at: DateTime<Utc>,
+}
+
+// An extra named value such as a PIN or an account number. Secret ones are
+// masked when shown, like the password.
+#[derive(Serialize, Deserialize, Clone, Debug, Zeroize, ZeroizeOnDrop)]
+pub struct CustomField {
+    pub name: String,
+    pub value: String,
+    pub secret: bool,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, Zeroize, ZeroizeOnDrop)]
 pub struct PasswordEntry {
@@ -17,6 +74,37 @@ pub struct PasswordEntry {
     pub created_at: DateTime<Utc>,
     #[zeroize(skip)]
     pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub history: Vec<PasswordHistoryItem>,
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub last_accessed: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub custom_fields: Vec<CustomField>,
+    // Other names `get` finds the entry by.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    // Pinned to the top of `list`.
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub favorite: bool,
+    // Labels such as `work` that `list --tag` filters on.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Rules remembered with --remember-policy for passwords generated for
+    // this entry.
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub gen_policy: Option<GeneratorOptions>,
 }
 
 impl PasswordEntry {
@@ -29,12 +117,242 @@ impl PasswordEntry {
             password,
             created_at: now,
             updated_at: now,
+            expires_at: None,
+            notes: None,
+            history: Vec::new(),
+            totp_secret: None,
+            url: None,
+            last_accessed: None,
+            custom_fields: Vec::new(),
+            aliases: Vec::new(),
+            favorite: false,
+            tags: Vec::new(),
+            gen_policy: None,
+        }
+    }
+
+    // Whole days since the password last changed. A timestamp in the future
+    // (clock skew between machines) counts as changed today.
+    pub fn age_days(&self, now: DateTime<Utc>) -> u64 {
+        now.signed_duration_since(self.updated_at).num_days().max(0) as u64
+    }
+
+    // Replaces the field with the same name, or adds it. An empty value
+    // removes the field.
+    pub fn set_custom_field(&mut self, field: CustomField) {
+        let existing = self.custom_fields.iter().position(|existing| existing.name == field.name);
+        match (existing, field.value.is_empty()) {
+            (Some(index), true) => {
+                self.custom_fields.remove(index);
+            },
+            (Some(index), false) => self.custom_fields[index] = field,
+            (None, true) => {},
+            (None, false) => self.custom_fields.push(field),
+        }
+    }
+
+    // Whether `name` is the service or one of the aliases, ignoring case.
+    pub fn answers_to(&self, name: &str) -> bool {
+        self.service.to_lowercase() == name.to_lowercase()
+            || self.aliases.iter().any(|alias| alias.to_lowercase() == name.to_lowercase())
+    }
+
+    // Tags match ignoring case, like service names.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|existing| existing.to_lowercase() == tag.to_lowercase())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.service.trim().is_empty() {
+            anyhow::bail!("Service name must not be empty");
+        }
+        if self.aliases.iter().any(|alias| alias.trim().is_empty()) {
+            anyhow::bail!("Aliases for {} must not be empty", self.service);
+        }
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            anyhow::bail!("Tags for {} must not be empty", self.service);
+        }
+        if self.password.is_empty() {
+            anyhow::bail!("Password for {} must not be empty", self.service);
+        }
+        Ok(())
+    }
+    
+    // Keeps the replaced password in `history`, newest first, holding at most
+    // `max_history` items.
+    pub fn update_password(&mut self, new_password: String, max_history: usize) {
+        let now = Utc::now();
+        let old_password = std::mem::replace(&mut self.password, new_password);
+        self.history.insert(0, PasswordHistoryItem {
+            password: old_password,
+            replaced_at: now,
+        });
+        self.history.truncate(max_history);
+        self.updated_at = now;
+    }
+    
+    pub fn export_fields(&self, fields: &[EntryField]) -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        for field in fields {
+            let value = match field {
+                EntryField::Id => self.id.to_string(),
+                EntryField::Service => self.service.clone(),
+                EntryField::Username => self.username.clone(),
+                EntryField::Password => self.password.clone(),
+                EntryField::CreatedAt => self.created_at.to_rfc3339(),
+                EntryField::UpdatedAt => self.updated_at.to_rfc3339(),
+                EntryField::ExpiresAt => self.expires_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                EntryField::Notes => self.notes.clone().unwrap_or_default(),
+                EntryField::TotpSecret => self.totp_secret.clone().unwrap_or_default(),
+                EntryField::Url => self.url.clone().unwrap_or_default(),
+                EntryField::Tags => self.tags.join(","),
+                EntryField::Metadata => {
+                    for custom in &self.custom_fields {
+                        values.insert(format!("meta_{}", custom.name), custom.value.clone());
+                    }
+                    continue;
+                }
+            };
+            values.insert(field.name().to_string(), value);
+        }
+        values
+    }
+}
+
+// Trims each tag and drops repeats that differ only in case, keeping the
+// order given.
+pub fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            anyhow::bail!("Tags must not be empty");
+        }
+        if !normalized.iter().any(|existing| existing.to_lowercase() == tag.to_lowercase()) {
+            normalized.push(tag.to_string());
+        }
+    }
+    Ok(normalized)
+}
+
+// Login URLs must be absolute http(s) URLs; returns the normalized form.
+pub fn validate_url(value: &str) -> Result<String> {
+    let url = url::Url::parse(value.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid URL '{}': {}", value, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        anyhow::bail!("URL must start with http:// or https://: {}", value);
+    }
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_entry() -> PasswordEntry {
+        let mut entry = PasswordEntry::new("github".to_string(), "alice".to_string(), "hunter2".to_string());
+        entry.notes = Some("work account".to_string());
+        entry.url = Some("https://github.com/".to_string());
+        entry.tags = vec!["work".to_string(), "code".to_string()];
+        entry.set_custom_field(CustomField { name: "pin".to_string(), value: "1234".to_string(), secret: true });
+        entry.set_custom_field(CustomField { name: "account".to_string(), value: "42".to_string(), secret: false });
+        entry
+    }
+
+    #[test]
+    fn export_fields_extracts_each_field() {
+        let entry = sample_entry();
+        let all = [
+            EntryField::Id, EntryField::Service, EntryField::Username, EntryField::Password,
+            EntryField::CreatedAt, EntryField::UpdatedAt, EntryField::ExpiresAt, EntryField::Notes,
+            EntryField::TotpSecret, EntryField::Url, EntryField::Tags,
+        ];
+        let values = entry.export_fields(&all);
+
+        assert_eq!(values["id"], entry.id.to_string());
+        assert_eq!(values["service"], "github");
+        assert_eq!(values["username"], "alice");
+        assert_eq!(values["password"], "hunter2");
+        assert_eq!(values["created_at"], entry.created_at.to_rfc3339());
+        assert_eq!(values["updated_at"], entry.updated_at.to_rfc3339());
+        assert_eq!(values["expires_at"], "");
+        assert_eq!(values["notes"], "work account");
+        assert_eq!(values["totp_secret"], "");
+        assert_eq!(values["url"], "https://github.com/");
+        assert_eq!(values["tags"], "work,code");
+        assert_eq!(values.len(), all.len());
+    }
+
+    #[test]
+    fn export_fields_prefixes_metadata_keys() {
+        let values = sample_entry().export_fields(&[EntryField::Metadata]);
+
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["meta_pin"], "1234");
+        assert_eq!(values["meta_account"], "42");
+    }
+
+    #[test]
+    fn export_fields_leaves_out_unrequested_fields() {
+        let values = sample_entry().export_fields(&[EntryField::Service, EntryField::Username]);
+
+        assert_eq!(values.len(), 2);
+        assert!(!values.contains_key("password"));
+        assert!(!values.contains_key("tags"));
+    }
+
+    #[test]
+    fn update_password_keeps_a_capped_history() {
+        let mut entry = PasswordEntry::new("github".to_string(), "alice".to_string(), "first".to_string());
+        entry.update_password("second".to_string(), 2);
+        entry.update_password("third".to_string(), 2);
+        entry.update_password("fourth".to_string(), 2);
+
+        assert_eq!(entry.password, "fourth");
+        let history: Vec<&str> = entry.history.iter().map(|item| item.password.as_str()).collect();
+        assert_eq!(history, ["third", "second"]);
+    }
+
+    #[test]
+    fn only_absolute_http_urls_are_accepted() {
+        assert_eq!(validate_url(" https://github.com/login ").unwrap(), "https://github.com/login");
+        assert_eq!(validate_url("http://example.com").unwrap(), "http://example.com/");
+        for invalid in ["github.com", "ftp://example.com", "javascript:alert(1)", "file:///etc/passwd"] {
+            assert!(validate_url(invalid).is_err(), "{}", invalid);
         }
     }
 
-    pub fn update_password(&mut self, new_password: String) {
-        self.password.zeroize();
-        self.password = new_password;
-        self.updated_at = Utc::now();
+    #[test]
+    fn age_counts_whole_days_and_never_goes_negative() {
+        let mut entry = PasswordEntry::new("github".to_string(), "alice".to_string(), "hunter2".to_string());
+        let now = entry.updated_at;
+
+        assert_eq!(entry.age_days(now + chrono::Duration::hours(47)), 1);
+        assert_eq!(entry.age_days(now + chrono::Duration::days(91)), 91);
+        entry.updated_at = now + chrono::Duration::days(3);
+        assert_eq!(entry.age_days(now), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn custom_fields_are_replaced_by_name_and_removed_when_empty() {
+        let mut entry = PasswordEntry::new("bank".to_string(), "alice".to_string(), "hunter2".to_string());
+        let field = |name: &str, value: &str| CustomField { name: name.to_string(), value: value.to_string(), secret: false };
+        entry.set_custom_field(field("account", "12345"));
+        entry.set_custom_field(field("branch", "north"));
+        entry.set_custom_field(field("account", "67890"));
+        let values: Vec<(&str, &str)> = entry.custom_fields.iter().map(|f| (f.name.as_str(), f.value.as_str())).collect();
+        assert_eq!(values, [("account", "67890"), ("branch", "north")]);
+
+        entry.set_custom_field(field("account", ""));
+        entry.set_custom_field(field("missing", ""));
+        assert_eq!(entry.custom_fields.len(), 1);
+        assert_eq!(entry.custom_fields[0].name, "branch");
+    }
+
+    #[test]
+    fn tags_are_trimmed_and_deduplicated_ignoring_case() {
+        let tags = normalize_tags(vec!["Work".to_string(), " work ".to_string(), "banking".to_string()]).unwrap();
+        assert_eq!(tags, ["Work", "banking"]);
+        assert!(normalize_tags(vec![" ".to_string()]).is_err());
+    }
+}
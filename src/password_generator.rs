@@ -1,5 +1,98 @@
-use rand::{thread_rng, Rng};
+use std::collections::HashSet;
+
+use phf::phf_map;
+use rand::{rngs::OsRng, thread_rng, Rng};
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+const MAX_UNIQUE_ATTEMPTS: usize = 32;
+const MAX_REPEAT_ATTEMPTS: usize = 100;
+const KEYBOARD_WALK_MIN_RUN: usize = 4;
+pub const MIN_PASSPHRASE_WORDS: usize = 3;
+
+// EFF large wordlist: "<dice roll>\t<word>" per line, 7776 words.
+static EFF_WORDLIST: &str = include_str!("eff_large_wordlist.txt");
+
+// Key positions as (row, column) for the layouts checked by `check_keyboard_walk`.
+static QWERTY: phf::Map<char, (i8, i8)> = phf_map! {
+    '1' => (0, 0), '2' => (0, 1), '3' => (0, 2), '4' => (0, 3), '5' => (0, 4), '6' => (0, 5),
+    '7' => (0, 6), '8' => (0, 7), '9' => (0, 8), '0' => (0, 9), '-' => (0, 10), '=' => (0, 11),
+    'q' => (1, 0), 'w' => (1, 1), 'e' => (1, 2), 'r' => (1, 3), 't' => (1, 4), 'y' => (1, 5),
+    'u' => (1, 6), 'i' => (1, 7), 'o' => (1, 8), 'p' => (1, 9), '[' => (1, 10), ']' => (1, 11),
+    'a' => (2, 0), 's' => (2, 1), 'd' => (2, 2), 'f' => (2, 3), 'g' => (2, 4), 'h' => (2, 5),
+    'j' => (2, 6), 'k' => (2, 7), 'l' => (2, 8), ';' => (2, 9), '\'' => (2, 10),
+    'z' => (3, 0), 'x' => (3, 1), 'c' => (3, 2), 'v' => (3, 3), 'b' => (3, 4), 'n' => (3, 5),
+    'm' => (3, 6), ',' => (3, 7), '.' => (3, 8), '/' => (3, 9),
+};
+
+static QWERTZ: phf::Map<char, (i8, i8)> = phf_map! {
+    '1' => (0, 0), '2' => (0, 1), '3' => (0, 2), '4' => (0, 3), '5' => (0, 4), '6' => (0, 5),
+    '7' => (0, 6), '8' => (0, 7), '9' => (0, 8), '0' => (0, 9), 'ß' => (0, 10),
+    'q' => (1, 0), 'w' => (1, 1), 'e' => (1, 2), 'r' => (1, 3), 't' => (1, 4), 'z' => (1, 5),
+    'u' => (1, 6), 'i' => (1, 7), 'o' => (1, 8), 'p' => (1, 9), 'ü' => (1, 10), '+' => (1, 11),
+    'a' => (2, 0), 's' => (2, 1), 'd' => (2, 2), 'f' => (2, 3), 'g' => (2, 4), 'h' => (2, 5),
+    'j' => (2, 6), 'k' => (2, 7), 'l' => (2, 8), 'ö' => (2, 9), 'ä' => (2, 10), '#' => (2, 11),
+    'y' => (3, 0), 'x' => (3, 1), 'c' => (3, 2), 'v' => (3, 3), 'b' => (3, 4), 'n' => (3, 5),
+    'm' => (3, 6), ',' => (3, 7), '.' => (3, 8), '-' => (3, 9),
+};
+
+static AZERTY: phf::Map<char, (i8, i8)> = phf_map! {
+    '1' => (0, 0), '2' => (0, 1), '3' => (0, 2), '4' => (0, 3), '5' => (0, 4), '6' => (0, 5),
+    '7' => (0, 6), '8' => (0, 7), '9' => (0, 8), '0' => (0, 9), ')' => (0, 10), '=' => (0, 11),
+    'a' => (1, 0), 'z' => (1, 1), 'e' => (1, 2), 'r' => (1, 3), 't' => (1, 4), 'y' => (1, 5),
+    'u' => (1, 6), 'i' => (1, 7), 'o' => (1, 8), 'p' => (1, 9), '^' => (1, 10), '$' => (1, 11),
+    'q' => (2, 0), 's' => (2, 1), 'd' => (2, 2), 'f' => (2, 3), 'g' => (2, 4), 'h' => (2, 5),
+    'j' => (2, 6), 'k' => (2, 7), 'l' => (2, 8), 'm' => (2, 9), 'ù' => (2, 10), '*' => (2, 11),
+    'w' => (3, 0), 'x' => (3, 1), 'c' => (3, 2), 'v' => (3, 3), 'b' => (3, 4), 'n' => (3, 5),
+    ',' => (3, 6), ';' => (3, 7), ':' => (3, 8), '!' => (3, 9),
+};
+
+static KEYBOARD_LAYOUTS: [&phf::Map<char, (i8, i8)>; 3] = [&QWERTY, &QWERTZ, &AZERTY];
+
+// Building blocks for pronounceable passwords. Clusters are picked whole, so
+// a syllable may take up to four letters.
+const CONSONANTS: &[&str] = &[
+    "b", "c", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v", "w", "z",
+    "br", "ch", "cr", "dr", "fl", "gr", "pl", "sh", "st", "th", "tr",
+];
+const VOWELS: &[&str] = &["a", "e", "i", "o", "u", "ai", "ea", "io", "ou"];
+
+// Glyphs that are easily confused with one another when read or typed.
+const AMBIGUOUS_CHARS: &str = "lI1|O0o";
+
+// Also stored on an entry as the rules for its generated passwords, so
+// fields added later need a default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneratorOptions {
+    pub length: usize,
+    pub include_symbols: bool,
+    pub exclude_ambiguous: bool,
+    pub min_lower: usize,
+    pub min_upper: usize,
+    pub min_digits: usize,
+    pub min_symbols: usize,
+    // Longest run of one character repeated, e.g. 2 allows "aa" but not "aaa".
+    pub max_repeat: Option<usize>,
+    // Characters a site will not accept.
+    pub exclude_chars: String,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            length: 16,
+            include_symbols: true,
+            exclude_ambiguous: false,
+            min_lower: 1,
+            min_upper: 1,
+            min_digits: 1,
+            min_symbols: 1,
+            max_repeat: None,
+            exclude_chars: String::new(),
+        }
+    }
+}
 
 pub struct PasswordGenerator {
     lowercase: &'static str,
@@ -8,6 +101,12 @@ pub struct PasswordGenerator {
     symbols: &'static str,
 }
 
+impl Default for PasswordGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PasswordGenerator {
     pub fn new() -> Self {
         Self {
@@ -19,40 +118,338 @@ impl PasswordGenerator {
     }
     
     pub fn generate(&self, length: usize, include_symbols: bool) -> Result<String> {
-        if length < 4 {
+        self.generate_with_options(&GeneratorOptions {
+            length,
+            include_symbols,
+            min_symbols: if include_symbols { 1 } else { 0 },
+            ..GeneratorOptions::default()
+        })
+    }
+    
+    pub fn generate_with_options(&self, options: &GeneratorOptions) -> Result<String> {
+        if options.length < 4 {
             anyhow::bail!("Password length must be at least 4 characters");
         }
+        if options.min_symbols > 0 && !options.include_symbols {
+            anyhow::bail!("A minimum number of symbols requires symbols to be included");
+        }
+        if options.max_repeat == Some(0) {
+            anyhow::bail!("The maximum run of repeated characters must be at least 1");
+        }
         
-        let mut charset = String::new();
-        charset.push_str(self.lowercase);
-        charset.push_str(self.uppercase);
-        charset.push_str(self.numbers);
+        let required = options.min_lower + options.min_upper + options.min_digits + options.min_symbols;
+        if required > options.length {
+            anyhow::bail!(
+                "Requested minimums need {} characters but the length is {}",
+                required,
+                options.length
+            );
+        }
         
-        if include_symbols {
-            charset.push_str(self.symbols);
+        let classes = [
+            (required_class("lowercase letters", self.lowercase, options.min_lower, options)?, options.min_lower),
+            (required_class("uppercase letters", self.uppercase, options.min_upper, options)?, options.min_upper),
+            (required_class("digits", self.numbers, options.min_digits, options)?, options.min_digits),
+            (required_class("symbols", self.symbols, options.min_symbols, options)?, options.min_symbols),
+        ];
+        let charset = self.charset(options);
+        if charset.is_empty() {
+            anyhow::bail!("Excluded characters leave nothing to generate a password from");
         }
         
-        let charset: Vec<char> = charset.chars().collect();
+        // The minimums hold by construction; only the repeat limit is checked
+        // after the fact, by drawing again.
         let mut rng = thread_rng();
-        let mut password = Vec::with_capacity(length);
+        for _ in 0..MAX_REPEAT_ATTEMPTS {
+            let mut password = Vec::with_capacity(options.length);
+            for (chars, min) in &classes {
+                for _ in 0..*min {
+                    password.push(chars[rng.gen_range(0..chars.len())]);
+                }
+            }
+            
+            for _ in password.len()..options.length {
+                password.push(charset[rng.gen_range(0..charset.len())]);
+            }
+            
+            for i in (1..password.len()).rev() {
+                let j = rng.gen_range(0..=i);
+                password.swap(i, j);
+            }
+            
+            if options.max_repeat.is_none_or(|max| longest_run(&password) <= max) {
+                return Ok(password.into_iter().collect());
+            }
+        }
         
-        password.push(self.lowercase.chars().nth(rng.gen_range(0..self.lowercase.len())).unwrap());
-        password.push(self.uppercase.chars().nth(rng.gen_range(0..self.uppercase.len())).unwrap());
-        password.push(self.numbers.chars().nth(rng.gen_range(0..self.numbers.len())).unwrap());
+        anyhow::bail!(
+            "Could not generate a password without runs longer than {}; allow longer runs or more characters",
+            options.max_repeat.unwrap_or_default()
+        )
+    }
+    
+    // Every character `generate_with_options` may draw from for these options.
+    fn charset(&self, options: &GeneratorOptions) -> Vec<char> {
+        let mut classes = vec![self.lowercase, self.uppercase, self.numbers];
+        if options.include_symbols {
+            classes.push(self.symbols);
+        }
+        classes.into_iter()
+            .flat_map(|class| pick_from(class, options))
+            .collect()
+    }
+    
+    pub fn charset_size(&self, options: &GeneratorOptions) -> usize {
+        self.charset(options).len()
+    }
+    
+    // Upper bound on the strength of a password from `generate_with_options`,
+    // treating every character as drawn uniformly from the full charset.
+    pub fn entropy_bits(&self, options: &GeneratorOptions) -> f64 {
+        options.length as f64 * (self.charset_size(options) as f64).log2()
+    }
+    
+    pub fn generate_unique(&self, options: &GeneratorOptions, taken: &HashSet<String>) -> Result<String> {
+        for _ in 0..MAX_UNIQUE_ATTEMPTS {
+            let password = self.generate_with_options(options)?;
+            if !taken.contains(&password) {
+                return Ok(password);
+            }
+        }
         
-        if include_symbols {
-            password.push(self.symbols.chars().nth(rng.gen_range(0..self.symbols.len())).unwrap());
+        anyhow::bail!("Could not generate a unique password; try a longer length")
+    }
+    
+    // Alternating consonant and vowel clusters, capitalized, followed by a
+    // digit and optionally a symbol, so the same classes are present as in
+    // `generate_with_options`. Each letter carries roughly 3 bits against
+    // about 6 for a fully random character, so a pronounceable password needs
+    // close to twice the length for the same strength.
+    pub fn generate_pronounceable(&self, length: usize, include_symbols: bool) -> Result<String> {
+        if length < 4 {
+            anyhow::bail!("Password length must be at least 4 characters");
         }
         
-        for _ in password.len()..length {
-            password.push(charset[rng.gen_range(0..charset.len())]);
+        let letters = length - 1 - usize::from(include_symbols);
+        let mut password = String::with_capacity(length);
+        let mut consonant = OsRng.gen_bool(0.5);
+        while password.len() < letters {
+            let clusters = if consonant { CONSONANTS } else { VOWELS };
+            password.push_str(clusters[OsRng.gen_range(0..clusters.len())]);
+            consonant = !consonant;
         }
+        password.truncate(letters);
+        password[..1].make_ascii_uppercase();
         
-        for i in (1..password.len()).rev() {
-            let j = rng.gen_range(0..=i);
-            password.swap(i, j);
+        let numbers: Vec<char> = self.numbers.chars().collect();
+        password.push(numbers[OsRng.gen_range(0..numbers.len())]);
+        if include_symbols {
+            let symbols: Vec<char> = self.symbols.chars().collect();
+            password.push(symbols[OsRng.gen_range(0..symbols.len())]);
         }
         
-        Ok(password.into_iter().collect())
+        Ok(password)
     }
-}
\ No newline at end of file
+    
+    pub fn generate_passphrase(&self, word_count: usize, separator: &str, capitalize: bool) -> Result<String> {
+        if word_count < MIN_PASSPHRASE_WORDS {
+            anyhow::bail!("Passphrase must have at least {} words", MIN_PASSPHRASE_WORDS);
+        }
+        
+        let words: Vec<&str> = EFF_WORDLIST
+            .lines()
+            .filter_map(|line| line.split('\t').nth(1))
+            .collect();
+        
+        let passphrase: Vec<String> = (0..word_count)
+            .map(|_| {
+                let word = words[OsRng.gen_range(0..words.len())];
+                if capitalize {
+                    let mut chars = word.chars();
+                    chars.next()
+                        .map(|first| first.to_uppercase().chain(chars).collect())
+                        .unwrap_or_default()
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect();
+        
+        Ok(passphrase.join(separator))
+    }
+    
+    pub fn check_keyboard_walk(password: &str) -> bool {
+        let chars: Vec<char> = password.chars().flat_map(char::to_lowercase).collect();
+        
+        KEYBOARD_LAYOUTS.iter().any(|layout| {
+            let mut run = 1;
+            for pair in chars.windows(2) {
+                let adjacent = match (layout.get(&pair[0]), layout.get(&pair[1])) {
+                    // A repeated key is not a step along the keyboard.
+                    (Some(a), Some(b)) => (a.0 - b.0).abs().max((a.1 - b.1).abs()) == 1,
+                    _ => false,
+                };
+                run = if adjacent { run + 1 } else { 1 };
+                if run >= KEYBOARD_WALK_MIN_RUN {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+}
+
+fn pick_from(class: &str, options: &GeneratorOptions) -> Vec<char> {
+    class.chars()
+        .filter(|c| !options.exclude_ambiguous || !AMBIGUOUS_CHARS.contains(*c))
+        .filter(|c| !options.exclude_chars.contains(*c))
+        .collect()
+}
+
+fn required_class(name: &str, class: &str, min: usize, options: &GeneratorOptions) -> Result<Vec<char>> {
+    let chars = pick_from(class, options);
+    if min > 0 && chars.is_empty() {
+        anyhow::bail!("Excluded characters leave no {} for the {} required", name, min);
+    }
+    Ok(chars)
+}
+
+fn longest_run(password: &[char]) -> usize {
+    password.chunk_by(|a, b| a == b).map(<[char]>::len).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn keyboard_walks_are_found() {
+        assert!(PasswordGenerator::check_keyboard_walk("qwerty"));
+        assert!(PasswordGenerator::check_keyboard_walk("xx1qaz2wsx"));
+        assert!(PasswordGenerator::check_keyboard_walk("ASDFgh"));
+    }
+    
+    #[test]
+    fn repeated_keys_are_not_a_keyboard_walk() {
+        assert!(!PasswordGenerator::check_keyboard_walk("aaaa"));
+        assert!(!PasswordGenerator::check_keyboard_walk("zzzzzzzz"));
+        assert!(!PasswordGenerator::check_keyboard_walk("qqwwee"));
+    }
+    
+    #[test]
+    fn ordinary_passwords_are_not_keyboard_walks() {
+        assert!(!PasswordGenerator::check_keyboard_walk("Vq7#tLm2"));
+        assert!(!PasswordGenerator::check_keyboard_walk("qwe"));
+    }
+    
+    #[test]
+    fn generate_takes_the_length_and_whether_to_use_symbols() {
+        let generator = PasswordGenerator::new();
+        let password = generator.generate(20, false).unwrap();
+        assert_eq!(password.len(), 20);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(generator.generate(12, true).unwrap().chars().any(|c| generator.symbols.contains(c)));
+    }
+    
+    #[test]
+    fn passphrases_have_the_requested_words() {
+        let generator = PasswordGenerator::new();
+        let passphrase = generator.generate_passphrase(5, "-", false).unwrap();
+        assert_eq!(passphrase.split('-').count(), 5);
+        assert!(generator.generate_passphrase(MIN_PASSPHRASE_WORDS - 1, "-", false).is_err());
+    }
+    
+    #[test]
+    fn capitalized_passphrases_start_each_word_upper_case() {
+        let passphrase = PasswordGenerator::new().generate_passphrase(4, " ", true).unwrap();
+        assert!(passphrase.split(' ').all(|word| word.starts_with(|c: char| c.is_uppercase())));
+    }
+    
+    #[test]
+    fn generated_passwords_meet_the_policy() {
+        let generator = PasswordGenerator::new();
+        let options = GeneratorOptions {
+            length: 12,
+            min_lower: 2,
+            min_upper: 3,
+            min_digits: 2,
+            min_symbols: 2,
+            max_repeat: Some(1),
+            exclude_ambiguous: true,
+            ..GeneratorOptions::default()
+        };
+        for _ in 0..1000 {
+            let password = generator.generate_with_options(&options).unwrap();
+            let chars: Vec<char> = password.chars().collect();
+            assert_eq!(chars.len(), 12);
+            assert!(chars.iter().filter(|c| c.is_ascii_lowercase()).count() >= 2);
+            assert!(chars.iter().filter(|c| c.is_ascii_uppercase()).count() >= 3);
+            assert!(chars.iter().filter(|c| c.is_ascii_digit()).count() >= 2);
+            assert!(chars.iter().filter(|c| !c.is_ascii_alphanumeric()).count() >= 2);
+            assert!(longest_run(&chars) <= 1, "{}", password);
+            assert!(!chars.iter().any(|c| AMBIGUOUS_CHARS.contains(*c)), "{}", password);
+        }
+    }
+    
+    #[test]
+    fn impossible_policies_are_refused() {
+        let generator = PasswordGenerator::new();
+        let too_many = GeneratorOptions { length: 8, min_lower: 3, min_upper: 3, min_digits: 3, ..GeneratorOptions::default() };
+        assert!(generator.generate_with_options(&too_many).is_err());
+    
+        let symbols_off = GeneratorOptions { include_symbols: false, ..GeneratorOptions::default() };
+        assert!(generator.generate_with_options(&symbols_off).is_err());
+    
+        let no_digits = GeneratorOptions { exclude_chars: "0123456789".to_string(), ..GeneratorOptions::default() };
+        assert!(generator.generate_with_options(&no_digits).is_err());
+    }
+    
+    #[test]
+    fn without_symbols_only_alphanumerics_are_drawn() {
+        let generator = PasswordGenerator::new();
+        let options = GeneratorOptions { include_symbols: false, min_symbols: 0, ..GeneratorOptions::default() };
+        for _ in 0..200 {
+            assert!(generator.generate_with_options(&options).unwrap().chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+    }
+    
+    #[test]
+    fn pronounceable_passwords_have_the_requested_shape() {
+        let generator = PasswordGenerator::new();
+        for (length, include_symbols) in [(4, true), (12, true), (12, false), (20, false)] {
+            for _ in 0..200 {
+                let password = generator.generate_pronounceable(length, include_symbols).unwrap();
+                let chars: Vec<char> = password.chars().collect();
+                let letters = length - 1 - usize::from(include_symbols);
+                assert_eq!(chars.len(), length, "{}", password);
+                assert!(chars[0].is_ascii_uppercase(), "{}", password);
+                assert!(chars[1..letters].iter().all(|c| c.is_ascii_lowercase()), "{}", password);
+                assert!(chars[letters].is_ascii_digit(), "{}", password);
+                assert_eq!(!chars[length - 1].is_ascii_alphanumeric(), include_symbols, "{}", password);
+            }
+        }
+        assert!(generator.generate_pronounceable(3, true).is_err());
+    }
+    
+    #[test]
+    fn entropy_follows_the_charset() {
+        let generator = PasswordGenerator::new();
+        let alphanumeric = GeneratorOptions { length: 20, include_symbols: false, min_symbols: 0, ..GeneratorOptions::default() };
+        assert_eq!(generator.charset_size(&alphanumeric), 62);
+        assert!((generator.entropy_bits(&alphanumeric) - 20.0 * 62f64.log2()).abs() < 1e-9);
+    
+        let unambiguous = GeneratorOptions { exclude_ambiguous: true, ..alphanumeric };
+        assert_eq!(generator.charset_size(&unambiguous), 56);
+        assert!(generator.charset_size(&GeneratorOptions::default()) > 62);
+    }
+    
+    #[test]
+    fn excluded_characters_never_appear() {
+        let generator = PasswordGenerator::new();
+        let options = GeneratorOptions { exclude_chars: "aeiouAEIOU0!@#".to_string(), ..GeneratorOptions::default() };
+        for _ in 0..1000 {
+            let password = generator.generate_with_options(&options).unwrap();
+            assert!(!password.chars().any(|c| options.exclude_chars.contains(c)), "{}", password);
+        }
+    }
+}
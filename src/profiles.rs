@@ -0,0 +1,70 @@
+// src/profiles.rs
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use directories::ProjectDirs;
+
+pub const DEFAULT_DATABASE_PATH: &str = "passwords.db";
+const PROFILE_EXTENSION: &str = "db";
+
+pub fn profiles_dir() -> Result<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "passrusted")
+        .ok_or_else(|| anyhow::anyhow!("Could not determine the config directory"))?;
+    Ok(dirs.config_dir().to_path_buf())
+}
+
+// The path used for a profile's database. The directory is created on first
+// use so `init` can write the file.
+pub fn profile_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        anyhow::bail!("Invalid profile name: {}", name);
+    }
+
+    let dir = profiles_dir()?;
+    create_private_dir(&dir)?;
+    Ok(dir.join(format!("{}.{}", name, PROFILE_EXTENSION)))
+}
+
+pub fn list_profiles() -> Result<Vec<String>> {
+    let dir = profiles_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut profiles: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == PROFILE_EXTENSION))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .collect();
+    profiles.sort();
+    Ok(profiles)
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &std::path::Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_names_cannot_leave_the_profiles_directory() {
+        for name in ["", ".hidden", "../work", "a/b", "a\\b"] {
+            assert!(profile_path(name).is_err(), "{:?}", name);
+        }
+    }
+}
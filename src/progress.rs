@@ -0,0 +1,72 @@
+// src/progress.rs
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::interrupt;
+
+// Quick derivations finish before anything is drawn.
+const SPINNER_DELAY: Duration = Duration::from_millis(500);
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+// Runs `work`, typically a key derivation, on a worker thread. With `show`
+// set, a spinner is drawn on stderr once it has taken longer than half a
+// second. Argon2 cannot be stopped part way, so Ctrl-C waits for the worker
+// and drops its result, zeroizing any key in it, before returning an error;
+// a second Ctrl-C ends the process at once.
+pub fn with_spinner<T: Send>(message: &str, show: bool, work: impl FnOnce() -> T + Send) -> Result<T> {
+    interrupt::begin_wait();
+    let started = Instant::now();
+    let result = thread::scope(|scope| {
+        let worker = scope.spawn(work);
+        let mut frame = 0;
+        let mut drawn = false;
+        let mut cancelling = false;
+        while !worker.is_finished() {
+            if interrupt::interrupted() {
+                if show && !cancelling {
+                    eprint!("\r{:width$}\rCancelling...", "", width = message.len() + 2);
+                    let _ = io::stderr().flush();
+                    drawn = true;
+                }
+                cancelling = true;
+            } else if show && started.elapsed() >= SPINNER_DELAY {
+                eprint!("\r{} {}", FRAMES[frame % FRAMES.len()], message);
+                let _ = io::stderr().flush();
+                frame += 1;
+                drawn = true;
+            }
+            thread::sleep(FRAME_INTERVAL);
+        }
+        if drawn {
+            eprint!("\r{}\r", " ".repeat(message.len().max(12) + 2));
+            let _ = io::stderr().flush();
+        }
+        worker.join().expect("key derivation thread panicked")
+    });
+    interrupt::end_wait();
+
+    if interrupt::interrupted() {
+        drop(result);
+        anyhow::bail!("Cancelled");
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn work_result_is_returned_when_not_interrupted() {
+        let value = with_spinner("Deriving key...", false, || {
+            thread::sleep(Duration::from_millis(150));
+            42
+        });
+        assert_eq!(value.unwrap(), 42);
+    }
+}
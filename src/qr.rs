@@ -0,0 +1,32 @@
+// src/qr.rs
+
+use anyhow::Result;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use terminal_size::{terminal_size, Width};
+use zeroize::Zeroizing;
+
+// Draws `data` with half-height block characters, two modules per line, in
+// light-on-dark so phone cameras read it from a dark terminal. Fails rather
+// than let a narrow terminal wrap the rows into something unscannable.
+pub fn render(data: &str) -> Result<Zeroizing<String>> {
+    let code = QrCode::new(data.as_bytes()).map_err(|e| anyhow::anyhow!("Could not encode QR code: {}", e))?;
+    let image = Zeroizing::new(
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build(),
+    );
+
+    let needed = image.lines().next().map_or(0, |line| line.chars().count());
+    if let Some((Width(columns), _)) = terminal_size() {
+        if (columns as usize) < needed {
+            anyhow::bail!(
+                "Terminal is {} columns wide but the QR code needs {}; widen the window or reduce the font size and try again",
+                columns,
+                needed
+            );
+        }
+    }
+    Ok(image)
+}
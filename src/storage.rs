@@ -1,61 +1,446 @@
 // src/storage.rs
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write, Seek, SeekFrom};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::cell::Cell;
+use std::time::{Instant, SystemTime};
 
-use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::crypto::{hash_master_password, verify_master_password, encrypt_data, decrypt_data, MasterKey};
-use crate::password_entry::PasswordEntry;
+use crate::crypto::{
+    derive_key, generate_recovery_key, generate_salt, hash_master_password, hash_uses_salt, normalize_recovery_key, unwrap_key, verify_master_password, wrap_key,
+    encrypt_data, decrypt_data, decrypt_legacy_data, KdfParams, MasterKey, DEFAULT_REKEY_AFTER, NONCE_LEN, TAG_LEN,
+};
+use crate::error::StoreError;
+use crate::event_log::{self, AccessRecord, AuditAction, AuditEvent, EventLog};
+use crate::password_entry::{CustomField, PasswordEntry};
+use crate::password_generator::{GeneratorOptions, PasswordGenerator};
+use crate::strength;
 
-#[derive(Serialize, Deserialize)]
+type Result<T, E = StoreError> = std::result::Result<T, E>;
+
+// Version 1 stored the header and entries with bincode. Version 2 switched
+// both to JSON so fields can be added with `#[serde(default)]`. Version 3
+// encrypts the entries with a subkey instead of the master key. Version 4
+// authenticates the header bytes as associated data of the entries. Version 5
+// stores a list of entries per service, one for each username. Version 6
+// encrypts the entries under a random data key, stored in the header wrapped
+// by the master password and optionally by a recovery key. Version 7 derives
+// the key that wraps it with a salt of its own; before, that key was the very
+// Argon2 output stored as the password hash.
+const CURRENT_VERSION: u32 = 7;
+const AUDIT_MIN_LENGTH: usize = 12;
+const AUDIT_MIN_CHAR_CLASSES: usize = 3;
+
+#[derive(Clone, Serialize, Deserialize)]
 struct DatabaseHeader {
     version: u32,
     master_hash: String,
     salt: Vec<u8>,
+    #[serde(default = "default_max_prev_files")]
+    max_prev_files: u32,
+    #[serde(default = "default_max_history")]
+    max_history: u32,
+    #[serde(default = "default_memory_kib")]
+    memory_kib: u32,
+    #[serde(default = "default_iterations")]
+    iterations: u32,
+    #[serde(default = "default_parallelism")]
+    parallelism: u32,
+    // Encryptions made under the current key, for the rekey warning.
+    #[serde(default)]
+    encryptions: u64,
+    #[serde(default = "default_rekey_after")]
+    rekey_after: u64,
+    #[serde(default)]
+    compression: Compression,
+    // Absent before version 6, when the entries were encrypted under the
+    // password-derived key itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wrapped_key: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recovery: Option<RecoveryHeader>,
+}
+
+// The data key wrapped a second time, by a key derived from the recovery key.
+#[derive(Clone, Serialize, Deserialize)]
+struct RecoveryHeader {
+    salt: Vec<u8>,
+    wrapped_key: Vec<u8>,
+}
+
+impl DatabaseHeader {
+    fn kdf_params(&self) -> KdfParams {
+        KdfParams {
+            memory_kib: self.memory_kib,
+            iterations: self.iterations,
+            parallelism: self.parallelism,
+        }
+    }
+    
+    // The key the entries are encrypted under, given the one derived from
+    // the master password. The password was already checked against the
+    // hash, so failing to unwrap means the header was modified.
+    fn data_key(&self, password_key: MasterKey) -> Result<MasterKey> {
+        match &self.wrapped_key {
+            Some(wrapped) => unwrap_key(wrapped, &password_key)
+                .map_err(|_| StoreError::Corrupt("Database header has been tampered with or corrupted".to_string())),
+            None => Ok(password_key),
+        }
+    }
+    
+    // Before version 7 the password hash and the wrapping key shared a salt,
+    // so anyone with the file could unwrap the data key from the hash alone.
+    fn key_exposed(&self) -> bool {
+        hash_uses_salt(&self.master_hash, &self.salt)
+    }
+}
+
+// Wraps `data_key` for a fresh recovery key, which is returned so it can be
+// shown to the user once.
+fn new_recovery(data_key: &MasterKey, kdf: &KdfParams) -> Result<(RecoveryHeader, Zeroizing<String>)> {
+    let recovery_key = generate_recovery_key();
+    let salt = generate_salt();
+    let kek = derive_key(&normalize_recovery_key(&recovery_key), &salt, kdf)?;
+    let wrapped_key = wrap_key(data_key, &kek)?;
+    Ok((RecoveryHeader { salt, wrapped_key }, recovery_key))
+}
+
+fn default_max_prev_files() -> u32 {
+    1
+}
+
+fn default_max_history() -> u32 {
+    10
+}
+
+// Vaults written before the KDF parameters were stored used the argon2
+// crate defaults.
+fn default_memory_kib() -> u32 {
+    KdfParams::default().memory_kib
+}
+
+fn default_iterations() -> u32 {
+    KdfParams::default().iterations
+}
+
+fn default_parallelism() -> u32 {
+    KdfParams::default().parallelism
+}
+
+fn default_rekey_after() -> u64 {
+    DEFAULT_REKEY_AFTER
+}
+
+#[derive(Deserialize)]
+struct DatabaseHeaderV1 {
+    version: u32,
+    master_hash: String,
+    salt: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct PasswordEntryV1 {
+    id: Uuid,
+    service: String,
+    username: String,
+    password: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<DatabaseHeaderV1> for DatabaseHeader {
+    fn from(old: DatabaseHeaderV1) -> Self {
+        DatabaseHeader {
+            version: old.version,
+            master_hash: old.master_hash,
+            salt: old.salt,
+            max_prev_files: default_max_prev_files(),
+            max_history: default_max_history(),
+            memory_kib: default_memory_kib(),
+            iterations: default_iterations(),
+            parallelism: default_parallelism(),
+            encryptions: 0,
+            rekey_after: default_rekey_after(),
+            compression: Compression::None,
+            wrapped_key: None,
+            recovery: None,
+        }
+    }
+}
+
+impl From<PasswordEntryV1> for PasswordEntry {
+    fn from(old: PasswordEntryV1) -> Self {
+        let mut entry = PasswordEntry::new(old.service, old.username, old.password);
+        entry.id = old.id;
+        entry.created_at = old.created_at;
+        entry.updated_at = old.updated_at;
+        entry
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    // Imported under a new service name, "name (imported)".
+    Rename,
+}
+
+// Applied to the serialized entries before they are encrypted. The file size
+// then depends on how well the entries compress, which leaks a little about
+// their contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zlib,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SortKey {
+    Service,
+    Updated,
+    Created,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub skipped: Vec<String>,
+    pub overwritten: Vec<String>,
+    // "old -> new" for each entry imported under a new name.
+    pub renamed: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    pub reused: Vec<Vec<String>>,
+    pub weak: Vec<WeakEntry>,
+}
+
+// What `migrate` did, with the new recovery key to show once.
+pub struct Migration {
+    pub from_version: u32,
+    pub recovery_key: Option<Zeroizing<String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub version: u32,
+    pub entries: usize,
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// Counts only; no password appears in it.
+#[derive(Debug, Default, Serialize)]
+pub struct VaultStats {
+    pub entries: usize,
+    pub unique_usernames: usize,
+    // Passwords shared by two or more entries, as `audit` counts them.
+    pub reused_passwords: usize,
+    pub old_days: u64,
+    pub older_than_days: usize,
+    pub average_length: f64,
+    pub oldest_created: Option<DateTime<Utc>>,
+    pub newest_created: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct WeakEntry {
+    pub service: String,
+    pub entropy_bits: f64,
+    pub issues: Vec<String>,
+}
+
+#[derive(Clone)]
+enum ImportAction {
+    Add,
+    Skip,
+    Overwrite,
+    Rename(String),
 }
 
 pub struct PasswordStore {
     file_path: String,
-    entries: HashMap<String, PasswordEntry>,
+    entries: HashMap<String, Vec<PasswordEntry>>,
     master_key: Option<MasterKey>,
     header: Option<DatabaseHeader>,
+    header_bytes: Vec<u8>,
+    encrypted_entries: Vec<u8>,
+    entries_file_mtime: Option<SystemTime>,
+    // Unlocking only derives the key. The entries are decrypted by
+    // `load_entries_cached`, or by the first change made to them, so a
+    // password check alone never pays for the whole vault.
+    entries_loaded: bool,
+    loaded_from_version: Option<u32>,
+    last_activity: Cell<Instant>,
+    lock_timeout: Option<std::time::Duration>,
+    // When the master password was last checked, and how long that lasts
+    // before the shell asks for it again however busy the session is.
+    last_auth: Option<Instant>,
+    reauth_interval: Option<std::time::Duration>,
+    // Held open for as long as this process may write the database; closing
+    // it releases the lock.
+    file_lock: Option<File>,
+    // The entries as they were when `begin_batch` was called, while a batch
+    // is open.
+    batch: Option<HashMap<String, Vec<PasswordEntry>>>,
+    // Events waiting for the batch to be committed.
+    pending_events: Vec<AuditEvent>,
+    // Changes are made in memory as usual but never saved.
+    dry_run: bool,
+    read_only: bool,
+    // Opened with `for_repair`: the file on disk is never read.
+    repairing: bool,
+    // Entries filed under a key other than their service fail to load
+    // instead of being moved.
+    strict: bool,
+    // What loading corrected, for the caller to report.
+    load_warnings: Vec<String>,
 }
 
 impl PasswordStore {
     pub fn new(file_path: &str) -> Result<Self> {
-        let mut store = Self {
-            file_path: file_path.to_string(),
-            entries: HashMap::new(),
-            master_key: None,
-            header: None,
+        let mut store = if Path::new(file_path).exists() {
+            let mut file = File::open(file_path)?;
+            Self::load_from_reader(&mut file)?
+        } else {
+            Self::empty()
         };
+        store.file_path = file_path.to_string();
+        
+        Ok(store)
+    }
+    
+    // A store for `file_path` that does not read it, so a database too
+    // damaged to open can still be replaced by `rollback_last_save` or
+    // `restore_from`.
+    pub fn for_repair(file_path: &str) -> Self {
+        Self {
+            file_path: file_path.to_string(),
+            repairing: true,
+            ..Self::empty()
+        }
+    }
+    
+    pub fn path(&self) -> &str {
+        &self.file_path
+    }
+    
+    // Takes an exclusive advisory lock so a second process cannot load the
+    // same entries and overwrite this one's changes. The lock is on a
+    // `.lock` file beside the database, since saving replaces the database
+    // file itself. The file is read again once the lock is held.
+    pub fn acquire_file_lock(&mut self) -> Result<()> {
+        if self.file_lock.is_some() {
+            return Ok(());
+        }
         
-        if Path::new(file_path).exists() {
-            store.load_header()?;
+        let lock_path = format!("{}.lock", self.file_path);
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|source| StoreError::LockFailed { path: lock_path.clone(), source })?;
+        match lock.try_lock_exclusive() {
+            Ok(()) => {},
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                return Err(StoreError::InUse(self.file_path.clone()));
+            },
+            Err(source) => return Err(StoreError::LockFailed { path: lock_path, source }),
         }
+        self.file_lock = Some(lock);
         
+        if Path::new(&self.file_path).exists() && !self.repairing {
+            let mut file = File::open(&self.file_path)?;
+            self.read_from(&mut file)?;
+        }
+        Ok(())
+    }
+    
+    // A store loaded this way is not tied to a file; use `save_to_writer` to
+    // persist it.
+    pub fn load_from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut store = Self::empty();
+        store.read_from(reader)?;
         Ok(store)
     }
     
+    fn empty() -> Self {
+        Self {
+            file_path: String::new(),
+            entries: HashMap::new(),
+            master_key: None,
+            header: None,
+            header_bytes: Vec::new(),
+            encrypted_entries: Vec::new(),
+            entries_file_mtime: None,
+            entries_loaded: false,
+            loaded_from_version: None,
+            last_activity: Cell::new(Instant::now()),
+            lock_timeout: None,
+            last_auth: None,
+            reauth_interval: None,
+            file_lock: None,
+            batch: None,
+            pending_events: Vec::new(),
+            dry_run: false,
+            read_only: false,
+            repairing: false,
+            strict: false,
+            load_warnings: Vec::new(),
+        }
+    }
+    
     pub fn is_initialized(&self) -> Result<bool> {
-        Ok(Path::new(&self.file_path).exists() && self.header.is_some())
+        Ok(self.header.is_some())
     }
     
-    pub fn initialize(&mut self, master_password: &str) -> Result<()> {
-        let (hash, salt) = hash_master_password(master_password)?;
+    pub fn initialize(&mut self, master_password: &str, max_prev_files: u32, max_history: u32, kdf: KdfParams, rekey_after: u64, compression: Compression) -> Result<()> {
+        self.assert_writable()?;
+        let hash = hash_master_password(master_password, &kdf)?;
+        let salt = generate_salt();
+        let data_key = MasterKey::generate();
+        let wrapped_key = wrap_key(&data_key, &derive_key(master_password, &salt, &kdf)?)?;
         
         let header = DatabaseHeader {
-            version: 1,
+            version: CURRENT_VERSION,
             master_hash: hash,
             salt,
+            max_prev_files: max_prev_files.max(1),
+            max_history,
+            memory_kib: kdf.memory_kib,
+            iterations: kdf.iterations,
+            parallelism: kdf.parallelism,
+            encryptions: 0,
+            rekey_after,
+            compression,
+            wrapped_key: Some(wrapped_key),
+            recovery: None,
         };
         
         self.header = Some(header);
-        self.master_key = Some(crate::crypto::derive_key(master_password, &self.header.as_ref().unwrap().salt)?);
+        self.master_key = Some(data_key);
+        self.entries_loaded = true;
+        self.last_activity.set(Instant::now());
+        self.last_auth = Some(Instant::now());
         self.save_to_file()?;
         
         Ok(())
@@ -63,119 +448,2663 @@ impl PasswordStore {
     
     pub fn verify_master_password(&mut self, password: &str) -> Result<bool> {
         let header = self.header.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Database not initialized"))?;
+            .ok_or(StoreError::NotInitialized)?;
         
-        if verify_master_password(password, &header.master_hash)? {
-            self.master_key = Some(crate::crypto::derive_key(password, &header.salt)?);
-            self.load_entries()?;
+        let kdf = header.kdf_params();
+        if verify_master_password(password, &header.master_hash, &kdf)? {
+            self.master_key = Some(header.data_key(derive_key(password, &header.salt, &kdf)?)?);
+            self.last_activity.set(Instant::now());
+            self.last_auth = Some(Instant::now());
             Ok(true)
         } else {
             Ok(false)
         }
     }
     
-    pub fn add_entry(&mut self, service: &str, username: &str, password: &str) -> Result<()> {
-        let entry = PasswordEntry::new(service.to_string(), username.to_string(), password.to_string());
-        self.entries.insert(service.to_string(), entry);
-        self.save_to_file()?;
-        Ok(())
+    // Checks the password against the header without deriving the key again
+    // or reloading anything: the key in memory is still the right one, since
+    // a password changed in this process updates both.
+    pub fn reauthenticate(&mut self, password: &str) -> Result<bool> {
+        let header = self.header.as_ref()
+            .ok_or(StoreError::NotInitialized)?;
+        
+        if verify_master_password(password, &header.master_hash, &header.kdf_params())? {
+            self.last_auth = Some(Instant::now());
+            self.last_activity.set(Instant::now());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
     }
     
-    pub fn get_entry(&self, service: &str) -> Result<Option<PasswordEntry>> {
-        Ok(self.entries.get(service).cloned())
+    // Only re-wraps the data key for the new password, so the entries and
+    // the recovery key are untouched. With `rekey`, or for a vault whose data
+    // key is missing or exposed by its header, the entries move to a new data
+    // key as in `reset_master_password`, and the new recovery key, if any, is
+    // returned.
+    pub fn change_master_password(&mut self, old_password: &str, new_password: &str, rekey: bool) -> Result<Option<Zeroizing<String>>> {
+        self.assert_writable()?;
+        let old_header = self.header.clone()
+            .ok_or(StoreError::NotInitialized)?;
+        
+        let kdf = old_header.kdf_params();
+        if !verify_master_password(old_password, &old_header.master_hash, &kdf)? {
+            return Err(StoreError::InvalidMasterPassword);
+        }
+        if self.master_key.is_none() {
+            self.master_key = Some(old_header.data_key(derive_key(old_password, &old_header.salt, &kdf)?)?);
+        }
+        if !self.entries_loaded {
+            self.load_entries()?;
+        }
+        if rekey || old_header.wrapped_key.is_none() || old_header.key_exposed() {
+            return self.reset_master_password(new_password);
+        }
+        
+        let hash = hash_master_password(new_password, &kdf)?;
+        let salt = generate_salt();
+        let wrapped_key = wrap_key(self.master_key.as_ref().unwrap(), &derive_key(new_password, &salt, &kdf)?)?;
+        self.header = Some(DatabaseHeader {
+            version: CURRENT_VERSION,
+            master_hash: hash,
+            salt,
+            wrapped_key: Some(wrapped_key),
+            ..old_header.clone()
+        });
+        
+        if let Err(e) = self.save_to_file() {
+            self.header = Some(old_header);
+            return Err(e);
+        }
+        Ok(None)
     }
     
-    pub fn list_entries(&self) -> Result<Vec<PasswordEntry>> {
-        Ok(self.entries.values().cloned().collect())
+    // Re-encrypts the unlocked entries under a new data key wrapped for
+    // `new_password`, which also starts a fresh encryption count. The old
+    // recovery key only unwraps the old data key, so a vault that had one
+    // gets a new one, returned so it can be shown once.
+    pub fn reset_master_password(&mut self, new_password: &str) -> Result<Option<Zeroizing<String>>> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let old_header = self.header.clone()
+            .ok_or(StoreError::NotInitialized)?;
+        
+        let kdf = old_header.kdf_params();
+        let hash = hash_master_password(new_password, &kdf)?;
+        let salt = generate_salt();
+        let data_key = MasterKey::generate();
+        let wrapped_key = wrap_key(&data_key, &derive_key(new_password, &salt, &kdf)?)?;
+        let (recovery, recovery_key) = match old_header.recovery {
+            Some(_) => {
+                let (recovery, recovery_key) = new_recovery(&data_key, &kdf)?;
+                (Some(recovery), Some(recovery_key))
+            },
+            None => (None, None),
+        };
+        let log = self.read_event_log()?;
+        
+        self.header = Some(DatabaseHeader {
+            version: CURRENT_VERSION,
+            master_hash: hash,
+            salt,
+            encryptions: 0,
+            wrapped_key: Some(wrapped_key),
+            recovery,
+            ..old_header.clone()
+        });
+        let old_key = self.master_key.replace(data_key);
+        
+        // Keep the in-memory state consistent with whichever file survives.
+        if let Err(e) = self.save_to_file() {
+            self.header = Some(old_header);
+            self.master_key = old_key;
+            return Err(e);
+        }
+        // The vault is already saved under the new key. Should this fail,
+        // the old events are left as records `log` reports as unreadable.
+        let _ = event_log::rewrite(&self.log_path(), &log.events, self.master_key.as_ref().unwrap());
+        
+        Ok(recovery_key)
     }
     
-    pub fn delete_entry(&mut self, service: &str) -> Result<()> {
-        self.entries.remove(service);
-        self.save_to_file()?;
-        Ok(())
+    // Adds a recovery key that unwraps the data key just as the master
+    // password does, replacing any earlier one.
+    pub fn create_recovery_key(&mut self) -> Result<Zeroizing<String>> {
+        self.assert_writable()?;
+        self.ensure_key()?;
+        let header = self.header.as_ref()
+            .ok_or(StoreError::NotInitialized)?;
+        if header.wrapped_key.is_none() {
+            return Err(StoreError::Invalid("Unlock the database once to upgrade it before adding a recovery key".to_string()));
+        }
+        
+        let (recovery, recovery_key) = new_recovery(self.master_key.as_ref().unwrap(), &header.kdf_params())?;
+        let previous = self.header.as_mut().unwrap().recovery.replace(recovery);
+        if let Err(e) = self.save_to_file() {
+            self.header.as_mut().unwrap().recovery = previous;
+            return Err(e);
+        }
+        Ok(recovery_key)
     }
     
-    pub fn update_password(&mut self, service: &str, new_password: &str) -> Result<()> {
-        if let Some(entry) = self.entries.get_mut(service) {
-            entry.update_password(new_password.to_string());
-            self.save_to_file()?;
-        }
-        Ok(())
+    pub fn has_recovery_key(&self) -> bool {
+        self.header.as_ref().is_some_and(|header| header.recovery.is_some())
     }
     
-    fn load_header(&mut self) -> Result<()> {
-        let mut file = File::open(&self.file_path)?;
-        let mut header_size_bytes = [0u8; 4];
-        file.read_exact(&mut header_size_bytes)?;
-        let header_size = u32::from_le_bytes(header_size_bytes);
-        
-        let mut header_bytes = vec![0u8; header_size as usize];
-        file.read_exact(&mut header_bytes)?;
-        
-        let header: DatabaseHeader = bincode::deserialize(&header_bytes)?;
-        self.header = Some(header);
+    // Unlocks with the recovery key instead of the master password. Returns
+    // false if the key is wrong.
+    pub fn unlock_with_recovery_key(&mut self, recovery_key: &str) -> Result<bool> {
+        let header = self.header.as_ref()
+            .ok_or(StoreError::NotInitialized)?;
+        let recovery = header.recovery.as_ref()
+            .ok_or(StoreError::NoRecoveryKey)?;
         
-        Ok(())
+        let kek = derive_key(&normalize_recovery_key(recovery_key), &recovery.salt, &header.kdf_params())?;
+        let data_key = match unwrap_key(&recovery.wrapped_key, &kek) {
+            Ok(data_key) => data_key,
+            Err(_) => return Ok(false),
+        };
+        self.master_key = Some(data_key);
+        self.last_activity.set(Instant::now());
+        self.last_auth = Some(Instant::now());
+        Ok(true)
     }
     
-    fn load_entries(&mut self) -> Result<()> {
-        if self.master_key.is_none() {
-            anyhow::bail!("Master key not available");
+    // Checks the file as it is on disk without ever writing to it: the entries
+    // must decrypt and parse, and each must agree with where it is stored.
+    // Only a wrong password or an unreadable file is an error; anything else
+    // wrong with the contents goes in the report.
+    pub fn verify(&mut self, password: &str) -> Result<VerifyReport> {
+        if !self.file_path.is_empty() {
+            let mut file = File::open(&self.file_path)?;
+            self.read_from(&mut file)?;
         }
+        let header = self.header.as_ref()
+            .ok_or(StoreError::NotInitialized)?;
         
-        let mut file = File::open(&self.file_path)?;
+        let kdf = header.kdf_params();
+        if !verify_master_password(password, &header.master_hash, &kdf)? {
+            return Err(StoreError::InvalidMasterPassword);
+        }
+        let mut report = VerifyReport { version: header.version, ..VerifyReport::default() };
+        self.master_key = Some(header.data_key(derive_key(password, &header.salt, &kdf)?)?);
+        self.last_activity.set(Instant::now());
+        // Whatever happens, the next cached load reads the file again.
+        self.entries_file_mtime = None;
         
-        // Skip header
-        let mut header_size_bytes = [0u8; 4];
-        file.read_exact(&mut header_size_bytes)?;
-        let header_size = u32::from_le_bytes(header_size_bytes);
-        file.seek(SeekFrom::Current(header_size as i64))?;
-        
-        let mut encrypted_data = Vec::new();
-        match file.read_to_end(&mut encrypted_data) {
-            Ok(0) => {
-                self.entries = HashMap::new();
-                return Ok(());
-            },
-            Ok(_) => {},
-            Err(e) => return Err(e.into()),
+        if let Err(e) = self.load_entries() {
+            self.entries.clear();
+            report.problems.push(format!("Entries could not be read: {}", e));
+            return Ok(report);
         }
+        // Misfiled entries are moved in memory, but the file still has them.
+        report.problems.extend(self.take_load_warnings());
         
-        if encrypted_data.is_empty() {
-            self.entries = HashMap::new();
-            return Ok(());
+        for (service, entries) in &self.entries {
+            if service.trim().is_empty() {
+                report.problems.push("An entry has an empty service name".to_string());
+            }
+            if entries.is_empty() {
+                report.problems.push(format!("{} has no entries", service));
+            }
+            let mut usernames = HashSet::new();
+            for entry in entries {
+                report.entries += 1;
+                if entry.service != *service {
+                    report.problems.push(format!("An entry stored under {} names its service {}", service, entry.service));
+                }
+                if entry.created_at > entry.updated_at {
+                    report.problems.push(format!("{} was updated before it was created", self.label(entry)));
+                }
+                if !usernames.insert(entry.username.as_str()) {
+                    report.problems.push(format!("{} has more than one entry for username {}", service, entry.username));
+                }
+            }
         }
+        report.problems.sort();
         
-        let key = self.master_key.as_ref().unwrap();
-        let decrypted_data = decrypt_data(&encrypted_data, key)?;
-        let entries: HashMap<String, PasswordEntry> = bincode::deserialize(&decrypted_data)?;
-        self.entries = entries;
+        Ok(report)
+    }
+    
+    // Writes the file back in the current format if it was unlocked from an
+    // older one. The original stays as `.prev`. Every older format exposes the
+    // key the entries are encrypted under, so they move to a new data key
+    // wrapped for `password`, and a vault with a recovery key gets a new one.
+    pub fn migrate(&mut self, password: &str) -> Result<Option<Migration>> {
+        if self.dry_run || self.read_only {
+            return Ok(None);
+        }
+        let from_version = match self.loaded_from_version {
+            Some(version) if version < CURRENT_VERSION => version,
+            _ => return Ok(None),
+        };
         
+        let recovery_key = self.reset_master_password(password)?;
+        self.loaded_from_version = Some(CURRENT_VERSION);
+        Ok(Some(Migration { from_version, recovery_key }))
+    }
+    
+    // The number of encryptions under the current key, once it has reached
+    // the vault's rekey threshold.
+    pub fn rekey_due(&self) -> Option<u64> {
+        self.header.as_ref()
+            .filter(|header| header.encryptions >= header.rekey_after)
+            .map(|header| header.encryptions)
+    }
+    
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+    
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+    
+    // Every method that changes the vault fails instead, except that reading
+    // a password no longer records the access.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+    
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    
+    // Corrections made while loading the entries since the last call. They
+    // are saved with the next change.
+    pub fn take_load_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.load_warnings)
+    }
+    
+    fn assert_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly(self.file_path.clone()));
+        }
         Ok(())
     }
     
-    fn save_to_file(&self) -> Result<()> {
-        let header = self.header.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Header not available"))?;
+    pub fn set_lock_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.lock_timeout = timeout;
+    }
+    
+    pub fn set_reauth_interval(&mut self, interval: Option<std::time::Duration>) {
+        self.reauth_interval = interval;
+    }
+    
+    // Unlike the lock timeout this counts from the last password check, not
+    // the last command, so it comes due even in constant use.
+    pub fn needs_reauth(&self) -> bool {
+        reauth_due(self.last_auth, self.reauth_interval, Instant::now())
+    }
+    
+    // Locked when no key has been derived yet or the store sat idle for
+    // longer than the lock timeout.
+    pub fn is_locked(&self) -> bool {
+        self.master_key.is_none()
+            || self.lock_timeout.is_some_and(|timeout| self.last_activity.get().elapsed() >= timeout)
+    }
+    
+    // Drops the derived key and the decrypted entries; both zeroize on drop.
+    pub fn lock(&mut self) {
+        self.master_key = None;
+        self.entries.clear();
+        self.entries_file_mtime = None;
+        self.entries_loaded = false;
+        self.batch = None;
+        self.pending_events.clear();
+    }
+    
+    // Until `commit_batch`, changes to entries stay in memory instead of each
+    // rewriting the file. Nothing is written if the batch is rolled back or
+    // the store is dropped first. Beginning a batch inside one does nothing.
+    pub fn begin_batch(&mut self) {
+        if self.batch.is_none() {
+            self.batch = Some(self.entries.clone());
+        }
+    }
+    
+    // Writes every change made since `begin_batch` at once. If the write
+    // fails, the entries go back to how they were when the batch began.
+    pub fn commit_batch(&mut self) -> Result<()> {
+        let previous = match self.batch.take() {
+            Some(previous) => previous,
+            None => return Ok(()),
+        };
+        if let Err(e) = self.save_to_file() {
+            self.entries = previous;
+            self.pending_events.clear();
+            return Err(e);
+        }
+        self.log_events(Vec::new())
+    }
+    
+    pub fn rollback_batch(&mut self) {
+        if let Some(previous) = self.batch.take() {
+            self.entries = previous;
+        }
+        self.pending_events.clear();
+    }
+    
+    // Appends to the encrypted event log beside the database. Inside a batch
+    // the event waits for `commit_batch` and is dropped by a rollback.
+    pub fn log_event(&mut self, event: AuditEvent) -> Result<()> {
+        self.log_events(vec![event])
+    }
+    
+    // Several changes to one entry in a batch make a single event.
+    fn log_events(&mut self, events: Vec<AuditEvent>) -> Result<()> {
+        if self.file_path.is_empty() || self.dry_run || self.read_only {
+            return Ok(());
+        }
+        for event in events {
+            if !self.pending_events.iter().any(|pending| pending.action == event.action && pending.service == event.service) {
+                self.pending_events.push(event);
+            }
+        }
+        if self.batch.is_some() || self.pending_events.is_empty() {
+            return Ok(());
+        }
+        
         let key = self.master_key.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Master key not available"))?;
+            .ok_or(StoreError::KeyUnavailable)?;
+        let events = std::mem::take(&mut self.pending_events);
+        Ok(event_log::append(&self.log_path(), &events, key)?)
+    }
+    
+    pub fn read_event_log(&self) -> Result<EventLog> {
+        self.ensure_key()?;
+        Ok(event_log::read(&self.log_path(), self.master_key.as_ref().unwrap())?)
+    }
+    
+    fn log_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.log", self.file_path))
+    }
+    
+    fn access_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.access", self.file_path))
+    }
+    
+    // Brings in the reads recorded since the last save.
+    fn apply_access_journal(&mut self) -> Result<()> {
+        if self.file_path.is_empty() {
+            return Ok(());
+        }
+        let records = event_log::read_access(&self.access_path(), self.master_key.as_ref().unwrap())?;
+        for record in records {
+            if let Some(entry) = self.entries.values_mut().flatten().find(|entry| entry.id == record.id) {
+                entry.last_accessed = entry.last_accessed.max(Some(record.accessed_at));
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn lock_if_idle(&mut self) -> bool {
+        let idle = self.master_key.is_some() && self.is_locked();
+        if idle {
+            self.lock();
+        }
+        idle
+    }
+    
+    fn ensure_key(&self) -> Result<()> {
+        if self.is_locked() {
+            return Err(StoreError::Locked);
+        }
+        self.last_activity.set(Instant::now());
+        Ok(())
+    }
+    
+    // For methods that only read entries, which cannot load them.
+    fn ensure_unlocked(&self) -> Result<()> {
+        self.ensure_key()?;
+        if !self.entries_loaded {
+            return Err(StoreError::NotLoaded);
+        }
+        Ok(())
+    }
+    
+    fn ensure_loaded(&mut self) -> Result<()> {
+        self.ensure_key()?;
+        if !self.entries_loaded {
+            self.load_entries_cached()?;
+        }
+        Ok(())
+    }
+    
+    // Replaces the entry with the same service and username, if any.
+    pub fn add_entry(&mut self, entry: PasswordEntry) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let event = AuditEvent::new(AuditAction::Added, &entry.service);
+        self.insert_entry(entry);
+        self.save_to_file()?;
+        self.log_event(event)
+    }
+    
+    pub fn previous_save(&self) -> Result<PasswordStore> {
+        let prev_path = self.backup_path(0);
+        if !Path::new(&prev_path).exists() {
+            return Err(StoreError::Invalid(format!("No previous save found at {}", prev_path)));
+        }
         
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&self.file_path)?;
+        let mut file = File::open(&prev_path)?;
+        let mut previous = Self::load_from_reader(&mut file)
+            .map_err(|e| StoreError::Corrupt(format!("Backup {} is not a valid database: {}", prev_path, e)))?;
+        previous.file_path = prev_path;
+        Ok(previous)
+    }
+    
+    pub fn rollback_last_save(&mut self) -> Result<()> {
+        self.assert_writable()?;
+        let previous = self.previous_save()?;
+        let max_prev_files = self.header.as_ref().map_or(1, |header| header.max_prev_files);
         
-        let header_bytes = bincode::serialize(header)?;
-        let header_size = header_bytes.len() as u32;
-        file.write_all(&header_size.to_le_bytes())?;
-        file.write_all(&header_bytes)?;
+        fs::rename(&previous.file_path, &self.file_path)?;
+        sync_parent_dir(Path::new(&self.file_path))?;
+        for index in 1..max_prev_files {
+            let older = self.backup_path(index);
+            if Path::new(&older).exists() {
+                fs::rename(&older, self.backup_path(index - 1))?;
+            }
+        }
+        
+        // The restored file may use a different master password, so the
+        // store is left locked until it is unlocked again.
+        self.header = previous.header;
+        self.header_bytes = previous.header_bytes;
+        self.encrypted_entries = previous.encrypted_entries;
+        self.lock();
+        
+        Ok(())
+    }
+    
+    // Copies the encrypted file as is to `<dir>/<name>-<timestamp>.db`; the
+    // default directory is the one holding the database.
+    pub fn backup_to(&self, dir: Option<&Path>) -> Result<std::path::PathBuf> {
+        let db_path = Path::new(&self.file_path);
+        if !db_path.exists() {
+            return Err(StoreError::Invalid(format!("No database at {} to back up", self.file_path)));
+        }
+        
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => db_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        fs::create_dir_all(&dir)?;
         
-        let entries_bytes = bincode::serialize(&self.entries)?;
-        let encrypted_data = encrypt_data(&entries_bytes, key)?;
-        file.write_all(&encrypted_data)?;
+        let name = db_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("passwords");
+        let target = dir.join(format!("{}-{}.db", name, Utc::now().format("%Y%m%d-%H%M%S")));
+        if target.exists() {
+            return Err(StoreError::Invalid(format!("Backup {} already exists", target.display())));
+        }
+        fs::copy(db_path, &target)?;
+        Ok(target)
+    }
+    
+    // Checks that `path` holds a database this build can read.
+    pub fn open_backup(path: &Path) -> Result<PasswordStore> {
+        let mut file = File::open(path)?;
+        let mut backup = Self::load_from_reader(&mut file)
+            .map_err(|e| StoreError::Corrupt(format!("{} is not a valid database: {}", path.display(), e)))?;
+        backup.file_path = path.to_string_lossy().into_owned();
+        Ok(backup)
+    }
+    
+    // Replaces the database with the backup. The current file is kept as
+    // `.prev` and the store is locked, as after a rollback.
+    pub fn restore_from(&mut self, backup: PasswordStore) -> Result<()> {
+        self.assert_writable()?;
+        let contents = fs::read(&backup.file_path)?;
+        self.replace_file(&contents)?;
         
-        file.sync_all()?;
+        self.header = backup.header;
+        self.header_bytes = backup.header_bytes;
+        self.encrypted_entries = backup.encrypted_entries;
+        self.lock();
         Ok(())
     }
-}
\ No newline at end of file
+    
+    // Wipes the entries and key from memory, then overwrites the database,
+    // its previous saves and its event log with zeros before unlinking them.
+    // Returns the files removed; none is not an error. On SSDs and
+    // copy-on-write file systems the old blocks may survive the overwrite.
+    pub fn purge(&mut self) -> Result<Vec<String>> {
+        self.assert_writable()?;
+        let paths = self.files();
+        self.lock();
+        self.header = None;
+        self.header_bytes.zeroize();
+        self.encrypted_entries.zeroize();
+        
+        let mut removed = Vec::new();
+        for path in paths {
+            if overwrite_and_remove(Path::new(&path))? {
+                removed.push(path);
+            }
+        }
+        sync_parent_dir(Path::new(&self.file_path))?;
+        Ok(removed)
+    }
+    
+    // The database and the files kept beside it that exist: a leftover
+    // temporary file, previous saves and the event log.
+    pub fn files(&self) -> Vec<String> {
+        let max_prev_files = self.header.as_ref().map_or(1, |header| header.max_prev_files);
+        let mut paths = vec![self.file_path.clone(), format!("{}.tmp", self.file_path)];
+        paths.extend((0..max_prev_files).map(|index| self.backup_path(index)));
+        paths.extend(event_log::paths(&self.log_path()).iter().map(|path| path.to_string_lossy().into_owned()));
+        paths.push(self.access_path().to_string_lossy().into_owned());
+        paths.retain(|path| Path::new(path).exists());
+        paths
+    }
+    
+    pub fn add_entry_checked(&mut self, entry: PasswordEntry) -> Result<()> {
+        entry.validate()?;
+        if self.contains_entry(&entry.service, &entry.username) {
+            return Err(already_exists(&entry.service, &entry.username));
+        }
+        self.add_entry(entry)
+    }
+    
+    // Adds each entry that is valid and not already present, saving once for
+    // all of them. The result for each entry says why it was left out.
+    pub fn add_entries(&mut self, entries: Vec<PasswordEntry>) -> Result<Vec<Result<()>>> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let mut results = Vec::with_capacity(entries.len());
+        let mut events = Vec::new();
+        for entry in entries {
+            let result = entry.validate().map_err(StoreError::from).and_then(|()| {
+                if self.contains_entry(&entry.service, &entry.username) {
+                    return Err(already_exists(&entry.service, &entry.username));
+                }
+                Ok(())
+            });
+            if result.is_ok() {
+                events.push(AuditEvent::new(AuditAction::Added, &entry.service));
+                self.insert_entry(entry);
+            }
+            results.push(result);
+        }
+        
+        if !events.is_empty() {
+            self.save_to_file()?;
+            self.log_events(events)?;
+        }
+        Ok(results)
+    }
+    
+    // `username` is only needed when the service has more than one entry.
+    // Without an exact match the name may differ in case or be an alias, as
+    // long as only one service answers to it.
+    pub fn get_entry(&self, service: &str, username: Option<&str>) -> Result<Option<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        let service = match self.matching_services(service)?.as_slice() {
+            [] => return Ok(None),
+            [found] => found.clone(),
+            found => return Err(StoreError::Ambiguous { name: service.to_string(), matches: found.to_vec() }),
+        };
+        Ok(self.position(&service, username)?.map(|index| self.entries[&service][index].clone()))
+    }
+    
+    // The service itself if it exists; otherwise every service whose name,
+    // ignoring case, or one of whose entries' aliases is `name`, sorted.
+    pub fn matching_services(&self, name: &str) -> Result<Vec<String>> {
+        self.ensure_unlocked()?;
+        if self.entries.contains_key(name) {
+            return Ok(vec![name.to_string()]);
+        }
+        let mut services: Vec<String> = self.entries.iter()
+            .filter(|(_, entries)| entries.iter().any(|entry| entry.answers_to(name)))
+            .map(|(service, _)| service.clone())
+            .collect();
+        services.sort();
+        Ok(services)
+    }
+    
+    // Records that the password was read. Only `get` calls this, so listing
+    // and auditing leave the timestamps alone. The time goes to the access
+    // journal rather than into a save, so a read does not take the place of
+    // the change `rollback` undoes.
+    pub fn mark_accessed(&mut self, service: &str, username: Option<&str>) -> Result<()> {
+        self.ensure_loaded()?;
+        if self.read_only {
+            return Ok(());
+        }
+        let entry = self.entry_mut(service, username)?;
+        let record = AccessRecord { id: entry.id, accessed_at: Utc::now() };
+        entry.last_accessed = Some(record.accessed_at);
+        if !self.file_path.is_empty() && !self.dry_run {
+            let key = self.master_key.as_ref()
+                .ok_or(StoreError::KeyUnavailable)?;
+            event_log::append_access(&self.access_path(), &record, key)?;
+        }
+        self.log_event(AuditEvent::new(AuditAction::Read, service))
+    }
+    
+    // Entries that have been read at least once, most recent first.
+    pub fn recently_accessed(&self, count: usize) -> Result<Vec<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        let mut entries: Vec<&PasswordEntry> = self.all_entries()
+            .filter(|entry| entry.last_accessed.is_some())
+            .collect();
+        entries.sort_by(|a, b| b.last_accessed.cmp(&a.last_accessed).then_with(|| a.service.cmp(&b.service)));
+        Ok(entries.into_iter().take(count).cloned().collect())
+    }
+    
+    pub fn usernames(&self, service: &str) -> Result<Vec<String>> {
+        self.ensure_unlocked()?;
+        Ok(self.entries.get(service)
+            .map(|entries| entries.iter().map(|entry| entry.username.clone()).collect())
+            .unwrap_or_default())
+    }
+    
+    pub fn list_entries(&self) -> Result<Vec<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        Ok(self.all_entries().cloned().collect())
+    }
+    
+    // Timestamps sort newest first; ties fall back to the service name and
+    // username so the order is stable between runs.
+    // Favorites come first whatever the sort key.
+    pub fn list_entries_sorted(&self, sort: SortKey, limit: Option<usize>, offset: usize, favorites_only: bool, tag: Option<&str>) -> Result<Vec<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        let mut entries: Vec<&PasswordEntry> = self.all_entries()
+            .filter(|entry| entry.favorite || !favorites_only)
+            .filter(|entry| tag.is_none_or(|tag| entry.has_tag(tag)))
+            .collect();
+        entries.sort_by(|a, b| {
+            let by_key = match sort {
+                SortKey::Service => std::cmp::Ordering::Equal,
+                SortKey::Updated => b.updated_at.cmp(&a.updated_at),
+                SortKey::Created => b.created_at.cmp(&a.created_at),
+            };
+            b.favorite.cmp(&a.favorite)
+                .then(by_key)
+                .then_with(|| a.service.cmp(&b.service))
+                .then_with(|| a.username.cmp(&b.username))
+        });
+        
+        Ok(entries.into_iter()
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+    
+    pub fn search(&self, query: &str, match_username: bool) -> Result<Vec<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        let query = query.to_lowercase();
+        let mut matches: Vec<PasswordEntry> = self.all_entries()
+            .filter(|entry| {
+                matches_query(&entry.service, &query)
+                    || (match_username && matches_query(&entry.username, &query))
+            })
+            .cloned()
+            .collect();
+        matches.sort_by(|a, b| a.service.cmp(&b.service).then_with(|| a.username.cmp(&b.username)));
+        Ok(matches)
+    }
+    
+    pub fn delete_entry(&mut self, service: &str, username: Option<&str>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        self.remove_entry(service, username)?;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Deleted, service))
+    }
+    
+    // Removes the entries with the given ids with a single save.
+    pub fn delete_entries(&mut self, ids: &[Uuid]) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let events: Vec<AuditEvent> = self.all_entries()
+            .filter(|entry| ids.contains(&entry.id))
+            .map(|entry| AuditEvent::new(AuditAction::Deleted, &entry.service))
+            .collect();
+        for entries in self.entries.values_mut() {
+            entries.retain(|entry| !ids.contains(&entry.id));
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+        self.save_to_file()?;
+        self.log_events(events)
+    }
+    
+    // The password keeps its age under the new name.
+    pub fn rename_entry(&mut self, old: &str, new: &str, username: Option<&str>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        if new.trim().is_empty() {
+            return Err(StoreError::Invalid("Service name must not be empty".to_string()));
+        }
+        let index = self.position(old, username)?
+            .ok_or_else(|| not_found(old, username))?;
+        let moving_username = self.entries[old][index].username.clone();
+        if self.contains_entry(new, &moving_username) {
+            return Err(already_exists(new, &moving_username));
+        }
+        let mut entry = self.remove_entry(old, Some(&moving_username))?
+            .ok_or_else(|| not_found(old, username))?;
+        
+        entry.service = new.to_string();
+        self.insert_entry(entry);
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Renamed, &format!("{} -> {}", old, new)))
+    }
+    
+    pub fn update_password(&mut self, service: &str, username: Option<&str>, new_password: &str) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let max_history = self.max_history();
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.update_password(new_password.to_string(), max_history);
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    // Editing the notes does not make the password any newer either.
+    pub fn set_notes(&mut self, service: &str, username: Option<&str>, notes: Option<String>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.notes.zeroize();
+        entry.notes = notes;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    pub fn set_custom_fields(&mut self, service: &str, username: Option<&str>, fields: Vec<CustomField>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        for field in fields {
+            entry.set_custom_field(field);
+        }
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    pub fn set_aliases(&mut self, service: &str, username: Option<&str>, aliases: Vec<String>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        if aliases.iter().any(|alias| alias.trim().is_empty()) {
+            return Err(StoreError::Invalid("Aliases must not be empty".to_string()));
+        }
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.aliases = aliases;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    // Leaves updated_at alone: it dates the password, and pinning an entry
+    // does not make its password any newer.
+    pub fn set_favorite(&mut self, service: &str, username: Option<&str>, value: bool) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.favorite = value;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    // Like a favorite, tags leave updated_at alone.
+    pub fn set_tags(&mut self, service: &str, username: Option<&str>, tags: Vec<String>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        if tags.iter().any(|tag| tag.trim().is_empty()) {
+            return Err(StoreError::Invalid("Tags must not be empty".to_string()));
+        }
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.tags = tags;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    // Like a favorite, the policy does not count as a change to the entry.
+    pub fn set_gen_policy(&mut self, service: &str, username: Option<&str>, policy: Option<GeneratorOptions>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.gen_policy = policy;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    pub fn set_totp_secret(&mut self, service: &str, username: Option<&str>, secret: Option<String>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.totp_secret.zeroize();
+        entry.totp_secret = secret;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    pub fn set_url(&mut self, service: &str, username: Option<&str>, url: Option<String>) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.url = url;
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    pub fn replace_password_no_history(&mut self, service: &str, username: Option<&str>, new_password: &str) -> Result<()> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let entry = self.entry_mut(service, username)?;
+        
+        entry.password.zeroize();
+        entry.password = new_password.to_string();
+        entry.updated_at = Utc::now();
+        self.save_to_file()?;
+        self.log_event(AuditEvent::new(AuditAction::Changed, service))
+    }
+    
+    pub fn import_entries(&mut self, entries: Vec<PasswordEntry>, on_conflict: ConflictPolicy) -> Result<ImportReport> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        let actions = self.plan_import(&entries, on_conflict);
+        let report = import_report(&entries, &actions);
+        
+        let mut events = Vec::new();
+        for (mut entry, action) in entries.into_iter().zip(actions) {
+            match action {
+                ImportAction::Add => {
+                    events.push(AuditEvent::new(AuditAction::Added, &entry.service));
+                    self.insert_entry(entry);
+                },
+                ImportAction::Overwrite => {
+                    events.push(AuditEvent::new(AuditAction::Changed, &entry.service));
+                    entry.updated_at = Utc::now();
+                    self.insert_entry(entry);
+                },
+                ImportAction::Rename(service) => {
+                    events.push(AuditEvent::new(AuditAction::Added, &service));
+                    entry.service = service;
+                    self.insert_entry(entry);
+                },
+                ImportAction::Skip => {},
+            }
+        }
+        self.save_to_file()?;
+        self.log_events(events)?;
+        
+        Ok(report)
+    }
+    
+    // Conflicts are judged against the loaded entries, so unlike the import
+    // itself this refuses to run before they are loaded.
+    pub fn import_entries_dry_run(&self, entries: Vec<PasswordEntry>, on_conflict: ConflictPolicy) -> Result<ImportReport> {
+        self.ensure_unlocked()?;
+        let actions = self.plan_import(&entries, on_conflict);
+        Ok(import_report(&entries, &actions))
+    }
+    
+    // Decides what happens to each incoming entry. Service and username pairs
+    // repeated within the import conflict with their earlier occurrence as
+    // well. Renamed entries get a service name not used by the vault or by
+    // anything else in the import.
+    fn plan_import(&self, entries: &[PasswordEntry], on_conflict: ConflictPolicy) -> Vec<ImportAction> {
+        let mut seen = HashSet::new();
+        let mut services: HashSet<String> = entries.iter().map(|entry| entry.service.clone()).collect();
+        entries.iter()
+            .map(|entry| {
+                let conflict = self.contains_entry(&entry.service, &entry.username)
+                    || !seen.insert((entry.service.as_str(), entry.username.as_str()));
+                match (conflict, on_conflict) {
+                    (false, _) => ImportAction::Add,
+                    (true, ConflictPolicy::Skip) => ImportAction::Skip,
+                    (true, ConflictPolicy::Overwrite) => ImportAction::Overwrite,
+                    (true, ConflictPolicy::Rename) => {
+                        let renamed = (1..)
+                            .map(|n| match n {
+                                1 => format!("{} (imported)", entry.service),
+                                n => format!("{} (imported {})", entry.service, n),
+                            })
+                            .find(|name| !self.entries.contains_key(name) && !services.contains(name))
+                            .unwrap();
+                        services.insert(renamed.clone());
+                        ImportAction::Rename(renamed)
+                    },
+                }
+            })
+            .collect()
+    }
+    
+    // Entries whose password was last changed more than `days` ago, oldest
+    // first.
+    pub fn entries_older_than(&self, days: u64) -> Result<Vec<PasswordEntry>> {
+        self.ensure_unlocked()?;
+        let now = Utc::now();
+        let mut entries: Vec<&PasswordEntry> = self.all_entries()
+            .filter(|entry| entry.age_days(now) > days)
+            .collect();
+        entries.sort_by(|a, b| a.updated_at.cmp(&b.updated_at).then_with(|| a.service.cmp(&b.service)));
+        Ok(entries.into_iter().cloned().collect())
+    }
+    
+    pub fn watch_for_expiry_and_notify(&self, days_warning: u64) -> Vec<String> {
+        let horizon = Duration::try_days(days_warning as i64)
+            .and_then(|window| Utc::now().checked_add_signed(window));
+        
+        let mut expiring: Vec<String> = self.all_entries()
+            .filter(|entry| match (entry.expires_at, horizon) {
+                (Some(expires_at), Some(horizon)) => expires_at <= horizon,
+                (Some(_), None) => true,
+                (None, _) => false,
+            })
+            .map(|entry| self.label(entry))
+            .collect();
+        expiring.sort();
+        expiring
+    }
+    
+    pub fn audit(&self) -> AuditReport {
+        // Group by borrowed passwords so no extra plaintext copies are made.
+        let mut by_password: HashMap<&str, Vec<String>> = HashMap::new();
+        for entry in self.all_entries() {
+            by_password.entry(entry.password.as_str()).or_default().push(self.label(entry));
+        }
+        
+        let mut reused: Vec<Vec<String>> = by_password.into_values()
+            .filter(|services| services.len() > 1)
+            .map(|mut services| {
+                services.sort();
+                services
+            })
+            .collect();
+        reused.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        
+        let mut weak: Vec<WeakEntry> = self.all_entries()
+            .filter_map(|entry| {
+                let issues = audit_issues(&entry.password);
+                if issues.is_empty() {
+                    return None;
+                }
+                Some(WeakEntry {
+                    service: self.label(entry),
+                    entropy_bits: strength::estimate_entropy(&entry.password),
+                    issues,
+                })
+            })
+            .collect();
+        weak.sort_by(|a, b| a.entropy_bits.total_cmp(&b.entropy_bits).then_with(|| a.service.cmp(&b.service)));
+        
+        AuditReport { reused, weak }
+    }
+    
+    // `old_days` is the age, from the last password change, past which an
+    // entry counts as old.
+    pub fn stats(&self, old_days: u64) -> Result<VaultStats> {
+        self.ensure_unlocked()?;
+        let now = Utc::now();
+        let entries: Vec<&PasswordEntry> = self.all_entries().collect();
+        let total_length: usize = entries.iter().map(|entry| entry.password.chars().count()).sum();
+        
+        Ok(VaultStats {
+            entries: entries.len(),
+            unique_usernames: entries.iter().map(|entry| entry.username.as_str()).collect::<HashSet<_>>().len(),
+            reused_passwords: self.audit().reused.len(),
+            old_days,
+            older_than_days: entries.iter().filter(|entry| entry.age_days(now) > old_days).count(),
+            average_length: if entries.is_empty() { 0.0 } else { total_length as f64 / entries.len() as f64 },
+            oldest_created: entries.iter().map(|entry| entry.created_at).min(),
+            newest_created: entries.iter().map(|entry| entry.created_at).max(),
+        })
+    }
+    
+    // Entries are keyed by exact service name and username, so accidental
+    // double-adds show up as near-identical names. Each group is ordered
+    // newest first.
+    pub fn duplicate_groups(&self) -> Result<Vec<Vec<PasswordEntry>>> {
+        self.ensure_unlocked()?;
+        let mut by_key: HashMap<(String, String), Vec<&PasswordEntry>> = HashMap::new();
+        for entry in self.all_entries() {
+            let key = (normalize_service(&entry.service), entry.username.trim().to_lowercase());
+            by_key.entry(key).or_default().push(entry);
+        }
+        
+        let mut groups: Vec<Vec<PasswordEntry>> = by_key.into_values()
+            .filter(|entries| entries.len() > 1)
+            .map(|mut entries| {
+                entries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| a.service.cmp(&b.service)));
+                entries.into_iter().cloned().collect()
+            })
+            .collect();
+        groups.sort_by(|a, b| a[0].service.cmp(&b[0].service));
+        Ok(groups)
+    }
+    
+    pub fn generate_and_rotate_multiple(&mut self, services: &[String], options: &GeneratorOptions) -> Result<HashMap<String, String>> {
+        self.assert_writable()?;
+        self.ensure_loaded()?;
+        for service in services {
+            self.position(service, None)?.ok_or_else(|| not_found(service, None))?;
+        }
+        // A dry run only checks that every service exists; no password is
+        // generated, so nothing in memory changes either.
+        if self.dry_run {
+            return Ok(HashMap::new());
+        }
+        
+        let generator = PasswordGenerator::new();
+        let mut taken: HashSet<String> = self.all_entries().map(|entry| entry.password.clone()).collect();
+        let mut rotated = HashMap::new();
+        
+        for service in services {
+            if rotated.contains_key(service) {
+                continue;
+            }
+            // An entry's remembered policy wins over the options given.
+            let index = self.position(service, None)?.ok_or_else(|| not_found(service, None))?;
+            let policy = self.entries[service][index].gen_policy.as_ref().unwrap_or(options);
+            let password = generator.generate_unique(policy, &taken)?;
+            taken.insert(password.clone());
+            rotated.insert(service.clone(), password);
+        }
+        
+        for mut password in taken.drain() {
+            password.zeroize();
+        }
+        
+        let max_history = self.max_history();
+        for (service, password) in &rotated {
+            self.entry_mut(service, None)?.update_password(password.clone(), max_history);
+        }
+        self.save_to_file()?;
+        self.log_events(rotated.keys().map(|service| AuditEvent::new(AuditAction::Changed, service)).collect())?;
+        
+        Ok(rotated)
+    }
+    
+    fn all_entries(&self) -> impl Iterator<Item = &PasswordEntry> {
+        self.entries.values().flatten()
+    }
+    
+    // The index of the entry for `service` among its usernames. Without a
+    // username this only succeeds when the service has a single entry.
+    fn position(&self, service: &str, username: Option<&str>) -> Result<Option<usize>> {
+        let entries = match self.entries.get(service) {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        match username {
+            Some(username) => Ok(entries.iter().position(|entry| entry.username == username)),
+            None if entries.len() > 1 => Err(StoreError::MultipleAccounts {
+                service: service.to_string(),
+                usernames: entries.iter().map(|entry| entry.username.clone()).collect(),
+            }),
+            None => Ok((!entries.is_empty()).then_some(0)),
+        }
+    }
+    
+    fn entry_mut(&mut self, service: &str, username: Option<&str>) -> Result<&mut PasswordEntry> {
+        let index = self.position(service, username)?
+            .ok_or_else(|| not_found(service, username))?;
+        Ok(&mut self.entries.get_mut(service).unwrap()[index])
+    }
+    
+    fn contains_entry(&self, service: &str, username: &str) -> bool {
+        self.entries.get(service).is_some_and(|entries| entries.iter().any(|entry| entry.username == username))
+    }
+    
+    fn insert_entry(&mut self, entry: PasswordEntry) {
+        let entries = self.entries.entry(entry.service.clone()).or_default();
+        match entries.iter_mut().find(|existing| existing.username == entry.username) {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+    }
+    
+    fn remove_entry(&mut self, service: &str, username: Option<&str>) -> Result<Option<PasswordEntry>> {
+        let index = match self.position(service, username)? {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let entries = self.entries.get_mut(service).unwrap();
+        let entry = entries.remove(index);
+        if entries.is_empty() {
+            self.entries.remove(service);
+        }
+        Ok(Some(entry))
+    }
+    
+    // The service name, with the username added when the service has
+    // several entries.
+    fn label(&self, entry: &PasswordEntry) -> String {
+        if self.entries.get(&entry.service).map_or(0, Vec::len) > 1 {
+            format!("{} ({})", entry.service, entry.username)
+        } else {
+            entry.service.clone()
+        }
+    }
+    
+    fn max_history(&self) -> usize {
+        self.header.as_ref().map_or(default_max_history(), |header| header.max_history) as usize
+    }
+    
+    // The sizes are checked against what was actually read before anything
+    // is allocated or parsed, so a cut-off file is reported as such.
+    fn read_from<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        self.entries_loaded = false;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let truncated = || StoreError::Truncated { size: data.len() as u64 };
+        
+        let (header_size, rest) = data.split_first_chunk::<4>().ok_or_else(truncated)?;
+        let header_size = u32::from_le_bytes(*header_size) as usize;
+        if header_size > rest.len() {
+            return Err(truncated());
+        }
+        let (header_bytes, encrypted_entries) = rest.split_at(header_size);
+        if !encrypted_entries.is_empty() && encrypted_entries.len() < NONCE_LEN + TAG_LEN {
+            return Err(truncated());
+        }
+        
+        // JSON headers always start with '{'; a version 1 bincode header starts
+        // with its little-endian version number instead.
+        let header: DatabaseHeader = if header_bytes.starts_with(b"{") {
+            serde_json::from_slice(header_bytes)
+                .map_err(|e| StoreError::Corrupt(format!("Database header is unreadable: {}", e)))?
+        } else {
+            let old: DatabaseHeaderV1 = bincode::deserialize(header_bytes)
+                .map_err(|e| StoreError::Corrupt(format!("Database header is unreadable: {}", e)))?;
+            old.into()
+        };
+        if header.version > CURRENT_VERSION {
+            return Err(StoreError::UnsupportedVersion { found: header.version, supported: CURRENT_VERSION });
+        }
+        self.loaded_from_version = Some(header.version);
+        self.header = Some(header);
+        self.header_bytes = header_bytes.to_vec();
+        self.encrypted_entries = encrypted_entries.to_vec();
+        
+        Ok(())
+    }
+    
+    // Skips decryption when the entries were already loaded from the file as
+    // it is now; re-reads it if another process has written since.
+    pub fn load_entries_cached(&mut self) -> Result<()> {
+        if self.file_path.is_empty() {
+            return self.load_entries();
+        }
+        
+        let modified = fs::metadata(&self.file_path)?.modified()?;
+        if self.entries_file_mtime == Some(modified) {
+            return Ok(());
+        }
+        
+        // Always decrypt what is on disk: the blob read at startup is stale
+        // once this store has saved, e.g. when unlocking again after a lock.
+        let mut file = File::open(&self.file_path)?;
+        self.read_from(&mut file)?;
+        self.load_entries()?;
+        self.entries_file_mtime = Some(modified);
+        Ok(())
+    }
+    
+    fn load_entries(&mut self) -> Result<()> {
+        if self.master_key.is_none() {
+            return Err(StoreError::KeyUnavailable);
+        }
+        
+        let header = self.header.as_mut()
+            .ok_or(StoreError::NotInitialized)?;
+        
+        self.entries = if self.encrypted_entries.is_empty() {
+            HashMap::new()
+        } else {
+            let key = self.master_key.as_ref().unwrap();
+            #[cfg(test)]
+            tests::count_decryption();
+            let decrypted_data = Zeroizing::new(match header.version {
+                0..=2 => decrypt_legacy_data(&self.encrypted_entries, key)?,
+                3 => decrypt_data(&self.encrypted_entries, key, &[])?,
+                // The password was already checked against the header hash,
+                // so a failure here means the file was modified.
+                _ => decrypt_data(&self.encrypted_entries, key, &self.header_bytes)
+                    .map_err(|_| StoreError::DecryptFailed)?,
+            });
+            let decrypted_data = decompress(decrypted_data, header.compression)?;
+            
+            match header.version {
+                0 | 1 => {
+                    let entries: HashMap<String, PasswordEntryV1> = bincode::deserialize(&decrypted_data)?;
+                    entries.into_iter().map(|(service, entry)| (service, vec![entry.into()])).collect()
+                },
+                2..=4 => {
+                    let entries: HashMap<String, PasswordEntry> = serde_json::from_slice(&decrypted_data)?;
+                    entries.into_iter().map(|(service, entry)| (service, vec![entry])).collect()
+                },
+                _ => serde_json::from_slice(&decrypted_data)?,
+            }
+        };
+        let entries = std::mem::take(&mut self.entries);
+        self.entries = reconcile_keys(entries, self.strict, &mut self.load_warnings)?;
+        // The entries are now held in the current format, so the next save
+        // upgrades the file.
+        header.version = CURRENT_VERSION;
+        self.apply_access_journal()?;
+        self.entries_loaded = true;
+        // A batch begun before the entries were loaded rolls back to them,
+        // not to the empty map it started with.
+        if self.batch.is_some() {
+            self.batch = Some(self.entries.clone());
+        }
+        
+        Ok(())
+    }
+    
+    pub fn save_to_writer<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        // Never write out an empty map in place of entries not yet decrypted.
+        if !self.entries_loaded {
+            self.load_entries()?;
+        }
+        let header = self.header.as_mut()
+            .ok_or(StoreError::NotInitialized)?;
+        header.encryptions += 1;
+        let key = self.master_key.as_ref()
+            .ok_or(StoreError::KeyUnavailable)?;
+        
+        let header_bytes = serde_json::to_vec(header)?;
+        let header_size = header_bytes.len() as u32;
+        writer.write_all(&header_size.to_le_bytes())?;
+        writer.write_all(&header_bytes)?;
+        
+        let entries_bytes = compress(Zeroizing::new(serde_json::to_vec(&self.entries)?), header.compression)?;
+        let encrypted_data = encrypt_data(&entries_bytes, key, &header_bytes)?;
+        writer.write_all(&encrypted_data)?;
+        
+        Ok(())
+    }
+    
+    // Writes the new contents next to the database and renames them into
+    // place, so a crash or failed write leaves the previous file intact.
+    // Inside a batch the write waits for `commit_batch`; in a dry run it
+    // never happens.
+    fn save_to_file(&mut self) -> Result<()> {
+        if self.batch.is_some() || self.dry_run {
+            return Ok(());
+        }
+        self.assert_writable()?;
+        let mut contents = Vec::new();
+        self.save_to_writer(&mut contents)?;
+        self.replace_file(&contents)?;
+        // The saved entries now carry the journaled reads.
+        let _ = fs::remove_file(self.access_path());
+        Ok(())
+    }
+    
+    fn replace_file(&self, contents: &[u8]) -> Result<()> {
+        let temp_path = format!("{}.tmp", self.file_path);
+        let result = (|| -> Result<()> {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&temp_path)?;
+            file.write_all(contents)?;
+            file.sync_all()?;
+            self.rotate_backups()?;
+            fs::rename(&temp_path, &self.file_path)?;
+            sync_parent_dir(Path::new(&self.file_path))
+        })();
+        
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+    
+    // Shifts `<db>.prev` to `<db>.prev.1` and so on, dropping the oldest,
+    // then copies the current database to `<db>.prev`.
+    fn rotate_backups(&self) -> Result<()> {
+        if !Path::new(&self.file_path).exists() {
+            return Ok(());
+        }
+        
+        let max_prev_files = self.header.as_ref().map_or(1, |header| header.max_prev_files);
+        for index in (1..max_prev_files).rev() {
+            let newer = self.backup_path(index - 1);
+            if Path::new(&newer).exists() {
+                fs::rename(&newer, self.backup_path(index))?;
+            }
+        }
+        fs::copy(&self.file_path, self.backup_path(0))?;
+        Ok(())
+    }
+    
+    fn backup_path(&self, index: u32) -> String {
+        match index {
+            0 => format!("{}.prev", self.file_path),
+            _ => format!("{}.prev.{}", self.file_path, index),
+        }
+    }
+}
+
+// Makes the rename durable; directories cannot be opened this way on Windows.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+// False if there was no file to remove.
+fn overwrite_and_remove(path: &Path) -> Result<bool> {
+    let mut file = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(StoreError::Invalid(format!("Could not open {}: {}", path.display(), e))),
+    };
+    let length = file.metadata()?.len() as usize;
+    file.write_all(&vec![0u8; length])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)?;
+    Ok(true)
+}
+
+fn compress(data: Zeroizing<Vec<u8>>, compression: Compression) -> Result<Zeroizing<Vec<u8>>> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&data)?;
+            Ok(Zeroizing::new(encoder.finish()?))
+        },
+    }
+}
+
+fn decompress(data: Zeroizing<Vec<u8>>, compression: Compression) -> Result<Zeroizing<Vec<u8>>> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Zlib => {
+            let mut decompressed = Zeroizing::new(Vec::new());
+            ZlibDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        },
+    }
+}
+
+fn import_report(entries: &[PasswordEntry], actions: &[ImportAction]) -> ImportReport {
+    let mut report = ImportReport::default();
+    for (entry, action) in entries.iter().zip(actions) {
+        let bucket = match action {
+            ImportAction::Add => &mut report.added,
+            ImportAction::Skip => &mut report.skipped,
+            ImportAction::Overwrite => &mut report.overwritten,
+            ImportAction::Rename(service) => {
+                report.renamed.push(format!("{} -> {}", entry.service, service));
+                continue;
+            },
+        };
+        bucket.push(entry.service.clone());
+    }
+    report
+}
+
+// Files each entry under its own service name. An entry stored under another
+// key, as a hand-edited or badly imported vault can have, is moved to its
+// service, or fails the load in strict mode. One with a blank service takes
+// the key as its service instead, and one whose service already holds its
+// username is left where it is so neither is lost.
+fn reconcile_keys(entries: HashMap<String, Vec<PasswordEntry>>, strict: bool, warnings: &mut Vec<String>) -> Result<HashMap<String, Vec<PasswordEntry>>> {
+    let mut misplaced = Vec::new();
+    let mut reconciled: HashMap<String, Vec<PasswordEntry>> = HashMap::new();
+    for (key, list) in entries {
+        for mut entry in list {
+            if entry.service == key {
+                reconciled.entry(key.clone()).or_default().push(entry);
+            } else if strict {
+                return Err(StoreError::ServiceMismatch { key, service: entry.service.clone() });
+            } else if entry.service.trim().is_empty() {
+                warnings.push(format!("An entry stored under {} had no service name; it now has that name", key));
+                entry.service = key.clone();
+                reconciled.entry(key.clone()).or_default().push(entry);
+            } else {
+                misplaced.push((key.clone(), entry));
+            }
+        }
+    }
+    
+    // Moved once everything correctly filed is in place, so a correctly
+    // filed entry always keeps its spot.
+    for (key, entry) in misplaced {
+        let taken = reconciled.get(&entry.service)
+            .is_some_and(|list| list.iter().any(|existing| existing.username == entry.username));
+        if taken {
+            warnings.push(format!(
+                "An entry stored under {} names its service {}, which already has an entry for {}; it was left under {}",
+                key, entry.service, entry.username, key
+            ));
+            reconciled.entry(key).or_default().push(entry);
+        } else {
+            warnings.push(format!("An entry stored under {} names its service {}; it was moved there", key, entry.service));
+            reconciled.entry(entry.service.clone()).or_default().push(entry);
+        }
+    }
+    Ok(reconciled)
+}
+
+fn not_found(service: &str, username: Option<&str>) -> StoreError {
+    StoreError::NotFound { service: service.to_string(), username: username.map(str::to_string) }
+}
+
+fn already_exists(service: &str, username: &str) -> StoreError {
+    StoreError::AlreadyExists { service: service.to_string(), username: username.to_string() }
+}
+
+// "WWW.GitHub ", "github" and "git-hub" all normalize to "github".
+fn normalize_service(service: &str) -> String {
+    let lower = service.trim().to_lowercase();
+    let lower = lower.strip_prefix("www.").unwrap_or(&lower);
+    lower.chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+// Case-insensitive substring match, falling back to a fuzzy match where the
+// query's characters appear in order (e.g. "gthb" matches "GitHub").
+fn matches_query(value: &str, query: &str) -> bool {
+    let value = value.to_lowercase();
+    if value.contains(query) {
+        return true;
+    }
+    
+    let mut chars = value.chars();
+    query.chars().all(|wanted| chars.any(|c| c == wanted))
+}
+
+// Why `audit` flags a password: too short, too few character classes, or a
+// common pattern. An empty list means it passed.
+fn audit_issues(password: &str) -> Vec<String> {
+    let mut issues = Vec::new();
+    
+    let length = password.chars().count();
+    if length < AUDIT_MIN_LENGTH {
+        issues.push(format!("only {} characters", length));
+    }
+    
+    let classes = [
+        password.chars().any(|c| c.is_ascii_lowercase()),
+        password.chars().any(|c| c.is_ascii_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_ascii_alphanumeric()),
+    ].iter().filter(|present| **present).count();
+    if classes < AUDIT_MIN_CHAR_CLASSES {
+        issues.push(format!("only {} character class(es)", classes));
+    }
+    
+    if strength::is_compromised_by_pattern(password) {
+        issues.push("common pattern".to_string());
+    }
+    issues
+}
+
+// Whether the master password must be asked for again at `now`. Taking the
+// time as an argument keeps the decision apart from the clock.
+fn reauth_due(last_auth: Option<Instant>, interval: Option<std::time::Duration>, now: Instant) -> bool {
+    match (last_auth, interval) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(last_auth), Some(interval)) => now.saturating_duration_since(last_auth) >= interval,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CryptoError;
+    use argon2::password_hash::PasswordHash;
+    use std::path::PathBuf;
+    
+    const PASSWORD: &str = "correct horse battery staple";
+    // Cheap Argon2 settings so each test derives its key quickly.
+    const FAST_KDF: KdfParams = KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 };
+    
+    // A vault in a directory of its own, removed when dropped.
+    struct TempVault {
+        dir: PathBuf,
+    }
+    
+    impl TempVault {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("passrusted-test-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+        
+        fn path(&self) -> String {
+            self.dir.join("passwords.db").to_string_lossy().into_owned()
+        }
+        
+        fn init(&self) -> PasswordStore {
+            let mut store = PasswordStore::new(&self.path()).unwrap();
+            store.initialize(PASSWORD, 1, 10, FAST_KDF, DEFAULT_REKEY_AFTER, Compression::None).unwrap();
+            store
+        }
+        
+        fn open(&self) -> PasswordStore {
+            let mut store = PasswordStore::new(&self.path()).unwrap();
+            assert!(store.verify_master_password(PASSWORD).unwrap());
+            store
+        }
+    }
+    
+    impl Drop for TempVault {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+    
+    thread_local! {
+        static DECRYPTIONS: Cell<usize> = const { Cell::new(0) };
+    }
+    
+    // Called by load_entries, so tests can tell when decryption was skipped.
+    pub(super) fn count_decryption() {
+        DECRYPTIONS.with(|count| count.set(count.get() + 1));
+    }
+    
+    fn decryptions() -> usize {
+        DECRYPTIONS.with(Cell::get)
+    }
+    
+    fn entry(service: &str, username: &str, password: &str) -> PasswordEntry {
+        PasswordEntry::new(service.to_string(), username.to_string(), password.to_string())
+    }
+    
+    #[test]
+    fn expiring_entries_are_listed() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        let mut soon = entry("soon", "alice", "hunter2");
+        soon.expires_at = Some(Utc::now() + Duration::days(3));
+        let mut later = entry("later", "alice", "hunter2");
+        later.expires_at = Some(Utc::now() + Duration::days(60));
+        store.add_entry(soon).unwrap();
+        store.add_entry(later).unwrap();
+        store.add_entry(entry("never", "alice", "hunter2")).unwrap();
+        
+        assert_eq!(store.watch_for_expiry_and_notify(14), ["soon"]);
+        assert_eq!(store.watch_for_expiry_and_notify(90), ["later", "soon"]);
+    }
+    
+    #[test]
+    fn queries_match_substrings_and_in_order_letters() {
+        assert!(matches_query("GitHub", "hub"));
+        assert!(matches_query("GitHub", "gthb"));
+        assert!(!matches_query("GitHub", "bhg"));
+        assert!(!matches_query("GitLab", "hub"));
+        assert!(matches_query("anything", ""));
+    }
+    
+    #[test]
+    fn saved_store_loads_back_from_memory() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        let mut github = entry("github", "alice", "hunter2");
+        github.expires_at = Some(Utc::now() + Duration::days(30));
+        store.add_entry(github).unwrap();
+    
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        store.save_to_writer(&mut buffer).unwrap();
+        buffer.set_position(0);
+        let mut loaded = PasswordStore::load_from_reader(&mut buffer).unwrap();
+        assert!(loaded.verify_master_password(PASSWORD).unwrap());
+        loaded.load_entries().unwrap();
+    
+        let github = loaded.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(github.username, "alice");
+        assert_eq!(github.password, "hunter2");
+        assert!(github.expires_at.is_some());
+        assert!(!loaded.verify_master_password("wrong").unwrap());
+    }
+    
+    #[test]
+    fn changing_the_master_password_keeps_the_entries() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        assert!(matches!(store.change_master_password("wrong", "new passphrase", false), Err(StoreError::InvalidMasterPassword)));
+        store.change_master_password(PASSWORD, "new passphrase", false).unwrap();
+    
+        let mut reopened = PasswordStore::new(&vault.path()).unwrap();
+        assert!(!reopened.verify_master_password(PASSWORD).unwrap());
+        assert!(reopened.verify_master_password("new passphrase").unwrap());
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    }
+    
+    #[test]
+    fn replacing_without_history_overwrites_the_password() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        store.replace_password_no_history("github", None, "new-password").unwrap();
+        let entry = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(entry.password, "new-password");
+        assert!(entry.history.is_empty());
+        assert!(matches!(store.replace_password_no_history("gitlab", None, "x"), Err(StoreError::NotFound { .. })));
+    }
+    
+    #[test]
+    fn import_preview_matches_the_import() {
+        let incoming = vec![entry("github", "alice", "other"), entry("gitlab", "alice", "x"), entry("gitlab", "alice", "y")];
+    
+        for policy in [ConflictPolicy::Skip, ConflictPolicy::Overwrite, ConflictPolicy::Rename] {
+            let vault = TempVault::new();
+            let mut store = vault.init();
+            store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+            let preview = store.import_entries_dry_run(incoming.clone(), policy).unwrap();
+            assert_eq!(store.import_entries(incoming.clone(), policy).unwrap(), preview);
+        }
+    
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let report = store.import_entries_dry_run(incoming.clone(), ConflictPolicy::Skip).unwrap();
+        assert_eq!(report.added, ["gitlab"]);
+        assert_eq!(report.skipped, ["github", "gitlab"]);
+        assert_eq!(store.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    
+        let report = store.import_entries_dry_run(incoming, ConflictPolicy::Rename).unwrap();
+        assert_eq!(report.added, ["gitlab"]);
+        assert_eq!(report.renamed, ["github -> github (imported)", "gitlab -> gitlab (imported)"]);
+    }
+    
+    #[test]
+    fn import_preview_needs_the_entries_loaded() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        let reopened = vault.open();
+        let incoming = vec![entry("github", "alice", "other")];
+        assert!(matches!(reopened.import_entries_dry_run(incoming, ConflictPolicy::Skip), Err(StoreError::NotLoaded)));
+    }
+    
+    #[test]
+    fn failed_save_leaves_the_previous_file() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let before = fs::read(vault.path()).unwrap();
+    
+        // A directory where the temporary file should go makes the write fail.
+        fs::create_dir(format!("{}.tmp", vault.path())).unwrap();
+        assert!(matches!(store.add_entry(entry("gitlab", "alice", "hunter3")), Err(StoreError::Io(_))));
+        assert_eq!(fs::read(vault.path()).unwrap(), before);
+    }
+    
+    #[test]
+    fn rollback_restores_the_previous_save() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.delete_entry("github", None).unwrap();
+    
+        store.rollback_last_save().unwrap();
+        assert!(store.verify_master_password(PASSWORD).unwrap());
+        store.load_entries_cached().unwrap();
+        assert_eq!(store.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    }
+    
+    #[test]
+    fn unchanged_file_is_decrypted_only_once() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        let mut reader = PasswordStore::new(&vault.path()).unwrap();
+        let start = decryptions();
+        assert!(reader.verify_master_password(PASSWORD).unwrap());
+        reader.load_entries_cached().unwrap();
+        reader.load_entries_cached().unwrap();
+        assert_eq!(decryptions() - start, 1);
+    
+        // Another writer changes the file. The mtime is moved on explicitly, as
+        // two writes can land within the filesystem's timestamp granularity.
+        store.add_entry(entry("gitlab", "alice", "hunter3")).unwrap();
+        let file = File::options().write(true).open(vault.path()).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10)).unwrap();
+        reader.load_entries_cached().unwrap();
+        assert_eq!(decryptions() - start, 2);
+        assert!(reader.get_entry("gitlab", None).unwrap().is_some());
+    }
+    
+    #[test]
+    fn audit_groups_a_password_shared_by_three_services() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        for service in ["github", "gitlab", "bitbucket"] {
+            store.add_entry(entry(service, "alice", "Shared#Passw0rd!x")).unwrap();
+        }
+        store.add_entry(entry("email", "alice", "abc")).unwrap();
+    
+        let report = store.audit();
+        assert_eq!(report.reused, [["bitbucket", "github", "gitlab"]]);
+        assert_eq!(report.weak.len(), 1);
+        assert_eq!(report.weak[0].service, "email");
+        assert!(audit_issues("Vq7#tLm2!xRb9&Kp").is_empty());
+    }
+    
+    #[test]
+    fn lock_drops_the_key_and_entries() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        assert!(!store.is_locked());
+    
+        store.lock();
+        assert!(store.is_locked());
+        assert!(store.entries.is_empty());
+        assert!(matches!(store.get_entry("github", None), Err(StoreError::Locked)));
+    
+        assert!(store.verify_master_password(PASSWORD).unwrap());
+        store.load_entries_cached().unwrap();
+        assert_eq!(store.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    }
+    
+    #[test]
+    fn idle_store_locks_after_the_timeout() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.set_lock_timeout(Some(std::time::Duration::from_secs(3600)));
+        assert!(!store.lock_if_idle());
+    
+        store.set_lock_timeout(Some(std::time::Duration::ZERO));
+        assert!(store.is_locked());
+        assert!(store.lock_if_idle());
+        assert!(store.master_key.is_none());
+    }
+    
+    #[test]
+    fn rename_keeps_the_entry_and_refuses_taken_names() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.add_entry(entry("gitlab", "alice", "x")).unwrap();
+        let original = store.get_entry("github", None).unwrap().unwrap();
+    
+        assert!(matches!(store.rename_entry("github", "gitlab", None), Err(StoreError::AlreadyExists { .. })));
+        assert!(store.rename_entry("github", " ", None).is_err());
+        assert!(matches!(store.rename_entry("bitbucket", "codeberg", None), Err(StoreError::NotFound { .. })));
+    
+        store.rename_entry("github", "github-work", None).unwrap();
+        assert!(store.get_entry("github", None).unwrap().is_none());
+        let renamed = store.get_entry("github-work", None).unwrap().unwrap();
+        assert_eq!(renamed.id, original.id);
+        assert_eq!(renamed.service, "github-work");
+        assert_eq!(renamed.updated_at, original.updated_at);
+    }
+    
+    #[test]
+    fn custom_kdf_params_unlock_and_mismatched_ones_fail() {
+        let vault = TempVault::new();
+        let kdf = KdfParams { memory_kib: 16, iterations: 2, parallelism: 1 };
+        let mut store = PasswordStore::new(&vault.path()).unwrap();
+        store.initialize(PASSWORD, 1, 10, kdf, DEFAULT_REKEY_AFTER, Compression::None).unwrap();
+    
+        let mut reopened = vault.open();
+        assert_eq!(reopened.header.as_ref().unwrap().kdf_params(), kdf);
+    
+        reopened.header.as_mut().unwrap().iterations = 3;
+        assert!(matches!(reopened.verify_master_password(PASSWORD), Err(StoreError::Crypto(CryptoError::KdfMismatch))));
+    }
+    
+    #[test]
+    fn updating_a_missing_service_is_an_error() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        store.update_password("github", None, "new-password").unwrap();
+        let github = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(github.password, "new-password");
+        assert_eq!(github.history[0].password, "hunter2");
+    
+        assert!(matches!(store.update_password("gitlab", None, "x"), Err(StoreError::NotFound { .. })));
+        assert!(store.get_entry("gitlab", None).unwrap().is_none());
+    }
+    
+    #[test]
+    fn listing_is_stable_and_paginated() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        for service in ["b", "d", "c", "a"] {
+            store.add_entry(entry(service, "alice", "hunter2")).unwrap();
+        }
+    
+        let names = |entries: Vec<PasswordEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.service.clone()).collect()
+        };
+        let first = names(store.list_entries_sorted(SortKey::Service, None, 0, false, None).unwrap());
+        assert_eq!(first, ["a", "b", "c", "d"]);
+        for _ in 0..5 {
+            assert_eq!(names(store.list_entries_sorted(SortKey::Service, None, 0, false, None).unwrap()), first);
+        }
+        assert_eq!(names(store.list_entries_sorted(SortKey::Service, Some(2), 1, false, None).unwrap()), ["b", "c"]);
+        assert_eq!(names(store.list_entries_sorted(SortKey::Created, Some(1), 0, false, None).unwrap()), ["a"]);
+    }
+    
+    #[test]
+    fn backup_restores_over_later_changes() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        let backup_dir = vault.dir.join("backups");
+        let backup_path = store.backup_to(Some(&backup_dir)).unwrap();
+        assert!(backup_path.starts_with(&backup_dir));
+        store.delete_entry("github", None).unwrap();
+    
+        let backup = PasswordStore::open_backup(&backup_path).unwrap();
+        store.restore_from(backup).unwrap();
+        assert!(store.is_locked());
+    
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+        assert!(PasswordStore::open_backup(&vault.dir.join("missing.db")).is_err());
+    }
+    
+    // Writes a vault the way version 1 did: a bincode header and bincode
+    // entries, encrypted under the password-derived key with no subkey.
+    fn write_v1_vault(path: &str) {
+        #[derive(Serialize)]
+        struct HeaderV1 {
+            version: u32,
+            master_hash: String,
+            salt: Vec<u8>,
+        }
+    
+        #[derive(Serialize)]
+        struct EntryV1 {
+            id: Uuid,
+            service: String,
+            username: String,
+            password: String,
+            created_at: DateTime<Utc>,
+            updated_at: DateTime<Utc>,
+        }
+    
+        use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+        use argon2::password_hash::{PasswordHasher, SaltString};
+    
+        let salt = generate_salt();
+        let master_hash = argon2::Argon2::default()
+            .hash_password(PASSWORD.as_bytes(), &SaltString::encode_b64(&salt).unwrap())
+            .unwrap()
+            .to_string();
+        let key = derive_key(PASSWORD, &salt, &KdfParams::default()).unwrap();
+    
+        let now = Utc::now();
+        let entries: HashMap<String, EntryV1> = HashMap::from([(
+            "github".to_string(),
+            EntryV1 { id: Uuid::new_v4(), service: "github".to_string(), username: "alice".to_string(), password: "hunter2".to_string(), created_at: now, updated_at: now },
+        )]);
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(key.as_bytes()).unwrap();
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, bincode::serialize(&entries).unwrap().as_slice()).unwrap();
+    
+        let header = bincode::serialize(&HeaderV1 { version: 1, master_hash, salt }).unwrap();
+        let mut contents = (header.len() as u32).to_le_bytes().to_vec();
+        contents.extend_from_slice(&header);
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&ciphertext);
+        fs::write(path, contents).unwrap();
+    }
+    
+    #[test]
+    fn version_1_vault_is_migrated_to_the_current_format() {
+        let vault = TempVault::new();
+        write_v1_vault(&vault.path());
+    
+        let mut old = vault.open();
+        assert_eq!(old.loaded_from_version, Some(1));
+        old.load_entries().unwrap();
+        assert_eq!(old.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    
+        let migration = old.migrate(PASSWORD).unwrap().unwrap();
+        assert_eq!(migration.from_version, 1);
+        assert!(Path::new(&format!("{}.prev", vault.path())).exists());
+    
+        let mut upgraded = vault.open();
+        assert_eq!(upgraded.loaded_from_version, Some(CURRENT_VERSION));
+        assert!(upgraded.header.as_ref().unwrap().wrapped_key.is_some());
+        upgraded.load_entries().unwrap();
+        let github = upgraded.get_entry("github", None).unwrap().unwrap();
+        assert_eq!((github.username.as_str(), github.password.as_str()), ("alice", "hunter2"));
+    }
+    
+    #[test]
+    fn newer_versions_are_refused() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.header.as_mut().unwrap().version = CURRENT_VERSION + 1;
+        let mut contents = Vec::new();
+        store.save_to_writer(&mut contents).unwrap();
+        fs::write(vault.path(), contents).unwrap();
+    
+        assert!(matches!(PasswordStore::new(&vault.path()), Err(StoreError::UnsupportedVersion { found, .. }) if found == CURRENT_VERSION + 1));
+    }
+    
+    #[test]
+    fn tampered_header_fails_to_decrypt() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        let contents = fs::read(vault.path()).unwrap();
+        let needle = b"\"max_history\":10";
+        let at = contents.windows(needle.len()).position(|window| window == needle).unwrap();
+        let mut tampered = contents.clone();
+        tampered[at + needle.len() - 1] = b'1';
+        fs::write(vault.path(), tampered).unwrap();
+    
+        let mut reopened = vault.open();
+        assert!(matches!(reopened.load_entries(), Err(StoreError::DecryptFailed)));
+    }
+    
+    #[test]
+    fn rekey_is_due_after_the_threshold_until_the_key_changes() {
+        let vault = TempVault::new();
+        let mut store = PasswordStore::new(&vault.path()).unwrap();
+        store.initialize(PASSWORD, 1, 10, FAST_KDF, 3, Compression::None).unwrap();
+        assert_eq!(store.rekey_due(), None);
+    
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.add_entry(entry("gitlab", "alice", "hunter2")).unwrap();
+        assert_eq!(store.rekey_due(), Some(3));
+        assert_eq!(vault.open().rekey_due(), Some(3));
+    
+        store.change_master_password(PASSWORD, "new passphrase", true).unwrap();
+        assert_eq!(store.rekey_due(), None);
+    }
+    
+    #[test]
+    fn compressed_vault_round_trips() {
+        let vault = TempVault::new();
+        let mut store = PasswordStore::new(&vault.path()).unwrap();
+        store.initialize(PASSWORD, 1, 10, FAST_KDF, DEFAULT_REKEY_AFTER, Compression::Zlib).unwrap();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        let mut reopened = vault.open();
+        assert_eq!(reopened.header.as_ref().unwrap().compression, Compression::Zlib);
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    }
+    
+    #[test]
+    fn near_identical_services_with_one_username_are_duplicates() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "a")).unwrap();
+        store.add_entry(entry("WWW.GitHub ", " Alice", "b")).unwrap();
+        store.add_entry(entry("git-hub", "bob", "c")).unwrap();
+        store.add_entry(entry("gitlab", "alice", "d")).unwrap();
+        assert_eq!(normalize_service("git-hub"), "github");
+    
+        let groups = store.duplicate_groups().unwrap();
+        assert_eq!(groups.len(), 1);
+        let services: Vec<&str> = groups[0].iter().map(|entry| entry.service.as_str()).collect();
+        assert_eq!(services, ["WWW.GitHub ", "github"]);
+    
+        store.delete_entries(&[groups[0][1].id]).unwrap();
+        assert!(store.duplicate_groups().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn one_service_holds_an_entry_per_username() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "alice-password")).unwrap();
+        store.add_entry(entry("github", "bob", "bob-password")).unwrap();
+    
+        assert_eq!(store.get_entry("github", Some("alice")).unwrap().unwrap().password, "alice-password");
+        assert_eq!(store.get_entry("github", Some("bob")).unwrap().unwrap().password, "bob-password");
+        assert_eq!(store.usernames("github").unwrap(), ["alice", "bob"]);
+        assert!(matches!(store.get_entry("github", None), Err(StoreError::MultipleAccounts { .. })));
+        assert!(matches!(store.add_entry_checked(entry("github", "bob", "again")), Err(StoreError::AlreadyExists { .. })));
+    
+        store.delete_entry("github", Some("alice")).unwrap();
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().username, "bob");
+    }
+    
+    #[test]
+    fn recently_accessed_lists_read_entries_newest_first() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        for service in ["github", "gitlab", "bitbucket"] {
+            store.add_entry(entry(service, "alice", "hunter2")).unwrap();
+        }
+        store.mark_accessed("gitlab", None).unwrap();
+        store.mark_accessed("github", None).unwrap();
+    
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        let recent: Vec<String> = reopened.recently_accessed(5).unwrap().into_iter().map(|entry| entry.service.clone()).collect();
+        assert_eq!(recent, ["github", "gitlab"]);
+        assert_eq!(store.recently_accessed(1).unwrap()[0].service, "github");
+    }
+    
+    #[test]
+    fn reading_an_entry_leaves_the_last_change_to_roll_back() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.add_entry(entry("gitlab", "alice", "hunter3")).unwrap();
+        store.delete_entry("gitlab", None).unwrap();
+        let encryptions = store.header.as_ref().unwrap().encryptions;
+        let saved = fs::read(vault.path()).unwrap();
+    
+        store.mark_accessed("github", None).unwrap();
+        assert_eq!(fs::read(vault.path()).unwrap(), saved);
+        assert_eq!(store.header.as_ref().unwrap().encryptions, encryptions);
+    
+        store.rollback_last_save().unwrap();
+        assert!(store.verify_master_password(PASSWORD).unwrap());
+        store.load_entries_cached().unwrap();
+        assert_eq!(store.get_entry("gitlab", None).unwrap().unwrap().password, "hunter3");
+    }
+    
+    #[test]
+    fn journaled_reads_are_kept_by_the_next_save() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.mark_accessed("github", None).unwrap();
+        let accessed = store.get_entry("github", None).unwrap().unwrap().last_accessed;
+        assert!(accessed.is_some());
+    
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().last_accessed, accessed);
+        reopened.add_entry(entry("gitlab", "alice", "hunter3")).unwrap();
+        assert!(!Path::new(&format!("{}.access", vault.path())).exists());
+    
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().last_accessed, accessed);
+    }
+    
+    #[test]
+    fn old_passwords_are_listed_oldest_first() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        let now = Utc::now();
+        for (service, age) in [("fresh", 10), ("old", 100), ("older", 200)] {
+            let mut old = entry(service, "alice", "hunter2");
+            old.updated_at = now - Duration::days(age);
+            store.add_entry(old).unwrap();
+        }
+    
+        let services: Vec<String> = store.entries_older_than(90).unwrap().into_iter().map(|entry| entry.service.clone()).collect();
+        assert_eq!(services, ["older", "old"]);
+    }
+    
+    #[test]
+    fn verify_reports_corrupted_entries() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let report = PasswordStore::new(&vault.path()).unwrap().verify(PASSWORD).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.entries, 1);
+    
+        let mut contents = fs::read(vault.path()).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        fs::write(vault.path(), &contents).unwrap();
+    
+        let mut damaged = PasswordStore::new(&vault.path()).unwrap();
+        let report = damaged.verify(PASSWORD).unwrap();
+        assert!(!report.passed());
+        assert!(damaged.verify("wrong").is_err());
+        assert_eq!(fs::read(vault.path()).unwrap(), contents);
+    }
+    
+    #[test]
+    fn second_store_cannot_lock_the_same_file() {
+        let vault = TempVault::new();
+        vault.init();
+        let mut first = PasswordStore::new(&vault.path()).unwrap();
+        first.acquire_file_lock().unwrap();
+    
+        let mut second = PasswordStore::new(&vault.path()).unwrap();
+        assert!(matches!(second.acquire_file_lock(), Err(StoreError::InUse(_))));
+    
+        drop(first);
+        second.acquire_file_lock().unwrap();
+    }
+    
+    #[test]
+    fn recovery_key_unwraps_the_data_key() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let recovery_key = store.create_recovery_key().unwrap();
+    
+        let mut recovered = PasswordStore::new(&vault.path()).unwrap();
+        assert!(!recovered.unlock_with_recovery_key("0000-0000-0000-0000-0000-0000-0000-0000").unwrap());
+        assert!(recovered.unlock_with_recovery_key(&recovery_key.to_lowercase()).unwrap());
+        recovered.load_entries().unwrap();
+        assert_eq!(recovered.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    }
+    
+    #[test]
+    fn change_master_rewraps_the_data_key_unless_rekeying() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let recovery_key = store.create_recovery_key().unwrap();
+        let data_key = store.master_key.as_ref().unwrap().as_bytes().to_vec();
+    
+        assert!(store.change_master_password(PASSWORD, "new passphrase", false).unwrap().is_none());
+        assert_eq!(store.master_key.as_ref().unwrap().as_bytes(), &data_key[..]);
+        let mut recovered = PasswordStore::new(&vault.path()).unwrap();
+        assert!(recovered.unlock_with_recovery_key(&recovery_key).unwrap());
+    
+        let new_recovery_key = store.change_master_password("new passphrase", "newer passphrase", true).unwrap();
+        assert_ne!(store.master_key.as_ref().unwrap().as_bytes(), &data_key[..]);
+        let mut reopened = PasswordStore::new(&vault.path()).unwrap();
+        assert!(!reopened.unlock_with_recovery_key(&recovery_key).unwrap());
+        assert!(reopened.unlock_with_recovery_key(&new_recovery_key.unwrap()).unwrap());
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+    }
+    
+    #[test]
+    fn add_entries_reports_each_record_and_saves_the_rest() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        let results = store.add_entries(vec![
+            entry("github", "alice", "again"),
+            entry("gitlab", "alice", "hunter3"),
+            entry("", "alice", "hunter4"),
+        ]).unwrap();
+        assert!(results[0].is_err() && results[1].is_ok() && results[2].is_err());
+    
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+        assert_eq!(reopened.get_entry("gitlab", None).unwrap().unwrap().password, "hunter3");
+    }
+    
+    #[test]
+    fn batch_writes_the_file_once() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        let before = store.header.as_ref().unwrap().encryptions;
+    
+        store.begin_batch();
+        for n in 0..10 {
+            store.add_entry(entry(&format!("service{}", n), "alice", "hunter2")).unwrap();
+        }
+        assert_eq!(store.header.as_ref().unwrap().encryptions, before);
+        store.commit_batch().unwrap();
+    
+        assert_eq!(store.header.as_ref().unwrap().encryptions, before + 1);
+        let mut reopened = vault.open();
+        reopened.load_entries().unwrap();
+        assert_eq!(reopened.list_entries().unwrap().len(), 10);
+    }
+    
+    #[test]
+    fn rolled_back_batch_restores_the_entries() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        store.begin_batch();
+        store.delete_entry("github", None).unwrap();
+        store.rollback_batch();
+    
+        assert!(store.get_entry("github", None).unwrap().is_some());
+    }
+    
+    #[test]
+    fn purge_removes_the_database_and_is_idempotent() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.add_entry(entry("gitlab", "alice", "hunter2")).unwrap();
+    
+        let removed = store.purge().unwrap();
+        assert!(removed.contains(&vault.path()));
+        assert!(removed.contains(&store.backup_path(0)));
+        assert!(!Path::new(&vault.path()).exists());
+        assert!(store.is_locked());
+    
+        assert!(store.purge().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn batched_events_wait_for_the_commit() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+    
+        store.begin_batch();
+        store.delete_entry("github", None).unwrap();
+        assert_eq!(store.read_event_log().unwrap().events.len(), 1);
+        store.rollback_batch();
+        assert_eq!(store.read_event_log().unwrap().events.len(), 1);
+    
+        store.begin_batch();
+        store.add_entry(entry("gitlab", "alice", "hunter2")).unwrap();
+        store.commit_batch().unwrap();
+        let actions: Vec<AuditAction> = store.read_event_log().unwrap().events.iter().map(|event| event.action).collect();
+        assert_eq!(actions, [AuditAction::Added, AuditAction::Added]);
+    }
+    
+    #[test]
+    fn services_are_found_by_case_and_alias() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("GitHub", "alice", "hunter2")).unwrap();
+        let mut gitlab = entry("GitLab", "alice", "hunter3");
+        gitlab.aliases = vec!["work-git".to_string()];
+        store.add_entry(gitlab).unwrap();
+        store.add_entry(entry("github", "bob", "hunter4")).unwrap();
+    
+        assert_eq!(store.matching_services("GitHub").unwrap(), ["GitHub"]);
+        assert_eq!(store.matching_services("gitlab").unwrap(), ["GitLab"]);
+        assert_eq!(store.matching_services("WORK-GIT").unwrap(), ["GitLab"]);
+        assert_eq!(store.matching_services("GITHUB").unwrap(), ["GitHub", "github"]);
+        assert!(matches!(store.get_entry("GITHUB", None), Err(StoreError::Ambiguous { .. })));
+        assert_eq!(store.get_entry("work-git", None).unwrap().unwrap().password, "hunter3");
+        assert!(store.get_entry("bitbucket", None).unwrap().is_none());
+    }
+    
+    #[test]
+    fn dry_run_delete_leaves_the_file_alone() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let before = fs::read(vault.path()).unwrap();
+        let modified = fs::metadata(vault.path()).unwrap().modified().unwrap();
+    
+        store.set_dry_run(true);
+        store.delete_entry("github", None).unwrap();
+        assert!(store.get_entry("github", None).unwrap().is_none());
+    
+        assert_eq!(fs::read(vault.path()).unwrap(), before);
+        assert_eq!(fs::metadata(vault.path()).unwrap().modified().unwrap(), modified);
+        assert_eq!(store.read_event_log().unwrap().events.len(), 1);
+    }
+    
+    #[test]
+    fn read_only_store_refuses_changes() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let before = fs::read(vault.path()).unwrap();
+    
+        store.set_read_only(true);
+        assert!(matches!(store.add_entry(entry("gitlab", "alice", "x")), Err(StoreError::ReadOnly(_))));
+        assert!(matches!(store.update_password("github", None, "x"), Err(StoreError::ReadOnly(_))));
+        assert!(matches!(store.delete_entry("github", None), Err(StoreError::ReadOnly(_))));
+        store.mark_accessed("github", None).unwrap();
+    
+        assert_eq!(fs::read(vault.path()).unwrap(), before);
+        assert_eq!(store.read_event_log().unwrap().events.len(), 1);
+    }
+    
+    #[test]
+    fn favorites_come_first_and_can_be_filtered() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        for service in ["a", "b", "c"] {
+            store.add_entry(entry(service, "alice", "hunter2")).unwrap();
+        }
+        let updated_at = store.get_entry("c", None).unwrap().unwrap().updated_at;
+        store.set_favorite("c", None, true).unwrap();
+        assert_eq!(store.get_entry("c", None).unwrap().unwrap().updated_at, updated_at);
+    
+        let services = |favorites_only| -> Vec<String> {
+            store.list_entries_sorted(SortKey::Service, None, 0, favorites_only, None).unwrap()
+                .into_iter().map(|entry| entry.service.clone()).collect()
+        };
+        assert_eq!(services(false), ["c", "a", "b"]);
+        assert_eq!(services(true), ["c"]);
+    
+        store.set_favorite("c", None, false).unwrap();
+        assert!(store.list_entries_sorted(SortKey::Service, None, 0, true, None).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn reauth_is_due_once_the_interval_has_passed() {
+        let start = Instant::now();
+        let interval = Some(std::time::Duration::from_secs(60));
+        let later = |secs| start + std::time::Duration::from_secs(secs);
+    
+        assert!(!reauth_due(Some(start), None, later(3600)));
+        assert!(reauth_due(None, interval, start));
+        assert!(!reauth_due(Some(start), interval, later(59)));
+        assert!(reauth_due(Some(start), interval, later(60)));
+        assert!(!reauth_due(Some(later(10)), interval, start));
+    }
+    
+    #[test]
+    fn verifying_the_password_decrypts_no_entries() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        for n in 0..50 {
+            store.add_entry(entry(&format!("service{}", n), "alice", "hunter2")).unwrap();
+        }
+    
+        let mut reader = PasswordStore::new(&vault.path()).unwrap();
+        let start = decryptions();
+        assert!(reader.verify_master_password(PASSWORD).unwrap());
+        assert_eq!(decryptions() - start, 0);
+        assert!(matches!(reader.get_entry("service0", None), Err(StoreError::NotLoaded)));
+    
+        reader.load_entries_cached().unwrap();
+        assert_eq!(decryptions() - start, 1);
+        assert!(reader.get_entry("service0", None).unwrap().is_some());
+    }
+    
+    #[test]
+    fn stats_count_a_known_vault() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        let mut old = entry("old", "alice", "12345678");
+        old.updated_at = Utc::now() - Duration::days(400);
+        store.add_entry(old).unwrap();
+        store.add_entry(entry("github", "alice", "1234")).unwrap();
+        store.add_entry(entry("gitlab", "bob", "1234")).unwrap();
+    
+        let stats = store.stats(365).unwrap();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.unique_usernames, 2);
+        assert_eq!(stats.reused_passwords, 1);
+        assert_eq!(stats.older_than_days, 1);
+        assert!((stats.average_length - 16.0 / 3.0).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn truncated_files_are_reported_as_such() {
+        let vault = TempVault::new();
+        for contents in [Vec::new(), vec![0x10, 0x00], vec![0xff, 0xff, 0xff, 0x7f, b'{']] {
+            fs::write(vault.path(), &contents).unwrap();
+            let error = PasswordStore::new(&vault.path()).err().unwrap();
+            assert!(matches!(error, StoreError::Truncated { size } if size == contents.len() as u64), "{:?}", contents);
+            assert!(error.is_damage());
+        }
+    }
+    
+    #[test]
+    fn unreadable_header_is_corrupt() {
+        let vault = TempVault::new();
+        let mut contents = 5u32.to_le_bytes().to_vec();
+        contents.extend_from_slice(b"{oops");
+        fs::write(vault.path(), contents).unwrap();
+    
+        assert!(matches!(PasswordStore::new(&vault.path()), Err(StoreError::Corrupt(_))));
+    }
+    
+    #[test]
+    fn damaged_database_can_be_rolled_back() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        store.add_entry(entry("gitlab", "alice", "hunter3")).unwrap();
+        fs::write(vault.path(), [0x10, 0x00]).unwrap();
+    
+        PasswordStore::for_repair(&vault.path()).rollback_last_save().unwrap();
+        let mut restored = vault.open();
+        restored.load_entries().unwrap();
+        assert!(restored.get_entry("github", None).unwrap().is_some());
+        assert!(restored.get_entry("gitlab", None).unwrap().is_none());
+    }
+    
+    #[test]
+    fn misfiled_entries_are_moved_or_refused_in_strict_mode() {
+        let mut warnings = Vec::new();
+        let misfiled = || HashMap::from([
+            ("wrong".to_string(), vec![entry("github", "alice", "hunter2")]),
+            ("gitlab".to_string(), vec![entry("", "bob", "hunter3")]),
+        ]);
+    
+        let reconciled = reconcile_keys(misfiled(), false, &mut warnings).unwrap();
+        assert_eq!(reconciled["github"][0].username, "alice");
+        assert_eq!(reconciled["gitlab"][0].service, "gitlab");
+        assert!(!reconciled.contains_key("wrong"));
+        assert_eq!(warnings.len(), 2);
+    
+        assert!(matches!(reconcile_keys(misfiled(), true, &mut Vec::new()), Err(StoreError::ServiceMismatch { .. })));
+    }
+    
+    #[test]
+    fn misfiled_entry_does_not_replace_a_correctly_filed_one() {
+        let mut warnings = Vec::new();
+        let entries = HashMap::from([
+            ("github".to_string(), vec![entry("github", "alice", "right")]),
+            ("wrong".to_string(), vec![entry("github", "alice", "misfiled")]),
+        ]);
+    
+        let reconciled = reconcile_keys(entries, false, &mut warnings).unwrap();
+        assert_eq!(reconciled["github"][0].password, "right");
+        assert_eq!(reconciled["wrong"][0].password, "misfiled");
+        assert_eq!(warnings.len(), 1);
+    }
+    
+    #[test]
+    fn remembered_policy_is_used_when_rotating() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("bank", "alice", "hunter2")).unwrap();
+        let policy = GeneratorOptions { length: 8, include_symbols: false, min_symbols: 0, exclude_chars: "0123456789".to_string(), min_digits: 0, ..GeneratorOptions::default() };
+        store.set_gen_policy("bank", None, Some(policy)).unwrap();
+    
+        let rotated = store.generate_and_rotate_multiple(&["bank".to_string()], &GeneratorOptions::default()).unwrap();
+        let password = &rotated["bank"];
+        assert_eq!(password.chars().count(), 8);
+        assert!(password.chars().all(|c| c.is_ascii_alphabetic()));
+        assert_eq!(&store.get_entry("bank", None).unwrap().unwrap().password, password);
+    }
+    
+    #[test]
+    fn list_can_be_filtered_by_tag_ignoring_case() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        for service in ["a", "b", "c"] {
+            store.add_entry(entry(service, "alice", "hunter2")).unwrap();
+        }
+        store.set_tags("a", None, vec!["Work".to_string(), "banking".to_string()]).unwrap();
+        store.set_tags("c", None, vec!["work".to_string()]).unwrap();
+    
+        let tagged: Vec<String> = store.list_entries_sorted(SortKey::Service, None, 0, false, Some("WORK")).unwrap()
+            .into_iter().map(|entry| entry.service.clone()).collect();
+        assert_eq!(tagged, ["a", "c"]);
+        assert!(store.set_tags("b", None, vec![" ".to_string()]).is_err());
+    }
+    
+    #[test]
+    fn editing_notes_keeps_the_password_age() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let updated_at = store.get_entry("github", None).unwrap().unwrap().updated_at;
+    
+        store.set_notes("github", None, Some("line one\nline two".to_string())).unwrap();
+        let edited = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(edited.notes.as_deref(), Some("line one\nline two"));
+        assert_eq!(edited.updated_at, updated_at);
+        assert_eq!(edited.password, "hunter2");
+    
+        store.set_notes("github", None, None).unwrap();
+        assert!(store.get_entry("github", None).unwrap().unwrap().notes.is_none());
+    }
+    
+    #[test]
+    fn setting_aliases_keeps_the_password_age() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let updated_at = store.get_entry("github", None).unwrap().unwrap().updated_at;
+    
+        store.set_aliases("github", None, vec!["gh".to_string()]).unwrap();
+        let edited = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(edited.aliases, ["gh"]);
+        assert_eq!(edited.updated_at, updated_at);
+    }
+    
+    #[test]
+    fn setting_custom_fields_keeps_the_password_age() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let updated_at = store.get_entry("github", None).unwrap().unwrap().updated_at;
+    
+        let field = CustomField { name: "pin".to_string(), value: "1234".to_string(), secret: true };
+        store.set_custom_fields("github", None, vec![field]).unwrap();
+        let edited = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(edited.custom_fields.len(), 1);
+        assert_eq!(edited.updated_at, updated_at);
+    }
+    
+    #[test]
+    fn setting_the_totp_secret_keeps_the_password_age() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let updated_at = store.get_entry("github", None).unwrap().unwrap().updated_at;
+    
+        store.set_totp_secret("github", None, Some("JBSWY3DPEHPK3PXP".to_string())).unwrap();
+        let edited = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(edited.totp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(edited.updated_at, updated_at);
+    }
+    
+    #[test]
+    fn setting_the_url_keeps_the_password_age() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(entry("github", "alice", "hunter2")).unwrap();
+        let updated_at = store.get_entry("github", None).unwrap().unwrap().updated_at;
+    
+        store.set_url("github", None, Some("https://github.com".to_string())).unwrap();
+        let edited = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(edited.url.as_deref(), Some("https://github.com"));
+        assert_eq!(edited.updated_at, updated_at);
+    }
+    
+    fn hash_output(master_hash: &str) -> Vec<u8> {
+        PasswordHash::new(master_hash).unwrap().hash.unwrap().as_bytes().to_vec()
+    }
+    
+    fn hash_salt(master_hash: &str) -> Vec<u8> {
+        let hash = PasswordHash::new(master_hash).unwrap();
+        let mut buffer = [0u8; 64];
+        hash.salt.unwrap().decode_b64(&mut buffer).unwrap().to_vec()
+    }
+    
+    #[test]
+    fn password_hash_is_not_the_wrapping_key() {
+        let vault = TempVault::new();
+        let store = vault.init();
+        let header = store.header.clone().unwrap();
+        
+        let wrapping_key = derive_key(PASSWORD, &header.salt, &FAST_KDF).unwrap();
+        assert_ne!(hash_output(&header.master_hash), wrapping_key.as_bytes());
+        assert_ne!(hash_salt(&header.master_hash), header.salt);
+        assert!(!header.key_exposed());
+    }
+    
+    // Rewrites a fresh vault the way version 6 stored it: the data key
+    // wrapped under the password hash's own salt.
+    fn downgrade_to_v6(store: &mut PasswordStore) {
+        let header = store.header.as_mut().unwrap();
+        header.salt = hash_salt(&header.master_hash);
+        let kek = derive_key(PASSWORD, &header.salt, &FAST_KDF).unwrap();
+        header.wrapped_key = Some(wrap_key(store.master_key.as_ref().unwrap(), &kek).unwrap());
+        header.version = 6;
+        let mut contents = Vec::new();
+        store.save_to_writer(&mut contents).unwrap();
+        store.replace_file(&contents).unwrap();
+    }
+    
+    #[test]
+    fn migration_moves_an_exposed_vault_to_a_new_data_key() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(PasswordEntry::new("github".to_string(), "me".to_string(), "hunter2".to_string())).unwrap();
+        downgrade_to_v6(&mut store);
+        
+        let mut old = vault.open();
+        let header = old.header.clone().unwrap();
+        assert_eq!(old.loaded_from_version, Some(6));
+        assert!(header.key_exposed());
+        assert_eq!(hash_output(&header.master_hash), derive_key(PASSWORD, &header.salt, &FAST_KDF).unwrap().as_bytes());
+        let old_key = old.master_key.as_ref().unwrap().as_bytes().to_vec();
+        
+        let migration = old.migrate(PASSWORD).unwrap().unwrap();
+        assert_eq!(migration.from_version, 6);
+        assert!(migration.recovery_key.is_none());
+        
+        let mut upgraded = vault.open();
+        assert_eq!(upgraded.loaded_from_version, Some(CURRENT_VERSION));
+        assert!(!upgraded.header.as_ref().unwrap().key_exposed());
+        assert_ne!(upgraded.master_key.as_ref().unwrap().as_bytes(), old_key.as_slice());
+        upgraded.load_entries().unwrap();
+        assert_eq!(upgraded.get_entry("github", None).unwrap().unwrap().password, "hunter2");
+        assert!(upgraded.migrate(PASSWORD).unwrap().is_none());
+    }
+    
+    #[test]
+    fn changing_the_password_of_an_exposed_vault_rekeys_it() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        downgrade_to_v6(&mut store);
+        
+        let mut old = vault.open();
+        let old_key = old.master_key.as_ref().unwrap().as_bytes().to_vec();
+        old.change_master_password(PASSWORD, "a different passphrase", false).unwrap();
+        
+        let mut changed = PasswordStore::new(&vault.path()).unwrap();
+        assert!(changed.verify_master_password("a different passphrase").unwrap());
+        assert!(!changed.header.as_ref().unwrap().key_exposed());
+        assert_ne!(changed.master_key.as_ref().unwrap().as_bytes(), old_key.as_slice());
+    }
+    
+    #[test]
+    fn dry_run_rotation_changes_nothing() {
+        let vault = TempVault::new();
+        let mut store = vault.init();
+        store.add_entry(PasswordEntry::new("github".to_string(), "me".to_string(), "hunter2".to_string())).unwrap();
+    
+        store.set_dry_run(true);
+        assert!(store.generate_and_rotate_multiple(&["github".to_string()], &GeneratorOptions::default()).unwrap().is_empty());
+        assert!(store.generate_and_rotate_multiple(&["gitlab".to_string()], &GeneratorOptions::default()).is_err());
+    
+        let entry = store.get_entry("github", None).unwrap().unwrap();
+        assert_eq!(entry.password, "hunter2");
+        assert!(entry.history.is_empty());
+    }
+}
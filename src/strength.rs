@@ -0,0 +1,223 @@
+// src/strength.rs
+
+use colored::*;
+
+use crate::password_generator::PasswordGenerator;
+
+pub const MIN_LENGTH: usize = 12;
+// Estimated bits a master password needs unless the config says otherwise.
+pub const DEFAULT_MASTER_MIN_BITS: f64 = 50.0;
+const SYMBOL_POOL: f64 = 33.0;
+const OTHER_POOL: f64 = 100.0;
+// Share of a character's entropy kept when it repeats or continues a sequence.
+const PATTERN_CHAR_WEIGHT: f64 = 0.25;
+
+const COMMON_PATTERNS: &[&str] = &[
+    "password", "passwort", "letmein", "welcome", "admin", "login", "qwerty", "azerty",
+    "iloveyou", "monkey", "dragon", "master", "sunshine", "football", "abc123", "123456",
+];
+
+// Rejected outright as master passwords, whatever their estimated entropy.
+// Compared ignoring case.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "password1", "password123", "passw0rd", "p@ssw0rd", "12345678", "123456789",
+    "1234567890", "11111111", "00000000", "87654321", "qwertyuiop", "qwerty123", "1q2w3e4r",
+    "1qaz2wsx", "zaq12wsx", "asdfghjkl", "iloveyou", "princess", "sunshine", "football",
+    "baseball", "superman", "starwars", "whatever", "trustno1", "welcome1", "letmein1",
+    "changeme", "computer", "michelle", "jennifer", "corvette", "mercedes", "master123",
+    "admin123", "abcd1234", "aa123456", "1234qwer", "qwertyui",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrengthCategory {
+    Weak,
+    Fair,
+    Strong,
+    VeryStrong,
+}
+
+impl StrengthCategory {
+    fn from_bits(bits: f64) -> Self {
+        match bits {
+            b if b < 40.0 => StrengthCategory::Weak,
+            b if b < 60.0 => StrengthCategory::Fair,
+            b if b < 80.0 => StrengthCategory::Strong,
+            _ => StrengthCategory::VeryStrong,
+        }
+    }
+
+    pub fn colored_label(self) -> ColoredString {
+        match self {
+            StrengthCategory::Weak => "Weak".red().bold(),
+            StrengthCategory::Fair => "Fair".yellow().bold(),
+            StrengthCategory::Strong => "Strong".green().bold(),
+            StrengthCategory::VeryStrong => "Very strong".green().bold(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StrengthReport {
+    pub entropy_bits: f64,
+    pub category: StrengthCategory,
+    pub weaknesses: Vec<String>,
+}
+
+pub fn estimate_strength(password: &str) -> StrengthReport {
+    let entropy_bits = estimate_entropy(password);
+    let mut weaknesses = Vec::new();
+
+    if password.chars().count() < MIN_LENGTH {
+        weaknesses.push(format!("Shorter than {} characters", MIN_LENGTH));
+    }
+    if !password.chars().any(|c| c.is_ascii_lowercase()) || !password.chars().any(|c| c.is_ascii_uppercase()) {
+        weaknesses.push("No mix of upper and lower case letters".to_string());
+    }
+    if !password.chars().any(|c| c.is_ascii_digit()) {
+        weaknesses.push("No digits".to_string());
+    }
+    if !password.chars().any(|c| c.is_ascii_punctuation()) {
+        weaknesses.push("No symbols".to_string());
+    }
+    if has_pattern_run(password) {
+        weaknesses.push("Repeated or sequential characters".to_string());
+    }
+    if PasswordGenerator::check_keyboard_walk(password) {
+        weaknesses.push("Contains a keyboard walk".to_string());
+    }
+    if contains_common_pattern(password) {
+        weaknesses.push("Contains a common password".to_string());
+    }
+
+    let category = if is_compromised_by_pattern(password) {
+        StrengthCategory::Weak
+    } else {
+        StrengthCategory::from_bits(entropy_bits)
+    };
+
+    StrengthReport { entropy_bits, category, weaknesses }
+}
+
+pub fn estimate_entropy(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    if chars.is_empty() {
+        return 0.0;
+    }
+
+    let mut pool = 0.0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += SYMBOL_POOL;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += OTHER_POOL;
+    }
+
+    let effective_length = 1.0 + chars
+        .windows(2)
+        .map(|pair| if is_pattern_pair(pair[0], pair[1]) { PATTERN_CHAR_WEIGHT } else { 1.0 })
+        .sum::<f64>();
+
+    let mut bits = effective_length * f64::log2(pool);
+    if PasswordGenerator::check_keyboard_walk(password) {
+        bits /= 2.0;
+    }
+    bits
+}
+
+// Why `password` is not good enough to protect the vault; empty if it is.
+pub fn master_password_problems(password: &str, min_bits: f64) -> Vec<String> {
+    let mut problems = Vec::new();
+    let lower = password.to_lowercase();
+    if COMMON_PASSWORDS.contains(&lower.as_str()) {
+        problems.push("It is one of the most common passwords".to_string());
+    } else if contains_common_pattern(password) {
+        problems.push("It contains a common password".to_string());
+    }
+    if PasswordGenerator::check_keyboard_walk(password) {
+        problems.push("It contains a keyboard walk".to_string());
+    }
+    let bits = estimate_entropy(password);
+    if bits < min_bits {
+        problems.push(format!("About {:.0} bits of entropy; at least {:.0} are required", bits, min_bits));
+    }
+    problems
+}
+
+pub fn is_compromised_by_pattern(password: &str) -> bool {
+    contains_common_pattern(password) || PasswordGenerator::check_keyboard_walk(password)
+}
+
+fn contains_common_pattern(password: &str) -> bool {
+    let lower = password.to_lowercase();
+    COMMON_PATTERNS.iter().any(|pattern| lower.contains(pattern))
+}
+
+// Same character twice, or neighbours in code point order ("ab", "21").
+fn is_pattern_pair(a: char, b: char) -> bool {
+    (a as i64 - b as i64).abs() <= 1
+}
+
+fn has_pattern_run(password: &str) -> bool {
+    let chars: Vec<char> = password.chars().collect();
+    chars.windows(3).any(|w| {
+        is_pattern_pair(w[0], w[1]) && w[1] as i64 - w[0] as i64 == w[2] as i64 - w[1] as i64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_walks_halve_the_estimate() {
+        assert!(is_compromised_by_pattern("zxcvbnm"));
+        assert_eq!(estimate_strength("zxcvbnm").category, StrengthCategory::Weak);
+    }
+
+    #[test]
+    fn repeated_characters_are_not_a_compromising_pattern() {
+        assert!(!is_compromised_by_pattern("aaaa"));
+        let report = estimate_strength("aaaa");
+        assert!(!report.weaknesses.iter().any(|weakness| weakness.contains("keyboard walk")));
+        assert!(report.weaknesses.iter().any(|weakness| weakness.contains("Repeated")));
+    }
+
+    #[test]
+    fn entropy_grows_with_length_and_classes() {
+        assert_eq!(estimate_entropy(""), 0.0);
+        assert!(estimate_entropy("Vq7#tLm2") < estimate_entropy("Vq7#tLm2!xRb9&Kp"));
+        assert!(estimate_entropy("vqetlmzx") < estimate_entropy("Vq7#tLmz"));
+    }
+
+    #[test]
+    fn common_passwords_are_weak_whatever_their_length() {
+        let report = estimate_strength("MyPassword-2024-Extra-Long");
+        assert_eq!(report.category, StrengthCategory::Weak);
+        assert!(report.weaknesses.iter().any(|weakness| weakness.contains("common password")));
+        assert_eq!(estimate_strength("Vq7#tLm2!xRb9&Kp").category, StrengthCategory::VeryStrong);
+    }
+
+    #[test]
+    fn master_password_threshold_is_inclusive() {
+        let password = "Vq7#tLm2!xRb9&Kp";
+        let bits = estimate_entropy(password);
+
+        assert!(master_password_problems(password, bits).is_empty());
+        assert_eq!(master_password_problems(password, bits + 1.0).len(), 1);
+    }
+
+    #[test]
+    fn common_passwords_are_rejected_whatever_the_threshold() {
+        assert!(!master_password_problems("Password123", 0.0).is_empty());
+        assert!(!master_password_problems("myqwertyfamily", 0.0).is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+// Uses the vault only through the library's public API, as another front
+// end would.
+
+use secure_password_manager::crypto::{KdfParams, DEFAULT_REKEY_AFTER};
+use secure_password_manager::error::StoreError;
+use secure_password_manager::password_entry::PasswordEntry;
+use secure_password_manager::password_generator::{GeneratorOptions, PasswordGenerator};
+use secure_password_manager::storage::{Compression, PasswordStore};
+
+const PASSWORD: &str = "correct horse battery staple";
+
+#[test]
+fn a_vault_round_trips_through_the_public_api() {
+    let dir = std::env::temp_dir().join(format!("passrusted-lib-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("passwords.db").to_string_lossy().into_owned();
+    let kdf = KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 };
+
+    let password = PasswordGenerator::new().generate_with_options(&GeneratorOptions::default()).unwrap();
+    let mut store = PasswordStore::new(&path).unwrap();
+    store.initialize(PASSWORD, 1, 10, kdf, DEFAULT_REKEY_AFTER, Compression::None).unwrap();
+    store.add_entry(PasswordEntry::new("github".to_string(), "alice".to_string(), password.clone())).unwrap();
+
+    let mut reopened = PasswordStore::new(&path).unwrap();
+    assert!(matches!(reopened.get_entry("github", None), Err(StoreError::Locked)));
+    assert!(!reopened.verify_master_password("wrong").unwrap());
+    assert!(reopened.verify_master_password(PASSWORD).unwrap());
+    reopened.load_entries_cached().unwrap();
+    assert_eq!(reopened.get_entry("github", None).unwrap().unwrap().password, password);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}